@@ -1,2 +1,9 @@
 pub mod dag;
+pub mod delayline;
 pub mod effects;
+pub mod meters;
+pub mod parameter;
+pub mod port;
+pub mod registry;
+pub mod streamstate;
+pub mod switches;
@@ -0,0 +1,85 @@
+/// A ring buffer of the last few frames that can be read back from between samples
+#[derive(Clone)]
+pub struct DelayLine {
+    buffer: Vec<f32>,
+    channels: usize,
+    length: usize,
+    // the frame the next push goes into
+    write: usize,
+}
+
+impl DelayLine {
+    /// Remembers `length` frames (so delays up to a couple of frames short of that can be read)
+    pub fn new(length: usize, channels: usize) -> Self {
+        let length = length.max(4);
+        Self {
+            buffer: vec![0.0; length * channels],
+            channels,
+            length,
+            write: 0,
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn push(&mut self, frame: &[f32]) {
+        let start = self.write * self.channels;
+        self.buffer[start..start + self.channels].copy_from_slice(&frame[..self.channels]);
+        self.write = (self.write + 1) % self.length;
+    }
+
+    /// The sample `frames_ago` frames back, where 1 is the last one pushed
+    fn tap(&self, channel: usize, frames_ago: usize) -> f32 {
+        let frame = (self.write + self.length - frames_ago) % self.length;
+        self.buffer[frame * self.channels + channel]
+    }
+
+    /// Read `delay` frames back (1 being the last frame pushed) using cubic hermite interpolation between frames.
+    /// The delay is kept within what the line can remember.
+    pub fn read(&self, channel: usize, delay: f32) -> f32 {
+        let delay = delay.clamp(1.0, (self.length - 2) as f32);
+        let i = delay as usize;
+        let t = delay - i as f32;
+
+        let y0 = self.tap(channel, (i - 1).max(1));
+        let y1 = self.tap(channel, i);
+        let y2 = self.tap(channel, i + 1);
+        let y3 = self.tap(channel, i + 2);
+
+        let c1 = 0.5 * (y2 - y0);
+        let c2 = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+        let c3 = 0.5 * (y3 - y0) + 1.5 * (y1 - y2);
+        ((c3 * t + c2) * t + c1) * t + y1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_whole_frames_exact() {
+        let mut line = DelayLine::new(16, 2);
+        for i in 0..20 {
+            line.push(&[i as f32, -(i as f32)]);
+        }
+
+        assert_eq!(line.read(0, 1.0), 19.0);
+        assert_eq!(line.read(0, 5.0), 15.0);
+        assert_eq!(line.read(1, 5.0), -15.0);
+    }
+
+    #[test]
+    fn test_between_frames() {
+        let mut line = DelayLine::new(16, 1);
+        for i in 0..16 {
+            line.push(&[i as f32]);
+        }
+
+        // a straight line is followed exactly by the cubic
+        assert!((line.read(0, 4.25) - 11.75).abs() < 1e-5);
+        assert!((line.read(0, 7.5) - 8.5).abs() < 1e-5);
+    }
+}
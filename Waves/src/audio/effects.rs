@@ -1,24 +1,95 @@
-use eframe::egui::Ui;
+use eframe::egui::{Checkbox, ComboBox, DragValue, Ui};
 use std::any::Any;
 use std::fmt::Debug;
 use std::sync::Arc;
 
+use crate::audio::meters;
+use crate::audio::parameter::Parameter;
+use crate::audio::port::PortType;
+use crate::audio::switches::{SwitchState, Switches};
 use crate::common::Channel;
+use crate::common::SAMPLE_RATE;
 use crate::common::mipmapchannel::SamplePlotData;
-use crate::common::track::Track;
+use crate::common::track::{FadeCurve, Track};
 use crate::ui::eqwidget::EQWidget;
 use crate::ui::nodegraph::GraphStyle;
+use crate::ui::trimwidget::TrimWidget;
 use crate::ui::waveformwidget::WaveformWidget;
 
 pub mod add;
+pub mod channels;
+pub mod chorus;
+pub mod distortion;
+pub mod envelope;
+pub mod events;
+pub mod feedback;
+pub mod filter;
+pub mod flanger;
 pub mod gain;
+pub mod group;
+pub mod lane;
+pub mod lfo;
+pub mod oscillator;
 pub mod output;
+pub mod pan;
+pub mod phaser;
 pub mod sinewave;
+pub mod splitter;
+pub mod tap;
+#[cfg(test)]
+pub mod testing;
+pub mod timestretch;
+pub mod width;
 pub mod zero;
 
 #[derive(Debug)]
 pub enum EffectError {
     OutOfBounds(usize),
+    UnknownSetting(String),
+    InvalidSetting { name: String, value: String },
+}
+
+impl std::fmt::Display for EffectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EffectError::OutOfBounds(index) => write!(f, "no input at index {index}"),
+            EffectError::UnknownSetting(name) => write!(f, "no setting called {name}"),
+            EffectError::InvalidSetting { name, value } => {
+                write!(f, "{value} is not a valid {name}")
+            }
+        }
+    }
+}
+
+/// For effects to read back a setting saved as the Debug name of one of its options
+pub fn parse_setting<T: Debug + Copy>(
+    options: &[T],
+    name: &str,
+    value: &str,
+) -> Result<T, EffectError> {
+    options
+        .iter()
+        .copied()
+        .find(|option| format!("{option:?}") == value)
+        .ok_or_else(|| EffectError::InvalidSetting {
+            name: name.to_string(),
+            value: value.to_string(),
+        })
+}
+
+/// What an input is connected to to take one of the effect's outputs
+pub fn output_of(effect: &Arc<dyn Effect>, index: usize) -> Arc<dyn Effect> {
+    effect
+        .output_at_index(index)
+        .unwrap_or_else(|| effect.clone())
+}
+
+/// The effect an input is connected to, and which of its outputs it takes
+pub fn output_source(input: Arc<dyn Effect>) -> (Arc<dyn Effect>, usize) {
+    match input.tap_source() {
+        Some(source) => source,
+        None => (input, 0),
+    }
 }
 
 pub trait Effect: Send + Sync + Any {
@@ -31,23 +102,144 @@ pub trait Effect: Send + Sync + Any {
 
     fn get_waveform_plot_data(&self, sample_plot_data: &mut SamplePlotData, channel: &Channel);
 
+    /// The numeric settings of the effect, whose modulation inputs come after the audio inputs in this order
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![]
+    }
+
+    fn input_name(&self, index: usize) -> String {
+        let parameters = self.parameters();
+        let audio_inputs = self.input_count() - parameters.len();
+
+        match index.checked_sub(audio_inputs) {
+            Some(i) if i < parameters.len() => format!("{} mod", parameters[i].name()),
+            _ => "input".to_string(),
+        }
+    }
+
+    /// What the input takes, which is stereo audio unless it modulates a parameter
+    fn input_type(&self, index: usize) -> PortType {
+        let audio_inputs = self.input_count() - self.parameters().len();
+        match index < audio_inputs {
+            true => PortType::STEREO,
+            false => PortType::Control,
+        }
+    }
+
+    fn output_type(&self, _index: usize) -> PortType {
+        PortType::STEREO
+    }
+
+    fn output_name(&self, _index: usize) -> String {
+        "output".to_string()
+    }
+
+    /// For effects with more than one output, the tap an input is connected to to take one of them.
+    /// None means the effect itself, which is all there is with just the one
+    fn output_at_index(&self, _index: usize) -> Option<Arc<dyn Effect>> {
+        None
+    }
+
+    /// For taps, the effect this is one of the outputs of, and which
+    fn tap_source(&self) -> Option<(Arc<dyn Effect>, usize)> {
+        None
+    }
+
     fn data_ui(&self, _ui: &mut Ui, _style: &GraphStyle) {
         ()
     }
+
+    /// The stable id of this kind of effect in the registry, if it can be made from there
+    fn effect_id(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// The settings that aren't parameters (eg which waveform an oscillator makes) as (name, value) to be saved
+    fn settings(&self) -> Vec<(&'static str, String)> {
+        vec![]
+    }
+
+    /// Put back a setting as saved by settings
+    fn set_setting(&self, name: &str, _value: &str) -> Result<(), EffectError> {
+        Err(EffectError::UnknownSetting(name.to_string()))
+    }
+
+    /// Where the node's switches are kept, for the effects that can be switched in the graph
+    fn switch_state(&self) -> Option<&SwitchState> {
+        None
+    }
+
+    /// How the node for this effect is switched in the graph
+    fn switches(&self) -> Switches {
+        self.switch_state()
+            .map(SwitchState::get)
+            .unwrap_or_default()
+    }
+
+    /// The first input, which is what gets passed through when bypassed. Sources have nothing to pass through
+    fn bypass_input(&self) -> Option<Arc<dyn Effect>> {
+        match self.input_count() > self.parameters().len() {
+            true => self.get_input_at_index(0).ok(),
+            false => None,
+        }
+    }
+
+    /// Apply, unless the node has been bypassed or muted. Effects take from their inputs through this,
+    /// which is also where they are metered while playing
+    fn process(&self, output: &mut [f32], start_sample: usize, channels: usize) {
+        let switches = self.switches();
+        match self.bypass_input() {
+            _ if switches.mute => output.fill(0.0),
+            Some(input) if switches.bypass => input.process(output, start_sample, channels),
+            None if switches.bypass => output.fill(0.0),
+            _ => meters::measure(
+                (self as *const Self).cast(),
+                output,
+                start_sample,
+                channels,
+                |output| self.apply(output, start_sample, channels),
+            ),
+        }
+    }
+
+    /// get_waveform_plot_data, unless the node has been bypassed or muted, for the same reason as process
+    fn plot_data(&self, sample_plot_data: &mut SamplePlotData, channel: &Channel) {
+        let switches = self.switches();
+        match self.bypass_input() {
+            // the sample_plot_data is defaulted to zero, as for the Zero effect
+            _ if switches.mute => (),
+            Some(input) if switches.bypass => input.plot_data(sample_plot_data, channel),
+            None if switches.bypass => (),
+            _ => self.get_waveform_plot_data(sample_plot_data, channel),
+        }
+    }
 }
 
 impl Effect for Track {
+    fn switch_state(&self) -> Option<&SwitchState> {
+        Some(&self.switches)
+    }
+
     /// We want this to feedback the useful output slice of data and nothing else - literally just read (and also if it is outside range then 0)
     fn apply(&self, output: &mut [f32], sample_clock: usize, channels: usize) {
+        let trim = self.trim();
+        let (data_left, data_right) = self.data();
+        let (data_left, data_right) = (data_left.get_full_data(), data_right.get_full_data());
+        // the length of the data read here, as an edit could swap it for a different length at any time
+        let length = data_left.len().min(data_right.len());
+        let (start, end) = trim.region(length);
+
         // frame is the instance in time
         for (i, frame) in output.chunks_mut(channels).enumerate() {
-            let (left, right) = if i + sample_clock >= self.length() as usize {
-                (0.0, 0.0)
-            } else {
-                (
-                    self.sample_data().0[sample_clock + i],
-                    self.sample_data().1[sample_clock + i],
-                )
+            let (left, right) = match trim.position(sample_clock + i, length) {
+                None => (0.0, 0.0),
+                Some(position) => {
+                    let gain = trim.fade_gain(position, end - start);
+                    (
+                        data_left[start + position] * gain,
+                        data_right[start + position] * gain,
+                    )
+                }
             };
 
             // for stereo
@@ -84,18 +276,86 @@ impl Effect for Track {
         let scope = tracing::trace_span!("track.get_plot_data");
         let _span = scope.enter();
 
-        //println!("{sample_plot_data:?}");
-
-        match channel {
-            Channel::Left => self
-                .file_data_left()
-                .get_presampled_data_from_step_and_start(sample_plot_data),
-            Channel::Right => self
-                .file_data_right()
-                .get_presampled_data_from_step_and_start(sample_plot_data),
+        let (data_left, data_right) = self.data();
+        let mipmap = match channel {
+            Channel::Left => data_left,
+            Channel::Right => data_right,
         };
 
-        //println!("{:?}", sample_plot_data);
+        let trim = self.trim();
+        let length = self.length() as usize;
+        let (start, end) = trim.region(length);
+        let step = sample_plot_data.step;
+        let data_width = sample_plot_data.data[0].len();
+
+        for v in &mut sample_plot_data.data {
+            v.fill(0.0);
+        }
+
+        // Go through in runs that are contiguous in the file, which only break at the end of the trimmed part
+        let mut i = 0;
+        while i < data_width {
+            let sample = sample_plot_data.start_sample + i * step;
+            let Some(position) = trim.position(sample, length) else {
+                i += 1;
+                continue;
+            };
+
+            let run = (end - start - position).div_ceil(step).min(data_width - i);
+            let mut part = SamplePlotData::new(step, start + position, run);
+            mipmap.get_presampled_data_from_step_and_start(&mut part);
+            sample_plot_data.is_min_max = part.is_min_max;
+
+            // the fades never go negative so the min/max lines stay the right way round
+            for j in 0..run {
+                let gain = trim.fade_gain(position + j * step, end - start);
+                for (v, p) in sample_plot_data.data.iter_mut().zip(&part.data) {
+                    v[i + j] = p[j] * gain;
+                }
+            }
+
+            i += run;
+        }
+    }
+
+    fn data_ui(&self, ui: &mut Ui, style: &GraphStyle) {
+        ui.add(TrimWidget::new(self, (style.plot_width, style.plot_height)));
+
+        let mut trim = self.trim();
+
+        // the offset is kept in samples, only shown in seconds, so it isn't rounded just by being drawn
+        let sample_rate = SAMPLE_RATE as f64;
+        ui.horizontal(|ui| {
+            ui.label("offset");
+            ui.add(
+                DragValue::new(&mut trim.offset)
+                    .speed(sample_rate * 0.01)
+                    .custom_formatter(|samples, _| format!("{:.2} s", samples / sample_rate))
+                    .custom_parser(|text| {
+                        let seconds = text.trim().trim_end_matches('s').trim();
+                        seconds.parse::<f64>().ok().map(|s| s * sample_rate)
+                    }),
+            );
+        });
+
+        for (label, curve) in [
+            ("fade in", &mut trim.fade_in_curve),
+            ("fade out", &mut trim.fade_out_curve),
+        ] {
+            ComboBox::from_id_salt(ui.id().with(label))
+                .selected_text(format!("{label}: {curve:?}"))
+                .show_ui(ui, |ui| {
+                    for c in FadeCurve::ALL {
+                        ui.selectable_value(curve, c, format!("{c:?}"));
+                    }
+                });
+        }
+
+        ui.add(Checkbox::new(&mut trim.looping, "loop"));
+
+        if trim != self.trim() {
+            self.set_trim(trim);
+        }
     }
 
     // For the track we want to render the waveform its
@@ -4,12 +4,14 @@ use eframe::egui::mutex::Mutex;
 
 use crate::{
     audio::effects::{Effect, EffectError},
+    audio::switches::SwitchState,
     common::mipmapchannel::SamplePlotData,
 };
 
 pub struct Add {
     input_0: Mutex<Arc<dyn Effect>>,
     input_1: Mutex<Arc<dyn Effect>>,
+    switches: SwitchState,
 }
 
 impl Add {
@@ -17,6 +19,7 @@ impl Add {
         Self {
             input_0: Mutex::new(input_0),
             input_1: Mutex::new(input_1),
+            switches: SwitchState::default(),
         }
     }
 }
@@ -25,10 +28,10 @@ impl Effect for Add {
     fn apply(&self, output: &mut [f32], start_sample: usize, channels: usize) {
         //println!("{:?}, {:?}", output, output.len());
         let mut output_1 = vec![0.0; output.len()];
-        self.input_0.lock().apply(output, start_sample, channels);
+        self.input_0.lock().process(output, start_sample, channels);
         self.input_1
             .lock()
-            .apply(&mut output_1, start_sample, channels);
+            .process(&mut output_1, start_sample, channels);
 
         //println!("{:?}", output_1);
 
@@ -71,6 +74,10 @@ impl Effect for Add {
         "Add"
     }
 
+    fn effect_id(&self) -> Option<&'static str> {
+        Some("add")
+    }
+
     fn get_waveform_plot_data(
         &self,
         sample_plot_data: &mut crate::common::mipmapchannel::SamplePlotData,
@@ -82,19 +89,17 @@ impl Effect for Add {
             sample_plot_data.start_sample,
             sample_plot_data.data[0].len(),
         );
-        self.input_0
-            .lock()
-            .get_waveform_plot_data(sample_plot_data, channel);
+        self.input_0.lock().plot_data(sample_plot_data, channel);
         self.input_1
             .lock()
-            .get_waveform_plot_data(sample_plot_data, channel);
+            .plot_data(&mut sample_plot_data_1, channel);
 
         //println!("{:?}", output_1);
 
-        for k in 0..sample_plot_data.data.len() {
-            for (i, v) in &mut sample_plot_data.data[k].iter_mut().enumerate() {
-                *v += sample_plot_data_1.data[k][i];
-            }
-        }
+        sample_plot_data.mix(&sample_plot_data_1, 1.0, 1.0);
+    }
+
+    fn switch_state(&self) -> Option<&SwitchState> {
+        Some(&self.switches)
     }
 }
@@ -0,0 +1,169 @@
+use std::sync::Arc;
+
+use eframe::egui::mutex::Mutex;
+use eframe::egui::{ComboBox, Ui};
+
+use crate::audio::effects::{Effect, EffectError, parse_setting};
+use crate::audio::switches::SwitchState;
+use crate::common::Channel;
+use crate::common::mipmapchannel::SamplePlotData;
+use crate::ui::nodegraph::GraphStyle;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ChannelMode {
+    /// Leave both channels as they are
+    Stereo,
+    /// Left goes out of the right and vice versa
+    Swap,
+    /// Both channels are the average of the two
+    MonoSum,
+    /// Both channels are the left channel
+    LeftOnly,
+    /// Both channels are the right channel
+    RightOnly,
+}
+
+impl ChannelMode {
+    pub const ALL: [ChannelMode; 5] = [
+        ChannelMode::Stereo,
+        ChannelMode::Swap,
+        ChannelMode::MonoSum,
+        ChannelMode::LeftOnly,
+        ChannelMode::RightOnly,
+    ];
+
+    /// Returns the new (left, right) pair from the old one
+    pub fn route(self, left: f32, right: f32) -> (f32, f32) {
+        match self {
+            ChannelMode::Stereo => (left, right),
+            ChannelMode::Swap => (right, left),
+            ChannelMode::MonoSum => {
+                let mono = (left + right) / 2.0;
+                (mono, mono)
+            }
+            ChannelMode::LeftOnly => (left, left),
+            ChannelMode::RightOnly => (right, right),
+        }
+    }
+}
+
+/// Swap, sum or duplicate the left and right channels.
+pub struct ChannelUtility {
+    mode: Mutex<ChannelMode>,
+    input: Mutex<Arc<dyn Effect>>,
+    switches: SwitchState,
+}
+
+impl ChannelUtility {
+    pub fn new(mode: ChannelMode, input: Arc<dyn Effect>) -> Self {
+        Self {
+            mode: Mutex::new(mode),
+            input: Mutex::new(input),
+            switches: SwitchState::default(),
+        }
+    }
+
+    pub fn mode(&self) -> ChannelMode {
+        *self.mode.lock()
+    }
+}
+
+impl Effect for ChannelUtility {
+    fn apply(&self, output: &mut [f32], start_sample: usize, channels: usize) {
+        self.input.lock().process(output, start_sample, channels);
+
+        // A mono output only ever gets the left channel
+        if channels < 2 {
+            return;
+        }
+
+        let mode = self.mode();
+        for frame in output.chunks_mut(channels) {
+            (frame[0], frame[1]) = mode.route(frame[0], frame[1]);
+        }
+    }
+
+    fn input_count(&self) -> usize {
+        1
+    }
+
+    fn output_count(&self) -> usize {
+        1
+    }
+
+    fn set_input_at_index(&self, index: usize, input: Arc<dyn Effect>) -> Result<(), EffectError> {
+        match index {
+            0 => {
+                *self.input.lock() = input;
+                Ok(())
+            }
+            _ => Err(EffectError::OutOfBounds(index)),
+        }
+    }
+
+    fn get_input_at_index(&self, index: usize) -> Result<Arc<dyn Effect>, EffectError> {
+        match index {
+            0 => Ok(self.input.lock().clone()),
+            _ => Err(EffectError::OutOfBounds(index)),
+        }
+    }
+
+    fn name(&self) -> &str {
+        "Channels"
+    }
+
+    fn effect_id(&self) -> Option<&'static str> {
+        Some("channels")
+    }
+
+    fn settings(&self) -> Vec<(&'static str, String)> {
+        vec![("mode", format!("{:?}", *self.mode.lock()))]
+    }
+
+    fn set_setting(&self, name: &str, value: &str) -> Result<(), EffectError> {
+        match name {
+            "mode" => *self.mode.lock() = parse_setting(&ChannelMode::ALL, name, value)?,
+            _ => return Err(EffectError::UnknownSetting(name.to_string())),
+        }
+        Ok(())
+    }
+
+    fn data_ui(&self, ui: &mut Ui, _style: &GraphStyle) {
+        let mut mode = self.mode.lock();
+        ComboBox::from_id_salt(ui.id().with("channel_mode"))
+            .selected_text(format!("{:?}", *mode))
+            .show_ui(ui, |ui| {
+                for m in ChannelMode::ALL {
+                    ui.selectable_value(&mut *mode, m, format!("{m:?}"));
+                }
+            });
+    }
+
+    fn get_waveform_plot_data(&self, sample_plot_data: &mut SamplePlotData, channel: &Channel) {
+        let input = self.input.lock().clone();
+
+        let source = match (self.mode(), channel) {
+            (ChannelMode::Stereo | ChannelMode::LeftOnly, Channel::Left)
+            | (ChannelMode::Swap | ChannelMode::LeftOnly, Channel::Right) => Channel::Left,
+            (ChannelMode::Stereo | ChannelMode::RightOnly, Channel::Right)
+            | (ChannelMode::Swap | ChannelMode::RightOnly, Channel::Left) => Channel::Right,
+            (ChannelMode::MonoSum, _) => {
+                let mut right = SamplePlotData::new(
+                    sample_plot_data.step,
+                    sample_plot_data.start_sample,
+                    sample_plot_data.data[0].len(),
+                );
+                input.plot_data(sample_plot_data, &Channel::Left);
+                input.plot_data(&mut right, &Channel::Right);
+                sample_plot_data.mix(&right, 0.5, 0.5);
+                return;
+            }
+        };
+
+        input.plot_data(sample_plot_data, &source);
+    }
+
+    fn switch_state(&self) -> Option<&SwitchState> {
+        Some(&self.switches)
+    }
+}
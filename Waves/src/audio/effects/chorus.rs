@@ -0,0 +1,222 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use eframe::egui::mutex::Mutex;
+use eframe::egui::{Slider, Ui};
+
+use crate::audio::delayline::DelayLine;
+use crate::audio::effects::lfo::phase_at;
+use crate::audio::effects::{Effect, EffectError};
+use crate::audio::parameter::{Parameter, get_modulation_input, set_modulation_input};
+use crate::audio::streamstate::StreamState;
+use crate::audio::switches::SwitchState;
+use crate::common::mipmapchannel::SamplePlotData;
+use crate::common::{Channel, SAMPLE_RATE};
+use crate::ui::nodegraph::GraphStyle;
+
+const MAX_VOICES: usize = 4;
+const MAX_DELAY_MS: f32 = 40.0;
+const MAX_DEPTH_MS: f32 = 10.0;
+
+#[derive(Clone)]
+struct ChorusState {
+    line: DelayLine,
+    phase: f32,
+}
+
+/// Mixes in a few copies of the signal, each delayed by a slowly wobbling amount
+pub struct Chorus {
+    voices: Mutex<usize>,
+    rate: Parameter,
+    depth: Parameter,
+    delay: Parameter,
+    mix: Parameter,
+    input: Mutex<Arc<dyn Effect>>,
+    states: StreamState<ChorusState>,
+    switches: SwitchState,
+}
+
+impl Chorus {
+    pub fn new(voices: usize, rate: f32, depth_ms: f32, input: Arc<dyn Effect>) -> Self {
+        Self {
+            voices: Mutex::new(voices.clamp(1, MAX_VOICES)),
+            rate: Parameter::new("rate", rate, 0.01..=5.0).logarithmic(true),
+            depth: Parameter::new("depth", depth_ms, 0.0..=MAX_DEPTH_MS),
+            delay: Parameter::new("delay", 15.0, 5.0..=MAX_DELAY_MS),
+            mix: Parameter::new("mix", 0.5, 0.0..=1.0),
+            input: Mutex::new(input),
+            states: StreamState::default(),
+            switches: SwitchState::default(),
+        }
+    }
+
+    pub fn voices(&self) -> usize {
+        *self.voices.lock()
+    }
+
+    fn line_length() -> usize {
+        ((MAX_DELAY_MS + MAX_DEPTH_MS) / 1000.0 * SAMPLE_RATE) as usize + 4
+    }
+
+    /// Run the chorus over the frames in place, carrying on from the given state
+    fn process(
+        &self,
+        state: &mut ChorusState,
+        frames: &mut [f32],
+        start_sample: usize,
+        channels: usize,
+    ) {
+        let count = frames.len() / channels;
+        let rates = self.rate.values(start_sample, count);
+        let depths = self.depth.values(start_sample, count);
+        let delays = self.delay.values(start_sample, count);
+        let mixes = self.mix.values(start_sample, count);
+        let voices = self.voices();
+
+        let mut wet = vec![0.0; channels];
+        for (i, frame) in frames.chunks_mut(channels).enumerate() {
+            wet.fill(0.0);
+            for (c, w) in wet.iter_mut().enumerate() {
+                for v in 0..voices {
+                    // spread the voices around the cycle, and the channels a quarter apart for some width
+                    let phase = state.phase + v as f32 / voices as f32 + c as f32 * 0.25;
+                    let sweep = 0.5 + 0.5 * (2.0 * PI * phase).sin();
+                    let delay_ms = delays[i] + depths[i] * sweep;
+                    *w += state.line.read(c, delay_ms / 1000.0 * SAMPLE_RATE);
+                }
+                *w /= voices as f32;
+            }
+
+            state.line.push(frame);
+            for (f, w) in frame.iter_mut().zip(&wet) {
+                *f = *f * (1.0 - mixes[i]) + w * mixes[i];
+            }
+
+            state.phase = (state.phase + rates[i] / SAMPLE_RATE).fract();
+        }
+    }
+}
+
+impl Effect for Chorus {
+    fn apply(&self, output: &mut [f32], start_sample: usize, channels: usize) {
+        let mut state = match self.states.take(start_sample) {
+            Some(state) if state.line.channels() == channels => state,
+            _ => {
+                // We have been moved so fill the delay line with what came just before
+                let pre_roll = Self::line_length().min(start_sample);
+                let mut history = vec![0.0; pre_roll * channels];
+                self.input
+                    .lock()
+                    .process(&mut history, start_sample - pre_roll, channels);
+
+                let mut state = ChorusState {
+                    line: DelayLine::new(Self::line_length(), channels),
+                    phase: phase_at(self.rate.get(), start_sample - pre_roll),
+                };
+                self.process(&mut state, &mut history, start_sample - pre_roll, channels);
+                state
+            }
+        };
+
+        self.input.lock().process(output, start_sample, channels);
+        self.process(&mut state, output, start_sample, channels);
+
+        self.states
+            .store(start_sample + output.len() / channels, state);
+    }
+
+    fn input_count(&self) -> usize {
+        1 + self.parameters().len()
+    }
+
+    fn output_count(&self) -> usize {
+        1
+    }
+
+    fn set_input_at_index(&self, index: usize, input: Arc<dyn Effect>) -> Result<(), EffectError> {
+        match index {
+            0 => {
+                *self.input.lock() = input;
+                Ok(())
+            }
+            _ => set_modulation_input(&self.parameters(), index, 1, input),
+        }
+    }
+
+    fn get_input_at_index(&self, index: usize) -> Result<Arc<dyn Effect>, EffectError> {
+        match index {
+            0 => Ok(self.input.lock().clone()),
+            _ => get_modulation_input(&self.parameters(), index, 1),
+        }
+    }
+
+    fn name(&self) -> &str {
+        "Chorus"
+    }
+
+    fn effect_id(&self) -> Option<&'static str> {
+        Some("chorus")
+    }
+
+    fn settings(&self) -> Vec<(&'static str, String)> {
+        vec![("voices", self.voices.lock().to_string())]
+    }
+
+    fn set_setting(&self, name: &str, value: &str) -> Result<(), EffectError> {
+        let invalid = || EffectError::InvalidSetting {
+            name: name.to_string(),
+            value: value.to_string(),
+        };
+        match name {
+            "voices" => {
+                let count = value.parse::<usize>().map_err(|_| invalid())?;
+                *self.voices.lock() = count.clamp(1, MAX_VOICES);
+            }
+            _ => return Err(EffectError::UnknownSetting(name.to_string())),
+        }
+        Ok(())
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![&self.rate, &self.depth, &self.delay, &self.mix]
+    }
+
+    fn data_ui(&self, ui: &mut Ui, _style: &GraphStyle) {
+        ui.add(Slider::new(&mut *self.voices.lock(), 1..=MAX_VOICES).text("voices"));
+        self.rate.ui(ui);
+        self.depth.ui(ui);
+        self.delay.ui(ui);
+        self.mix.ui(ui);
+    }
+
+    /// The delays are too short to see in the overview, so this just shows the input
+    fn get_waveform_plot_data(&self, sample_plot_data: &mut SamplePlotData, channel: &Channel) {
+        self.input.lock().plot_data(sample_plot_data, channel);
+    }
+
+    fn switch_state(&self) -> Option<&SwitchState> {
+        Some(&self.switches)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audio::effects::sinewave::SineWave;
+    use crate::audio::effects::testing::{assert_blocks_carry_on, assert_relocation_matches};
+
+    fn chorus() -> Chorus {
+        let sine = Arc::new(SineWave::new(0.5, 220.0, 0.0));
+        Chorus::new(3, 0.8, 5.0, sine)
+    }
+
+    #[test]
+    fn test_blocks_carry_on() {
+        assert_blocks_carry_on(chorus, 10000, 1024, 2, 0.0);
+    }
+
+    #[test]
+    fn test_relocation_matches() {
+        assert_relocation_matches(chorus, 15000, 256, 1e-3);
+    }
+}
@@ -0,0 +1,505 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use eframe::egui::mutex::Mutex;
+use eframe::egui::{ComboBox, Pos2, Sense, Shape, Stroke, StrokeKind, Ui, Vec2};
+
+use crate::audio::effects::{Effect, EffectError, parse_setting};
+use crate::audio::parameter::{Parameter, get_modulation_input, set_modulation_input};
+use crate::audio::streamstate::StreamState;
+use crate::audio::switches::SwitchState;
+use crate::common::mipmapchannel::SamplePlotData;
+use crate::common::{Channel, dB};
+use crate::ui::nodegraph::GraphStyle;
+
+/// How many zero crossings of the sinc the oversampling filters have either side of their centre
+const HALF_WIDTH: usize = 8;
+
+/// How many frames before a relocation we run over to fill the filters and the sample hold
+const PRE_ROLL: usize = 128;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Curve {
+    Tanh,
+    HardClip,
+    Foldback,
+    BitCrush,
+    SampleRateReduction,
+}
+
+impl Curve {
+    pub const ALL: [Curve; 5] = [
+        Curve::Tanh,
+        Curve::HardClip,
+        Curve::Foldback,
+        Curve::BitCrush,
+        Curve::SampleRateReduction,
+    ];
+
+    /// Shapes a single (already driven) sample. Sample rate reduction is done separately
+    /// since it works over time, so here it only clips like the rest of the digital ones.
+    fn shape(self, x: f32, bits: f32) -> f32 {
+        match self {
+            Curve::Tanh => x.tanh(),
+            Curve::HardClip | Curve::SampleRateReduction => x.clamp(-1.0, 1.0),
+            // reflect back off 1 and -1 as many times as it takes
+            Curve::Foldback => 1.0 - ((x + 1.0).rem_euclid(4.0) - 2.0).abs(),
+            Curve::BitCrush => {
+                let steps = 2f32.powf(bits - 1.0);
+                (x.clamp(-1.0, 1.0) * steps).round() / steps
+            }
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Oversampling {
+    Off,
+    X2,
+    X4,
+    X8,
+}
+
+impl Oversampling {
+    pub const ALL: [Oversampling; 4] = [
+        Oversampling::Off,
+        Oversampling::X2,
+        Oversampling::X4,
+        Oversampling::X8,
+    ];
+
+    pub fn factor(self) -> usize {
+        match self {
+            Oversampling::Off => 1,
+            Oversampling::X2 => 2,
+            Oversampling::X4 => 4,
+            Oversampling::X8 => 8,
+        }
+    }
+
+    /// The filters are symmetric so the signal comes out this many frames late,
+    /// which we make up for by asking our input for audio this far ahead
+    fn latency(self) -> usize {
+        match self {
+            Oversampling::Off => 0,
+            _ => 2 * HALF_WIDTH,
+        }
+    }
+
+    /// A blackman windowed sinc low pass at the original nyquist, running at the oversampled rate
+    fn kernel(self) -> Vec<f32> {
+        let factor = self.factor();
+        let length = 2 * HALF_WIDTH * factor + 1;
+        let centre = (length / 2) as f32;
+
+        (0..length)
+            .map(|i| {
+                let x = (i as f32 - centre) / factor as f32;
+                let sinc = match x == 0.0 {
+                    true => 1.0,
+                    false => (PI * x).sin() / (PI * x),
+                };
+                let w = 2.0 * PI * i as f32 / (length - 1) as f32;
+                let window = 0.42 - 0.5 * w.cos() + 0.08 * (2.0 * w).cos();
+                sinc * window / factor as f32
+            })
+            .collect()
+    }
+}
+
+/// What the filters and sample hold remember for each channel between calls
+#[derive(Clone)]
+struct DistortionState {
+    oversampling: Oversampling,
+    /// The last few oversampled inputs (before filtering) for each channel
+    up: Vec<Vec<f32>>,
+    /// The last few shaped samples for each channel
+    down: Vec<Vec<f32>>,
+    held: Vec<f32>,
+}
+
+impl DistortionState {
+    fn new(oversampling: Oversampling, channels: usize) -> Self {
+        let history = 2 * HALF_WIDTH * oversampling.factor();
+        Self {
+            oversampling,
+            up: vec![vec![0.0; history]; channels],
+            down: vec![vec![0.0; history]; channels],
+            held: vec![0.0; channels],
+        }
+    }
+}
+
+/// Convolve the kernel over the signal that follows the history, returning every `step`th result
+/// and leaving the end of the signal as the new history
+fn convolve(kernel: &[f32], history: &mut Vec<f32>, signal: &[f32], step: usize) -> Vec<f32> {
+    let keep = history.len();
+    history.extend_from_slice(signal);
+
+    let out = (0..signal.len())
+        .step_by(step)
+        .map(|i| {
+            let window = &history[i + keep + 1 - kernel.len()..=i + keep];
+            window.iter().rev().zip(kernel).map(|(x, k)| x * k).sum()
+        })
+        .collect();
+
+    history.drain(..signal.len());
+    out
+}
+
+/// A waveshaper to add some dirt, oversampled so the harmonics it makes don't fold back down as aliasing
+pub struct Distortion {
+    curve: Mutex<Curve>,
+    oversampling: Mutex<Oversampling>,
+    drive: Parameter,
+    level: Parameter,
+    bits: Parameter,
+    reduction: Parameter,
+    input: Mutex<Arc<dyn Effect>>,
+    states: StreamState<DistortionState>,
+    switches: SwitchState,
+}
+
+impl Distortion {
+    pub fn new(
+        curve: Curve,
+        drive: dB,
+        oversampling: Oversampling,
+        input: Arc<dyn Effect>,
+    ) -> Self {
+        Self {
+            curve: Mutex::new(curve),
+            oversampling: Mutex::new(oversampling),
+            drive: Parameter::new("drive", drive.0, 0.0..=36.0),
+            level: Parameter::new("level", 0.0, -24.0..=6.0),
+            bits: Parameter::new("bits", 8.0, 1.0..=16.0),
+            reduction: Parameter::new("reduction", 4.0, 1.0..=64.0).logarithmic(true),
+            input: Mutex::new(input),
+            states: StreamState::default(),
+            switches: SwitchState::default(),
+        }
+    }
+
+    pub fn curve(&self) -> Curve {
+        *self.curve.lock()
+    }
+
+    pub fn oversampling(&self) -> Oversampling {
+        *self.oversampling.lock()
+    }
+
+    /// Distort the frames in place, carrying on from the given state
+    fn process(
+        &self,
+        state: &mut DistortionState,
+        frames: &mut [f32],
+        start_sample: usize,
+        channels: usize,
+    ) {
+        let count = frames.len() / channels;
+        let drives = self.drive.values(start_sample, count);
+        let levels = self.level.values(start_sample, count);
+        let bits = self.bits.values(start_sample, count);
+        let reductions = self.reduction.values(start_sample, count);
+        let curve = self.curve();
+
+        let factor = state.oversampling.factor();
+        let kernel = state.oversampling.kernel();
+
+        for c in 0..channels {
+            // stuff zeros between the samples (scaled up to keep the level once filtered)
+            let mut signal = vec![0.0; count * factor];
+            for (i, frame) in frames.chunks(channels).enumerate() {
+                signal[i * factor] = frame[c] * factor as f32;
+            }
+
+            if factor > 1 {
+                signal = convolve(&kernel, &mut state.up[c], &signal, 1);
+            }
+
+            for (j, s) in signal.iter_mut().enumerate() {
+                let i = j / factor;
+                let driven = *s * dB(drives[i]).to_amplitude();
+
+                if curve == Curve::SampleRateReduction {
+                    // hold on to a sample at a time, lined up with the start of the track so it doesn't move about
+                    let hold = (reductions[i].round() as usize).max(1) * factor;
+                    if (start_sample * factor + j).is_multiple_of(hold) {
+                        state.held[c] = driven;
+                    }
+                    *s = curve.shape(state.held[c], bits[i]);
+                } else {
+                    *s = curve.shape(driven, bits[i]);
+                }
+            }
+
+            if factor > 1 {
+                signal = convolve(&kernel, &mut state.down[c], &signal, factor);
+            }
+
+            for (i, (frame, s)) in frames.chunks_mut(channels).zip(signal).enumerate() {
+                frame[c] = s * dB(levels[i]).to_amplitude();
+            }
+        }
+    }
+}
+
+impl Effect for Distortion {
+    fn apply(&self, output: &mut [f32], start_sample: usize, channels: usize) {
+        let oversampling = self.oversampling();
+        let latency = oversampling.latency();
+
+        let mut state = match self.states.take(start_sample) {
+            Some(state) if state.oversampling == oversampling && state.held.len() == channels => {
+                state
+            }
+            _ => {
+                // We have been moved (or the oversampling changed) so run over what came just before to settle down
+                let pre_roll = PRE_ROLL.min(start_sample);
+                let mut history = vec![0.0; pre_roll * channels];
+                self.input.lock().process(
+                    &mut history,
+                    start_sample - pre_roll + latency,
+                    channels,
+                );
+
+                let mut state = DistortionState::new(oversampling, channels);
+                self.process(&mut state, &mut history, start_sample - pre_roll, channels);
+                state
+            }
+        };
+
+        self.input
+            .lock()
+            .process(output, start_sample + latency, channels);
+        self.process(&mut state, output, start_sample, channels);
+
+        self.states
+            .store(start_sample + output.len() / channels, state);
+    }
+
+    fn input_count(&self) -> usize {
+        1 + self.parameters().len()
+    }
+
+    fn output_count(&self) -> usize {
+        1
+    }
+
+    fn set_input_at_index(&self, index: usize, input: Arc<dyn Effect>) -> Result<(), EffectError> {
+        match index {
+            0 => {
+                *self.input.lock() = input;
+                Ok(())
+            }
+            _ => set_modulation_input(&self.parameters(), index, 1, input),
+        }
+    }
+
+    fn get_input_at_index(&self, index: usize) -> Result<Arc<dyn Effect>, EffectError> {
+        match index {
+            0 => Ok(self.input.lock().clone()),
+            _ => get_modulation_input(&self.parameters(), index, 1),
+        }
+    }
+
+    fn name(&self) -> &str {
+        "Distortion"
+    }
+
+    fn effect_id(&self) -> Option<&'static str> {
+        Some("distortion")
+    }
+
+    fn settings(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("curve", format!("{:?}", *self.curve.lock())),
+            ("oversampling", format!("{:?}", *self.oversampling.lock())),
+        ]
+    }
+
+    fn set_setting(&self, name: &str, value: &str) -> Result<(), EffectError> {
+        match name {
+            "curve" => *self.curve.lock() = parse_setting(&Curve::ALL, name, value)?,
+            "oversampling" => {
+                *self.oversampling.lock() = parse_setting(&Oversampling::ALL, name, value)?
+            }
+            _ => return Err(EffectError::UnknownSetting(name.to_string())),
+        }
+        Ok(())
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![&self.drive, &self.level, &self.bits, &self.reduction]
+    }
+
+    fn data_ui(&self, ui: &mut Ui, style: &GraphStyle) {
+        {
+            let mut curve = self.curve.lock();
+            ComboBox::from_id_salt(ui.id().with("distortion_curve"))
+                .selected_text(format!("{:?}", *curve))
+                .show_ui(ui, |ui| {
+                    for c in Curve::ALL {
+                        ui.selectable_value(&mut *curve, c, format!("{c:?}"));
+                    }
+                });
+        }
+        {
+            let mut oversampling = self.oversampling.lock();
+            ComboBox::from_id_salt(ui.id().with("distortion_oversampling"))
+                .selected_text(format!("{:?}", *oversampling))
+                .show_ui(ui, |ui| {
+                    for o in Oversampling::ALL {
+                        ui.selectable_value(&mut *oversampling, o, format!("{o:?}"));
+                    }
+                });
+        }
+
+        let curve = self.curve();
+        self.drive.ui(ui);
+        self.level.ui(ui);
+        match curve {
+            Curve::BitCrush => self.bits.ui(ui),
+            Curve::SampleRateReduction => self.reduction.ui(ui),
+            _ => (),
+        }
+
+        // The transfer curve, input along the bottom and output up the side, both from -1 to 1
+        let (response, painter) = ui.allocate_painter(
+            Vec2::new(style.plot_width, style.plot_height),
+            Sense::hover(),
+        );
+        let rect = response.rect;
+        let stroke = Stroke::new(1.0, style.line_colour);
+        painter.rect_stroke(rect, 0.0, stroke, StrokeKind::Inside);
+        painter.line_segment([rect.left_center(), rect.right_center()], stroke);
+        painter.line_segment([rect.center_top(), rect.center_bottom()], stroke);
+
+        let drive = dB(self.drive.get()).to_amplitude();
+        let level = dB(self.level.get()).to_amplitude();
+        let bits = self.bits.get();
+        let points = (0..=rect.width() as usize)
+            .map(|i| {
+                let x = 2.0 * i as f32 / rect.width() - 1.0;
+                let y = (curve.shape(x * drive, bits) * level).clamp(-1.0, 1.0);
+                Pos2::new(
+                    rect.left() + i as f32,
+                    rect.center().y - y * rect.height() / 2.0,
+                )
+            })
+            .collect();
+        painter.add(Shape::line(points, Stroke::new(2.0, style.line_colour)));
+    }
+
+    /// Drawn as if every point went through the curve on its own (so without the smoothing of the oversampling)
+    fn get_waveform_plot_data(&self, sample_plot_data: &mut SamplePlotData, channel: &Channel) {
+        self.input.lock().plot_data(sample_plot_data, channel);
+
+        let curve = self.curve();
+        let drive = dB(self.drive.get()).to_amplitude();
+        let level = dB(self.level.get()).to_amplitude();
+        let bits = self.bits.get();
+
+        for v in &mut sample_plot_data.data {
+            for j in v {
+                *j = curve.shape(*j * drive, bits) * level;
+            }
+        }
+
+        // folding back can swap which line is on top
+        if curve == Curve::Foldback && sample_plot_data.is_min_max {
+            let (min, max) = sample_plot_data.data.split_at_mut(1);
+            for (lower, upper) in min[0].iter_mut().zip(max[0].iter_mut()) {
+                if lower > upper {
+                    std::mem::swap(lower, upper);
+                }
+            }
+        }
+    }
+
+    fn switch_state(&self) -> Option<&SwitchState> {
+        Some(&self.switches)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audio::effects::sinewave::SineWave;
+    use crate::audio::effects::testing::{assert_blocks_carry_on, assert_relocation_matches};
+    use crate::common::SAMPLE_RATE;
+
+    #[test]
+    fn test_curves() {
+        assert_eq!(Curve::HardClip.shape(2.0, 8.0), 1.0);
+        assert_eq!(Curve::HardClip.shape(-0.5, 8.0), -0.5);
+        assert!((Curve::Foldback.shape(1.5, 8.0) - 0.5).abs() < 1e-6);
+        assert!((Curve::Foldback.shape(-2.5, 8.0) - 0.5).abs() < 1e-6);
+        assert!((Curve::Tanh.shape(-0.3, 8.0) + 0.3f32.tanh()).abs() < 1e-6);
+        assert_eq!(Curve::BitCrush.shape(0.3, 1.0), 0.0);
+        assert_eq!(Curve::BitCrush.shape(0.3, 3.0), 0.25);
+    }
+
+    #[test]
+    fn test_oversampling_is_transparent() {
+        // a quiet sine hardly touches the curve, so should come out as it went in (no later because of the filters)
+        for oversampling in Oversampling::ALL {
+            let sine = Arc::new(SineWave::new(0.1, 440.0, 0.0));
+            let d = Distortion::new(Curve::HardClip, dB(0.0), oversampling, sine.clone());
+
+            let mut expected = vec![0.0; 1024];
+            sine.apply(&mut expected, 4800, 1);
+            let mut output = vec![0.0; 1024];
+            d.apply(&mut output, 4800, 1);
+
+            for (a, b) in expected.iter().zip(&output) {
+                assert!((a - b).abs() < 1e-3, "{oversampling:?} changed the signal");
+            }
+        }
+    }
+
+    #[test]
+    fn test_blocks_carry_on() {
+        let distortion = || {
+            let sine = Arc::new(SineWave::new(0.8, 3000.0, 0.0));
+            Distortion::new(Curve::Tanh, dB(12.0), Oversampling::X4, sine)
+        };
+        assert_blocks_carry_on(distortion, 4800, 1024, 2, 1e-6);
+    }
+
+    #[test]
+    fn test_relocation_matches() {
+        let distortion = || {
+            let sine = Arc::new(SineWave::new(0.8, 3000.0, 0.0));
+            Distortion::new(Curve::Tanh, dB(12.0), Oversampling::X4, sine)
+        };
+        assert_relocation_matches(distortion, 15000, 256, 1e-4);
+    }
+
+    /// How much of the frequency there is in the samples, which must hold a whole number of its cycles
+    fn magnitude(samples: &[f32], frequency: f32) -> f32 {
+        let (mut re, mut im) = (0.0, 0.0);
+        for (i, s) in samples.iter().enumerate() {
+            let w = 2.0 * PI * frequency * i as f32 / SAMPLE_RATE;
+            re += s * w.cos();
+            im -= s * w.sin();
+        }
+        2.0 * (re * re + im * im).sqrt() / samples.len() as f32
+    }
+
+    #[test]
+    fn test_oversampling_stops_aliasing() {
+        // clipping a 5kHz sine makes odd harmonics, of which 45kHz and 55kHz fold back down to 3kHz and 7kHz
+        let aliased = |oversampling| {
+            let sine = Arc::new(SineWave::new(0.8, 5000.0, 0.0));
+            let d = Distortion::new(Curve::HardClip, dB(24.0), oversampling, sine);
+            let mut output = vec![0.0; 4800];
+            d.apply(&mut output, 4800, 1);
+            magnitude(&output, 3000.0) + magnitude(&output, 7000.0)
+        };
+
+        let (off, on) = (aliased(Oversampling::Off), aliased(Oversampling::X8));
+        assert!(on < off / 100.0, "aliasing went from {off} to {on}");
+    }
+}
@@ -0,0 +1,154 @@
+use std::sync::Arc;
+
+use eframe::egui::Ui;
+use eframe::egui::mutex::Mutex;
+
+use crate::audio::effects::{Effect, EffectError};
+use crate::audio::parameter::{Parameter, get_modulation_input, set_modulation_input};
+use crate::audio::port::PortType;
+use crate::audio::streamstate::StreamState;
+use crate::audio::switches::SwitchState;
+use crate::common::mipmapchannel::SamplePlotData;
+use crate::common::{Channel, SAMPLE_RATE};
+use crate::ui::nodegraph::GraphStyle;
+
+/// One pole smoothing coefficient for a time in milliseconds
+fn coefficient(time_ms: f32) -> f32 {
+    (-1000.0 / (time_ms * SAMPLE_RATE)).exp()
+}
+
+/// Follows how loud its input is, giving a value between 0 and (roughly) 1 to plug into other parameters.
+pub struct EnvelopeFollower {
+    attack: Parameter,
+    release: Parameter,
+    input: Mutex<Arc<dyn Effect>>,
+    states: StreamState<f32>,
+    switches: SwitchState,
+}
+
+impl EnvelopeFollower {
+    pub fn new(attack_ms: f32, release_ms: f32, input: Arc<dyn Effect>) -> Self {
+        Self {
+            attack: Parameter::new("attack", attack_ms, 0.1..=500.0).logarithmic(true),
+            release: Parameter::new("release", release_ms, 1.0..=2000.0).logarithmic(true),
+            input: Mutex::new(input),
+            states: StreamState::default(),
+            switches: SwitchState::default(),
+        }
+    }
+
+    /// Run the follower over the frames, leaving the envelope in every channel
+    fn follow(&self, envelope: &mut f32, frames: &mut [f32], start_sample: usize, channels: usize) {
+        let count = frames.len() / channels;
+        let attacks = self.attack.values(start_sample, count);
+        let releases = self.release.values(start_sample, count);
+
+        for ((frame, attack), release) in frames.chunks_mut(channels).zip(attacks).zip(releases) {
+            let level = frame.iter().fold(0.0f32, |m, f| m.max(f.abs()));
+            let c = match level > *envelope {
+                true => coefficient(attack),
+                false => coefficient(release),
+            };
+            *envelope = c * *envelope + (1.0 - c) * level;
+
+            for f in frame {
+                *f = *envelope;
+            }
+        }
+    }
+}
+
+impl Effect for EnvelopeFollower {
+    fn apply(&self, output: &mut [f32], start_sample: usize, channels: usize) {
+        let mut envelope = match self.states.take(start_sample) {
+            Some(envelope) => envelope,
+            None => {
+                // We have been moved so listen to what came just before to settle down
+                let pre_roll = ((self.release.get() / 1000.0 * SAMPLE_RATE) as usize)
+                    .min(SAMPLE_RATE as usize)
+                    .min(start_sample);
+                let mut history = vec![0.0; pre_roll * channels];
+                self.input
+                    .lock()
+                    .process(&mut history, start_sample - pre_roll, channels);
+
+                let mut envelope = 0.0;
+                self.follow(
+                    &mut envelope,
+                    &mut history,
+                    start_sample - pre_roll,
+                    channels,
+                );
+                envelope
+            }
+        };
+
+        self.input.lock().process(output, start_sample, channels);
+        self.follow(&mut envelope, output, start_sample, channels);
+
+        self.states
+            .store(start_sample + output.len() / channels, envelope);
+    }
+
+    fn input_count(&self) -> usize {
+        1 + self.parameters().len()
+    }
+
+    fn output_count(&self) -> usize {
+        1
+    }
+
+    fn output_type(&self, _index: usize) -> PortType {
+        PortType::Control
+    }
+
+    fn set_input_at_index(&self, index: usize, input: Arc<dyn Effect>) -> Result<(), EffectError> {
+        match index {
+            0 => {
+                *self.input.lock() = input;
+                Ok(())
+            }
+            _ => set_modulation_input(&self.parameters(), index, 1, input),
+        }
+    }
+
+    fn get_input_at_index(&self, index: usize) -> Result<Arc<dyn Effect>, EffectError> {
+        match index {
+            0 => Ok(self.input.lock().clone()),
+            _ => get_modulation_input(&self.parameters(), index, 1),
+        }
+    }
+
+    fn name(&self) -> &str {
+        "Envelope Follower"
+    }
+
+    fn effect_id(&self) -> Option<&'static str> {
+        Some("envelope_follower")
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![&self.attack, &self.release]
+    }
+
+    fn data_ui(&self, ui: &mut Ui, _style: &GraphStyle) {
+        self.attack.ui(ui);
+        self.release.ui(ui);
+    }
+
+    /// Approximated by the loudest the input gets around each point
+    fn get_waveform_plot_data(&self, sample_plot_data: &mut SamplePlotData, channel: &Channel) {
+        self.input.lock().plot_data(sample_plot_data, channel);
+
+        let (min, max) = sample_plot_data.data.split_at_mut(1);
+        for (lower, upper) in min[0].iter_mut().zip(max[0].iter_mut()) {
+            let level = lower.abs().max(upper.abs());
+            (*lower, *upper) = (level, level);
+        }
+        sample_plot_data.is_min_max = false;
+    }
+
+    fn switch_state(&self) -> Option<&SwitchState> {
+        Some(&self.switches)
+    }
+}
@@ -0,0 +1,301 @@
+use std::sync::Arc;
+
+use eframe::egui::Ui;
+use eframe::egui::mutex::Mutex;
+
+use crate::audio::effects::{Effect, EffectError};
+use crate::audio::parameter::{Parameter, get_modulation_input, set_modulation_input};
+use crate::audio::port::PortType;
+use crate::audio::streamstate::StreamState;
+use crate::audio::switches::SwitchState;
+use crate::common::mipmapchannel::SamplePlotData;
+use crate::common::{Channel, SAMPLE_RATE};
+use crate::ui::nodegraph::GraphStyle;
+
+/// How far back a trigger envelope looks for the last event after being moved, in seconds
+const LOOK_BACK: f32 = 2.0;
+
+/// Whether a clock going at bpm has a tick on the sample, which is worked out from the start so it never drifts
+fn ticks_at(bpm: f32, sample: usize) -> bool {
+    let beats = |sample: usize| (sample as f64 * bpm as f64 / 60.0 / SAMPLE_RATE as f64).floor();
+    sample == 0 || beats(sample) != beats(sample - 1)
+}
+
+/// Ticks on every beat, as an event stream to trigger other effects with.
+/// An event is a single frame holding its velocity, with nothing in between
+pub struct Clock {
+    bpm: Parameter,
+    velocity: Parameter,
+    switches: SwitchState,
+}
+
+impl Clock {
+    pub fn new(bpm: f32, velocity: f32) -> Self {
+        Self {
+            bpm: Parameter::new("bpm", bpm, 20.0..=300.0),
+            velocity: Parameter::new("velocity", velocity, 0.0..=1.0),
+            switches: SwitchState::default(),
+        }
+    }
+}
+
+impl Effect for Clock {
+    fn apply(&self, output: &mut [f32], start_sample: usize, channels: usize) {
+        // the tempo only moves once a block, so the ticks stay on the beat
+        let bpm = self.bpm.values(start_sample, 1)[0];
+        let velocity = self.velocity.values(start_sample, 1)[0];
+
+        for (i, frame) in output.chunks_mut(channels).enumerate() {
+            let event = match ticks_at(bpm, start_sample + i) {
+                true => velocity,
+                false => 0.0,
+            };
+            frame.fill(event);
+        }
+    }
+
+    fn input_count(&self) -> usize {
+        self.parameters().len()
+    }
+
+    fn output_count(&self) -> usize {
+        1
+    }
+
+    fn output_type(&self, _index: usize) -> PortType {
+        PortType::Midi
+    }
+
+    fn set_input_at_index(&self, index: usize, input: Arc<dyn Effect>) -> Result<(), EffectError> {
+        set_modulation_input(&self.parameters(), index, 0, input)
+    }
+
+    fn get_input_at_index(&self, index: usize) -> Result<Arc<dyn Effect>, EffectError> {
+        get_modulation_input(&self.parameters(), index, 0)
+    }
+
+    fn name(&self) -> &str {
+        "Clock"
+    }
+
+    fn effect_id(&self) -> Option<&'static str> {
+        Some("clock")
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![&self.bpm, &self.velocity]
+    }
+
+    fn data_ui(&self, ui: &mut Ui, _style: &GraphStyle) {
+        self.bpm.ui(ui);
+        self.velocity.ui(ui);
+    }
+
+    /// A line up to the velocity wherever there is a tick
+    fn get_waveform_plot_data(&self, sample_plot_data: &mut SamplePlotData, _channel: &Channel) {
+        let (bpm, velocity) = (self.bpm.get(), self.velocity.get());
+        let beat = 60.0 / bpm * SAMPLE_RATE;
+        let (start_sample, step) = (sample_plot_data.start_sample, sample_plot_data.step);
+        sample_plot_data.is_min_max = true;
+
+        let (min, max) = sample_plot_data.data.split_at_mut(1);
+        for (i, (lower, upper)) in min[0].iter_mut().zip(max[0].iter_mut()).enumerate() {
+            let start = (start_sample + i * step) as f32;
+            // whether a beat falls between start and the next point
+            let is_tick = (start / beat).ceil() * beat < start + step as f32;
+            (*lower, *upper) = (0.0, if is_tick { velocity } else { 0.0 });
+        }
+    }
+
+    fn switch_state(&self) -> Option<&SwitchState> {
+        Some(&self.switches)
+    }
+}
+
+/// Where a trigger envelope is, as the time since it was last triggered and how hard
+#[derive(Clone, Copy, Default)]
+struct Trigger {
+    frames: usize,
+    velocity: f32,
+}
+
+/// Rises to the velocity of each event it is sent, then falls away, to plug into other parameters
+pub struct TriggerEnvelope {
+    attack: Parameter,
+    decay: Parameter,
+    input: Mutex<Arc<dyn Effect>>,
+    states: StreamState<Option<Trigger>>,
+    switches: SwitchState,
+}
+
+impl TriggerEnvelope {
+    pub fn new(attack_ms: f32, decay_ms: f32, input: Arc<dyn Effect>) -> Self {
+        Self {
+            attack: Parameter::new("attack", attack_ms, 0.1..=1000.0).logarithmic(true),
+            decay: Parameter::new("decay", decay_ms, 1.0..=5000.0).logarithmic(true),
+            input: Mutex::new(input),
+            states: StreamState::default(),
+            switches: SwitchState::default(),
+        }
+    }
+
+    /// Turn the events in the frames into the envelope, carrying on from the last trigger
+    fn follow(
+        &self,
+        trigger: &mut Option<Trigger>,
+        frames: &mut [f32],
+        start_sample: usize,
+        channels: usize,
+    ) {
+        let count = frames.len() / channels;
+        let attacks = self.attack.values(start_sample, count);
+        let decays = self.decay.values(start_sample, count);
+
+        for ((frame, attack), decay) in frames.chunks_mut(channels).zip(attacks).zip(decays) {
+            if frame[0] > 0.0 {
+                *trigger = Some(Trigger {
+                    frames: 0,
+                    velocity: frame[0],
+                });
+            }
+
+            let level = match trigger {
+                None => 0.0,
+                Some(trigger) => {
+                    let t = trigger.frames as f32 / SAMPLE_RATE * 1000.0;
+                    trigger.frames += 1;
+                    match t < attack {
+                        true => trigger.velocity * t / attack,
+                        false => trigger.velocity * (-(t - attack) / decay).exp(),
+                    }
+                }
+            };
+            frame.fill(level);
+        }
+    }
+}
+
+impl Effect for TriggerEnvelope {
+    fn apply(&self, output: &mut [f32], start_sample: usize, channels: usize) {
+        let mut trigger = match self.states.take(start_sample) {
+            Some(trigger) => trigger,
+            None => {
+                // We have been moved so find the last event from just before
+                let look_back = ((LOOK_BACK * SAMPLE_RATE) as usize).min(start_sample);
+                let mut history = vec![0.0; look_back * channels];
+                self.input
+                    .lock()
+                    .process(&mut history, start_sample - look_back, channels);
+
+                let mut trigger = None;
+                self.follow(
+                    &mut trigger,
+                    &mut history,
+                    start_sample - look_back,
+                    channels,
+                );
+                trigger
+            }
+        };
+
+        self.input.lock().process(output, start_sample, channels);
+        self.follow(&mut trigger, output, start_sample, channels);
+
+        self.states
+            .store(start_sample + output.len() / channels, trigger);
+    }
+
+    fn input_count(&self) -> usize {
+        1 + self.parameters().len()
+    }
+
+    fn output_count(&self) -> usize {
+        1
+    }
+
+    fn input_name(&self, index: usize) -> String {
+        match index {
+            0 => "events".to_string(),
+            _ => format!("{} mod", self.parameters()[index - 1].name()),
+        }
+    }
+
+    fn input_type(&self, index: usize) -> PortType {
+        match index {
+            0 => PortType::Midi,
+            _ => PortType::Control,
+        }
+    }
+
+    fn output_type(&self, _index: usize) -> PortType {
+        PortType::Control
+    }
+
+    fn set_input_at_index(&self, index: usize, input: Arc<dyn Effect>) -> Result<(), EffectError> {
+        match index {
+            0 => {
+                *self.input.lock() = input;
+                Ok(())
+            }
+            _ => set_modulation_input(&self.parameters(), index, 1, input),
+        }
+    }
+
+    fn get_input_at_index(&self, index: usize) -> Result<Arc<dyn Effect>, EffectError> {
+        match index {
+            0 => Ok(self.input.lock().clone()),
+            _ => get_modulation_input(&self.parameters(), index, 1),
+        }
+    }
+
+    fn name(&self) -> &str {
+        "Trigger Envelope"
+    }
+
+    fn effect_id(&self) -> Option<&'static str> {
+        Some("trigger_envelope")
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![&self.attack, &self.decay]
+    }
+
+    fn data_ui(&self, ui: &mut Ui, _style: &GraphStyle) {
+        self.attack.ui(ui);
+        self.decay.ui(ui);
+    }
+
+    /// The events it is sent, as the envelopes are too quick to see this far out
+    fn get_waveform_plot_data(&self, sample_plot_data: &mut SamplePlotData, channel: &Channel) {
+        self.input.lock().plot_data(sample_plot_data, channel);
+    }
+
+    fn switch_state(&self) -> Option<&SwitchState> {
+        Some(&self.switches)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_clock_triggers_envelope() {
+        // a tick every half second, which the envelope jumps up to and dies away from
+        let clock: Arc<dyn Effect> = Arc::new(Clock::new(120.0, 0.8));
+        let envelope = TriggerEnvelope::new(1.0, 50.0, clock);
+        let beat = SAMPLE_RATE as usize / 2;
+
+        let mut output = vec![0.0; beat * 2];
+        envelope.apply(&mut output, beat - 100, 1);
+        assert!(output[0] < 1e-3, "the last tick has died away");
+        let peak = output.iter().fold(0.0f32, |peak, s| peak.max(*s));
+        assert!((peak - 0.8).abs() < 1e-2);
+        assert!(output[beat] < 0.01, "it should have died away by the next");
+
+        // jumping in just after a tick still hears it
+        let mut moved = vec![0.0; 64];
+        envelope.apply(&mut moved, beat + 100, 1);
+        assert!((moved[0] - output[200]).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,506 @@
+use std::any::Any;
+use std::collections::VecDeque;
+use std::sync::{Arc, Weak};
+
+use eframe::egui::mutex::Mutex;
+use eframe::egui::{TextEdit, Ui};
+
+use crate::audio::effects::{Effect, EffectError, group::Group};
+use crate::audio::parameter::{Parameter, get_modulation_input, set_modulation_input};
+use crate::audio::streamstate::{self, StreamState};
+use crate::audio::switches::SwitchState;
+use crate::common::mipmapchannel::SamplePlotData;
+use crate::common::{Channel, SAMPLE_RATE};
+use crate::ui::nodegraph::GraphStyle;
+
+/// The longest a receive can be set to wait, in milliseconds
+const MAX_DELAY_MS: f32 = 2000.0;
+/// Room for the longest block anything asks for on top of the longest delay
+const MAX_BLOCK: usize = 8192;
+
+/// What has gone through a send recently, for one of the streams asking for audio
+#[derive(Default)]
+struct History {
+    channels: usize,
+    frames: VecDeque<f32>,
+    /// The addresses of the sends that have been through the latest block, which is what they all add up to
+    senders: Vec<usize>,
+}
+
+/// The buses of one graph by name, so the sends and receives in it with the same name find each other
+/// but those in another graph don't
+#[derive(Default)]
+pub struct FeedbackBuses {
+    buses: Mutex<Vec<(String, Weak<FeedbackBus>)>>,
+}
+
+impl FeedbackBuses {
+    /// The bus with the name, made if nothing is using it yet
+    fn named(&self, name: &str) -> Arc<FeedbackBus> {
+        let mut buses = self.buses.lock();
+        buses.retain(|(_, bus)| bus.strong_count() > 0);
+        if let Some(bus) = buses
+            .iter()
+            .find(|(n, _)| n == name)
+            .and_then(|(_, bus)| bus.upgrade())
+        {
+            return bus;
+        }
+
+        let bus = Arc::new(FeedbackBus::default());
+        buses.push((name.to_string(), Arc::downgrade(&bus)));
+        bus
+    }
+
+    /// A name no bus is using yet, for a new send to start out on
+    pub fn unused_name(&self) -> String {
+        let mut buses = self.buses.lock();
+        buses.retain(|(_, bus)| bus.strong_count() > 0);
+        (1..)
+            .map(|i| format!("bus {i}"))
+            .find(|name| buses.iter().all(|(n, _)| n != name))
+            .unwrap_or_default()
+    }
+
+    /// The name of the bus made most recently that is still in use, for a new receive to listen to
+    pub fn newest_name(&self) -> String {
+        let mut buses = self.buses.lock();
+        buses.retain(|(_, bus)| bus.strong_count() > 0);
+        match buses.last() {
+            Some((name, _)) => name.clone(),
+            None => "bus 1".to_string(),
+        }
+    }
+
+    /// Have the sends and receives in effect (or inside it, for a group) use these buses
+    pub fn adopt(self: &Arc<Self>, effect: &Arc<dyn Effect>) {
+        let any = &**effect as &dyn Any;
+        if let Some(send) = any.downcast_ref::<FeedbackSend>() {
+            send.bus.join(self);
+        } else if let Some(receive) = any.downcast_ref::<FeedbackReceive>() {
+            receive.bus.join(self);
+        } else if let Some(group) = any.downcast_ref::<Group>() {
+            for effect in group.effects() {
+                self.adopt(effect);
+            }
+        }
+    }
+}
+
+/// What joins the sends and receives with the same name
+#[derive(Default)]
+pub struct FeedbackBus {
+    histories: StreamState<History>,
+}
+
+impl FeedbackBus {
+    /// Keep what the sender sent between start_sample and the end of frames.
+    /// Several sends on the one bus are added together
+    fn record(&self, sender: usize, frames: &[f32], start_sample: usize, channels: usize) {
+        let end = start_sample + frames.len() / channels;
+        // (whether another send has already been through this block, whether this one has)
+        let (is_added, is_sent) = self
+            .histories
+            .peek(end, |history| {
+                let fits = history.channels == channels && history.frames.len() >= frames.len();
+                let is_sent = history.senders.contains(&sender);
+                (fits && !is_sent, fits && is_sent)
+            })
+            .unwrap_or_default();
+        // the send taken from more than once in the block has nothing more to add
+        if is_sent {
+            return;
+        }
+        if is_added && let Some(mut history) = self.histories.take(end) {
+            let start = history.frames.len() - frames.len();
+            for (sum, sample) in history.frames.range_mut(start..).zip(frames) {
+                *sum += sample;
+            }
+            history.senders.push(sender);
+            self.histories.store(end, history);
+            return;
+        }
+
+        let mut history = match self.histories.take(start_sample) {
+            Some(history) if history.channels == channels => history,
+            _ => History {
+                channels,
+                ..Default::default()
+            },
+        };
+
+        history.frames.extend(frames);
+        history.senders = vec![sender];
+        let kept = ((MAX_DELAY_MS / 1000.0 * SAMPLE_RATE) as usize + MAX_BLOCK) * channels;
+        let excess = history.frames.len().saturating_sub(kept);
+        history.frames.drain(..excess);
+
+        self.histories
+            .store(start_sample + frames.len() / channels, history);
+    }
+
+    /// Fill output with what was sent delay frames before start_sample, or silence where nothing was
+    fn play(&self, output: &mut [f32], start_sample: usize, channels: usize, delay: usize) {
+        output.fill(0.0);
+        let frames = output.len() / channels;
+        // how far past start_sample the history runs
+        let mut copy = |history: &History, ahead: usize| {
+            if history.channels != channels {
+                return;
+            }
+            let start = history.frames.len() as isize - ((delay + ahead) * channels) as isize;
+            for (i, sample) in output.iter_mut().enumerate() {
+                let index = start + i as isize;
+                if index >= 0
+                    && let Some(value) = history.frames.get(index as usize)
+                {
+                    *sample = *value;
+                }
+            }
+        };
+
+        // the send may already have been through this block if the receive is also used outside the loop
+        if self
+            .histories
+            .peek(start_sample, |history| copy(history, 0))
+            .is_none()
+        {
+            self.histories
+                .peek(start_sample + frames, |history| copy(history, frames));
+        }
+    }
+}
+
+/// The bus a send or receive is on, by name among the buses of its graph
+struct BusLink {
+    name: Mutex<String>,
+    buses: Mutex<Arc<FeedbackBuses>>,
+    bus: Mutex<Arc<FeedbackBus>>,
+}
+
+impl BusLink {
+    /// On buses of its own until it is adopted by a graph
+    fn new(name: &str) -> Self {
+        let buses = Arc::new(FeedbackBuses::default());
+        Self {
+            name: Mutex::new(name.to_string()),
+            bus: Mutex::new(buses.named(name)),
+            buses: Mutex::new(buses),
+        }
+    }
+
+    fn name(&self) -> String {
+        self.name.lock().clone()
+    }
+
+    fn bus(&self) -> Arc<FeedbackBus> {
+        self.bus.lock().clone()
+    }
+
+    fn rename(&self, name: &str) {
+        *self.name.lock() = name.to_string();
+        *self.bus.lock() = self.buses.lock().named(name);
+    }
+
+    /// Move over to the bus with the same name among the buses
+    fn join(&self, buses: &Arc<FeedbackBuses>) {
+        *self.buses.lock() = buses.clone();
+        self.rename(&self.name());
+    }
+
+    /// A text box for the name of the bus, swapping over to the new bus once changed
+    fn ui(&self, ui: &mut Ui) {
+        let mut name = self.name();
+        let response = ui.add(TextEdit::singleline(&mut name).desired_width(80.0));
+        if response.changed() {
+            self.rename(&name);
+        }
+    }
+}
+
+/// Passes its input straight through, keeping a copy for the receives with the same bus name to play back later.
+/// A loop goes from a receive, through some effects, back into the send, so the graph itself never loops
+pub struct FeedbackSend {
+    bus: BusLink,
+    input: Mutex<Arc<dyn Effect>>,
+    switches: SwitchState,
+}
+
+impl FeedbackSend {
+    pub fn new(name: &str, input: Arc<dyn Effect>) -> Self {
+        Self {
+            bus: BusLink::new(name),
+            input: Mutex::new(input),
+            switches: SwitchState::default(),
+        }
+    }
+
+    pub fn bus_name(&self) -> String {
+        self.bus.name()
+    }
+}
+
+impl Effect for FeedbackSend {
+    fn apply(&self, output: &mut [f32], start_sample: usize, channels: usize) {
+        // the input may come round from a receive on this bus, which reads the history as it was
+        self.input.lock().process(output, start_sample, channels);
+        // a preview of what is upstream isn't what was played, so mustn't come back round
+        if streamstate::is_previewing() {
+            return;
+        }
+        self.bus
+            .bus()
+            .record(self as *const Self as usize, output, start_sample, channels);
+    }
+
+    fn input_count(&self) -> usize {
+        1
+    }
+
+    fn output_count(&self) -> usize {
+        1
+    }
+
+    fn set_input_at_index(&self, index: usize, input: Arc<dyn Effect>) -> Result<(), EffectError> {
+        match index {
+            0 => {
+                *self.input.lock() = input;
+                Ok(())
+            }
+            _ => Err(EffectError::OutOfBounds(index)),
+        }
+    }
+
+    fn get_input_at_index(&self, index: usize) -> Result<Arc<dyn Effect>, EffectError> {
+        match index {
+            0 => Ok(self.input.lock().clone()),
+            _ => Err(EffectError::OutOfBounds(index)),
+        }
+    }
+
+    fn name(&self) -> &str {
+        "Feedback Send"
+    }
+
+    fn effect_id(&self) -> Option<&'static str> {
+        Some("feedback_send")
+    }
+
+    fn settings(&self) -> Vec<(&'static str, String)> {
+        vec![("bus", self.bus_name())]
+    }
+
+    fn set_setting(&self, name: &str, value: &str) -> Result<(), EffectError> {
+        match name {
+            "bus" => self.bus.rename(value),
+            _ => return Err(EffectError::UnknownSetting(name.to_string())),
+        }
+        Ok(())
+    }
+
+    fn data_ui(&self, ui: &mut Ui, _style: &GraphStyle) {
+        self.bus.ui(ui);
+    }
+
+    fn get_waveform_plot_data(&self, sample_plot_data: &mut SamplePlotData, channel: &Channel) {
+        self.input.lock().plot_data(sample_plot_data, channel);
+    }
+
+    fn switch_state(&self) -> Option<&SwitchState> {
+        Some(&self.switches)
+    }
+}
+
+/// Plays back what went into the sends with the same bus name, at least one block later
+pub struct FeedbackReceive {
+    bus: BusLink,
+    delay: Parameter,
+    switches: SwitchState,
+}
+
+impl FeedbackReceive {
+    pub fn new(name: &str, delay_ms: f32) -> Self {
+        Self {
+            bus: BusLink::new(name),
+            delay: Parameter::new("delay", delay_ms, 0.0..=MAX_DELAY_MS),
+            switches: SwitchState::default(),
+        }
+    }
+
+    pub fn bus_name(&self) -> String {
+        self.bus.name()
+    }
+}
+
+impl Effect for FeedbackReceive {
+    fn apply(&self, output: &mut [f32], start_sample: usize, channels: usize) {
+        let frames = output.len() / channels;
+        let delay_ms = self.delay.values(start_sample, 1)[0];
+        // never less than the block, so all of it has already been through the send
+        let delay = ((delay_ms / 1000.0 * SAMPLE_RATE) as usize).max(frames);
+        self.bus.bus().play(output, start_sample, channels, delay);
+    }
+
+    fn input_count(&self) -> usize {
+        self.parameters().len()
+    }
+
+    fn output_count(&self) -> usize {
+        1
+    }
+
+    fn set_input_at_index(&self, index: usize, input: Arc<dyn Effect>) -> Result<(), EffectError> {
+        set_modulation_input(&self.parameters(), index, 0, input)
+    }
+
+    fn get_input_at_index(&self, index: usize) -> Result<Arc<dyn Effect>, EffectError> {
+        get_modulation_input(&self.parameters(), index, 0)
+    }
+
+    fn name(&self) -> &str {
+        "Feedback Receive"
+    }
+
+    fn effect_id(&self) -> Option<&'static str> {
+        Some("feedback_receive")
+    }
+
+    fn settings(&self) -> Vec<(&'static str, String)> {
+        vec![("bus", self.bus_name())]
+    }
+
+    fn set_setting(&self, name: &str, value: &str) -> Result<(), EffectError> {
+        match name {
+            "bus" => self.bus.rename(value),
+            _ => return Err(EffectError::UnknownSetting(name.to_string())),
+        }
+        Ok(())
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![&self.delay]
+    }
+
+    fn data_ui(&self, ui: &mut Ui, _style: &GraphStyle) {
+        self.bus.ui(ui);
+        self.delay.ui(ui);
+    }
+
+    /// What comes back depends on what has been played, which the overview doesn't know
+    fn get_waveform_plot_data(&self, _sample_plot_data: &mut SamplePlotData, _channel: &Channel) {}
+
+    fn switch_state(&self) -> Option<&SwitchState> {
+        Some(&self.switches)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audio::effects::{add::Add, gain::Gain, sinewave::SineWave};
+    use crate::common::dB;
+
+    #[test]
+    fn test_feedback_loop() {
+        // sine + half of what came out a block ago, which goes on into the send
+        let sine: Arc<dyn Effect> = Arc::new(SineWave::new(0.5, 440.0, 0.0));
+        let receive: Arc<dyn Effect> = Arc::new(FeedbackReceive::new("test_feedback_loop", 0.0));
+        let quieter: Arc<dyn Effect> = Arc::new(Gain::new(dB(-6.0), receive.clone()));
+        let add: Arc<dyn Effect> = Arc::new(Add::new(sine.clone(), quieter));
+        let send: Arc<dyn Effect> = Arc::new(FeedbackSend::new("test_feedback_loop", add));
+        let buses = Arc::new(FeedbackBuses::default());
+        buses.adopt(&send);
+        buses.adopt(&receive);
+
+        let block = 64;
+        let mut first = vec![0.0; block * 2];
+        send.apply(&mut first, 0, 2);
+        let mut expected = vec![0.0; block * 2];
+        sine.apply(&mut expected, 0, 2);
+        assert_eq!(first, expected, "nothing has come round yet");
+
+        let mut second = vec![0.0; block * 2];
+        send.apply(&mut second, block, 2);
+        let mut dry = vec![0.0; block * 2];
+        sine.apply(&mut dry, block, 2);
+        let half = dB(-6.0).to_amplitude();
+        for ((s, d), f) in second.iter().zip(dry).zip(first) {
+            assert!((s - (d + f * half)).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_sends_add_up() {
+        let low: Arc<dyn Effect> = Arc::new(SineWave::new(0.5, 220.0, 0.0));
+        let high: Arc<dyn Effect> = Arc::new(SineWave::new(0.25, 880.0, 0.0));
+        let first: Arc<dyn Effect> = Arc::new(FeedbackSend::new("test_sends_add_up", low.clone()));
+        let second: Arc<dyn Effect> =
+            Arc::new(FeedbackSend::new("test_sends_add_up", high.clone()));
+        let receive: Arc<dyn Effect> = Arc::new(FeedbackReceive::new("test_sends_add_up", 0.0));
+        let buses = Arc::new(FeedbackBuses::default());
+        for effect in [&first, &second, &receive] {
+            buses.adopt(effect);
+        }
+
+        let block = 64;
+        let mut output = vec![0.0; block * 2];
+        first.apply(&mut output, 0, 2);
+        second.apply(&mut output, 0, 2);
+        // taking from them again in the same block adds nothing more
+        first.apply(&mut output, 0, 2);
+        second.apply(&mut output, 0, 2);
+
+        let mut played = vec![0.0; block * 2];
+        receive.apply(&mut played, block, 2);
+        let mut expected = vec![0.0; block * 2];
+        low.apply(&mut expected, 0, 2);
+        let mut other = vec![0.0; block * 2];
+        high.apply(&mut other, 0, 2);
+        for ((p, e), o) in played.iter().zip(expected).zip(other) {
+            assert!((p - (e + o)).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_send_taken_from_twice() {
+        // the send is taken from twice in the middle block, as though it fed two effects for a moment
+        let sine: Arc<dyn Effect> = Arc::new(SineWave::new(0.5, 440.0, 0.0));
+        let send: Arc<dyn Effect> = Arc::new(FeedbackSend::new("bus 1", sine.clone()));
+        let receive: Arc<dyn Effect> = Arc::new(FeedbackReceive::new("bus 1", 3.0));
+        let buses = Arc::new(FeedbackBuses::default());
+        buses.adopt(&send);
+        buses.adopt(&receive);
+
+        let block = SAMPLE_RATE as usize / 1000;
+        for (start, times) in [(0, 1), (block, 2), (block * 2, 1)] {
+            for _ in 0..times {
+                send.apply(&mut vec![0.0; block * 2], start, 2);
+            }
+        }
+
+        // three blocks behind is still the first
+        let mut played = vec![0.0; block * 2];
+        receive.apply(&mut played, block * 3, 2);
+        let mut expected = vec![0.0; block * 2];
+        sine.apply(&mut expected, 0, 2);
+        for (p, e) in played.iter().zip(expected) {
+            assert!((p - e).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_buses_kept_apart() {
+        // the same name in another graph is another bus
+        let sine: Arc<dyn Effect> = Arc::new(SineWave::new(0.5, 440.0, 0.0));
+        let send: Arc<dyn Effect> = Arc::new(FeedbackSend::new("bus 1", sine));
+        let receive: Arc<dyn Effect> = Arc::new(FeedbackReceive::new("bus 1", 0.0));
+        Arc::new(FeedbackBuses::default()).adopt(&send);
+        Arc::new(FeedbackBuses::default()).adopt(&receive);
+
+        let block = 64;
+        send.apply(&mut vec![0.0; block * 2], 0, 2);
+        let mut played = vec![1.0; block * 2];
+        receive.apply(&mut played, block, 2);
+        assert!(played.iter().all(|s| *s == 0.0));
+    }
+}
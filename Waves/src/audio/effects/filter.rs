@@ -0,0 +1,237 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use eframe::egui::mutex::Mutex;
+use eframe::egui::{ComboBox, Ui};
+
+use crate::audio::effects::{Effect, EffectError, parse_setting};
+use crate::audio::parameter::{Parameter, get_modulation_input, set_modulation_input};
+use crate::audio::streamstate::StreamState;
+use crate::audio::switches::SwitchState;
+use crate::common::mipmapchannel::SamplePlotData;
+use crate::common::{Channel, SAMPLE_RATE};
+use crate::ui::nodegraph::GraphStyle;
+
+/// How many samples before a relocation we run the filter over to get it into the right state
+const PRE_ROLL: usize = 2048;
+
+#[allow(clippy::enum_variant_names)]
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum FilterMode {
+    LowPass,
+    BandPass,
+    HighPass,
+}
+
+impl FilterMode {
+    pub const ALL: [FilterMode; 3] = [
+        FilterMode::LowPass,
+        FilterMode::BandPass,
+        FilterMode::HighPass,
+    ];
+}
+
+/// The two integrator states of the filter for one channel
+#[derive(Clone, Copy, Default)]
+struct SvfState {
+    ic1eq: f32,
+    ic2eq: f32,
+}
+
+/// A resonant state variable filter (Andrew Simper's trapezoidal version, which stays stable when the cutoff moves quickly)
+pub struct Filter {
+    mode: Mutex<FilterMode>,
+    cutoff: Parameter,
+    resonance: Parameter,
+    input: Mutex<Arc<dyn Effect>>,
+    states: StreamState<Vec<SvfState>>,
+    switches: SwitchState,
+}
+
+impl Filter {
+    pub fn new(mode: FilterMode, cutoff: f32, resonance: f32, input: Arc<dyn Effect>) -> Self {
+        Self {
+            mode: Mutex::new(mode),
+            cutoff: Parameter::new("cutoff", cutoff, 20.0..=20000.0).logarithmic(true),
+            resonance: Parameter::new("resonance", resonance, 0.5..=20.0).logarithmic(true),
+            input: Mutex::new(input),
+            states: StreamState::default(),
+            switches: SwitchState::default(),
+        }
+    }
+
+    pub fn mode(&self) -> FilterMode {
+        *self.mode.lock()
+    }
+
+    /// Filter the frames in place, carrying on from the given state
+    fn filter(
+        &self,
+        states: &mut [SvfState],
+        frames: &mut [f32],
+        start_sample: usize,
+        channels: usize,
+    ) {
+        let count = frames.len() / channels;
+        let cutoffs = self.cutoff.values(start_sample, count);
+        let resonances = self.resonance.values(start_sample, count);
+        let mode = self.mode();
+
+        for ((frame, cutoff), q) in frames.chunks_mut(channels).zip(cutoffs).zip(resonances) {
+            // keep well clear of nyquist where tan blows up
+            let g = (PI * cutoff.min(SAMPLE_RATE * 0.49) / SAMPLE_RATE).tan();
+            let k = 1.0 / q;
+            let a1 = 1.0 / (1.0 + g * (g + k));
+            let a2 = g * a1;
+            let a3 = g * a2;
+
+            for (f, s) in frame.iter_mut().zip(states.iter_mut()) {
+                let v0 = *f;
+                let v3 = v0 - s.ic2eq;
+                let v1 = a1 * s.ic1eq + a2 * v3;
+                let v2 = s.ic2eq + a2 * s.ic1eq + a3 * v3;
+                s.ic1eq = 2.0 * v1 - s.ic1eq;
+                s.ic2eq = 2.0 * v2 - s.ic2eq;
+
+                *f = match mode {
+                    FilterMode::LowPass => v2,
+                    FilterMode::BandPass => v1,
+                    FilterMode::HighPass => v0 - k * v1 - v2,
+                };
+            }
+        }
+    }
+}
+
+impl Effect for Filter {
+    fn apply(&self, output: &mut [f32], start_sample: usize, channels: usize) {
+        let mut states = match self.states.take(start_sample) {
+            Some(states) if states.len() == channels => states,
+            _ => {
+                // We have been moved so run over what came just before to settle down
+                let pre_roll = PRE_ROLL.min(start_sample);
+                let mut history = vec![0.0; pre_roll * channels];
+                self.input
+                    .lock()
+                    .process(&mut history, start_sample - pre_roll, channels);
+
+                let mut states = vec![SvfState::default(); channels];
+                self.filter(&mut states, &mut history, start_sample - pre_roll, channels);
+                states
+            }
+        };
+
+        self.input.lock().process(output, start_sample, channels);
+        self.filter(&mut states, output, start_sample, channels);
+
+        self.states
+            .store(start_sample + output.len() / channels, states);
+    }
+
+    fn input_count(&self) -> usize {
+        1 + self.parameters().len()
+    }
+
+    fn output_count(&self) -> usize {
+        1
+    }
+
+    fn set_input_at_index(&self, index: usize, input: Arc<dyn Effect>) -> Result<(), EffectError> {
+        match index {
+            0 => {
+                *self.input.lock() = input;
+                Ok(())
+            }
+            _ => set_modulation_input(&self.parameters(), index, 1, input),
+        }
+    }
+
+    fn get_input_at_index(&self, index: usize) -> Result<Arc<dyn Effect>, EffectError> {
+        match index {
+            0 => Ok(self.input.lock().clone()),
+            _ => get_modulation_input(&self.parameters(), index, 1),
+        }
+    }
+
+    fn name(&self) -> &str {
+        "Filter"
+    }
+
+    fn effect_id(&self) -> Option<&'static str> {
+        Some("filter")
+    }
+
+    fn settings(&self) -> Vec<(&'static str, String)> {
+        vec![("mode", format!("{:?}", *self.mode.lock()))]
+    }
+
+    fn set_setting(&self, name: &str, value: &str) -> Result<(), EffectError> {
+        match name {
+            "mode" => *self.mode.lock() = parse_setting(&FilterMode::ALL, name, value)?,
+            _ => return Err(EffectError::UnknownSetting(name.to_string())),
+        }
+        Ok(())
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![&self.cutoff, &self.resonance]
+    }
+
+    fn data_ui(&self, ui: &mut Ui, _style: &GraphStyle) {
+        {
+            let mut mode = self.mode.lock();
+            ComboBox::from_id_salt(ui.id().with("filter_mode"))
+                .selected_text(format!("{:?}", *mode))
+                .show_ui(ui, |ui| {
+                    for m in FilterMode::ALL {
+                        ui.selectable_value(&mut *mode, m, format!("{m:?}"));
+                    }
+                });
+        }
+
+        self.cutoff.ui(ui);
+        self.resonance.ui(ui);
+    }
+
+    /// The overview can't see frequencies, so this just shows the input
+    fn get_waveform_plot_data(&self, sample_plot_data: &mut SamplePlotData, channel: &Channel) {
+        self.input.lock().plot_data(sample_plot_data, channel);
+    }
+
+    fn switch_state(&self) -> Option<&SwitchState> {
+        Some(&self.switches)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audio::effects::oscillator::{Oscillator, Waveform};
+    use crate::audio::effects::zero::Zero;
+
+    fn rms_through(mode: FilterMode, frequency: f32) -> f32 {
+        let sine = Arc::new(Oscillator::new(
+            Waveform::Sine,
+            1.0,
+            frequency,
+            Arc::new(Zero),
+        ));
+        let filter = Filter::new(mode, 1000.0, 0.707, sine);
+
+        let mut output = vec![0.0; 4800];
+        filter.apply(&mut output, 48000, 1);
+        (output.iter().map(|f| f * f).sum::<f32>() / output.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn test_low_pass() {
+        assert!(rms_through(FilterMode::LowPass, 100.0) > 0.65);
+        assert!(rms_through(FilterMode::LowPass, 10000.0) < 0.02);
+    }
+
+    #[test]
+    fn test_high_pass() {
+        assert!(rms_through(FilterMode::HighPass, 100.0) < 0.02);
+        assert!(rms_through(FilterMode::HighPass, 10000.0) > 0.65);
+    }
+}
@@ -0,0 +1,199 @@
+use std::sync::Arc;
+
+use eframe::egui::Ui;
+use eframe::egui::mutex::Mutex;
+
+use crate::audio::delayline::DelayLine;
+use crate::audio::effects::lfo::phase_at;
+use crate::audio::effects::{Effect, EffectError};
+use crate::audio::parameter::{Parameter, get_modulation_input, set_modulation_input};
+use crate::audio::streamstate::StreamState;
+use crate::audio::switches::SwitchState;
+use crate::common::mipmapchannel::SamplePlotData;
+use crate::common::{Channel, SAMPLE_RATE};
+use crate::ui::nodegraph::GraphStyle;
+
+const MAX_DELAY_MS: f32 = 10.0;
+const MAX_DEPTH_MS: f32 = 5.0;
+
+/// How many samples before a relocation we run over to let the feedback build back up
+const PRE_ROLL: usize = 4800;
+
+#[derive(Clone)]
+struct FlangerState {
+    line: DelayLine,
+    phase: f32,
+}
+
+/// A very short sweeping delay fed back into itself, giving the jet plane whoosh
+pub struct Flanger {
+    rate: Parameter,
+    depth: Parameter,
+    delay: Parameter,
+    feedback: Parameter,
+    mix: Parameter,
+    input: Mutex<Arc<dyn Effect>>,
+    states: StreamState<FlangerState>,
+    switches: SwitchState,
+}
+
+impl Flanger {
+    pub fn new(rate: f32, depth_ms: f32, feedback: f32, input: Arc<dyn Effect>) -> Self {
+        Self {
+            rate: Parameter::new("rate", rate, 0.01..=5.0).logarithmic(true),
+            depth: Parameter::new("depth", depth_ms, 0.0..=MAX_DEPTH_MS),
+            delay: Parameter::new("delay", 1.0, 0.1..=MAX_DELAY_MS),
+            feedback: Parameter::new("feedback", feedback, -0.95..=0.95),
+            mix: Parameter::new("mix", 0.5, 0.0..=1.0),
+            input: Mutex::new(input),
+            states: StreamState::default(),
+            switches: SwitchState::default(),
+        }
+    }
+
+    fn line_length() -> usize {
+        ((MAX_DELAY_MS + MAX_DEPTH_MS) / 1000.0 * SAMPLE_RATE) as usize + 4
+    }
+
+    /// Run the flanger over the frames in place, carrying on from the given state
+    fn process(
+        &self,
+        state: &mut FlangerState,
+        frames: &mut [f32],
+        start_sample: usize,
+        channels: usize,
+    ) {
+        let count = frames.len() / channels;
+        let rates = self.rate.values(start_sample, count);
+        let depths = self.depth.values(start_sample, count);
+        let delays = self.delay.values(start_sample, count);
+        let feedbacks = self.feedback.values(start_sample, count);
+        let mixes = self.mix.values(start_sample, count);
+
+        let mut fed = vec![0.0; channels];
+        for (i, frame) in frames.chunks_mut(channels).enumerate() {
+            for (c, (f, fed)) in frame.iter_mut().zip(fed.iter_mut()).enumerate() {
+                // triangle sweep between 0 and 1, the channels a quarter apart
+                let phase = (state.phase + c as f32 * 0.25).fract();
+                let sweep = 1.0 - (2.0 * phase - 1.0).abs();
+                let delay_ms = delays[i] + depths[i] * sweep;
+
+                let delayed = state.line.read(c, delay_ms / 1000.0 * SAMPLE_RATE);
+                *fed = *f + feedbacks[i] * delayed;
+                *f = *f * (1.0 - mixes[i]) + delayed * mixes[i];
+            }
+
+            state.line.push(&fed);
+            state.phase = (state.phase + rates[i] / SAMPLE_RATE).fract();
+        }
+    }
+}
+
+impl Effect for Flanger {
+    fn apply(&self, output: &mut [f32], start_sample: usize, channels: usize) {
+        let mut state = match self.states.take(start_sample) {
+            Some(state) if state.line.channels() == channels => state,
+            _ => {
+                // We have been moved so run over what came just before to settle down
+                let pre_roll = PRE_ROLL.min(start_sample);
+                let mut history = vec![0.0; pre_roll * channels];
+                self.input
+                    .lock()
+                    .process(&mut history, start_sample - pre_roll, channels);
+
+                let mut state = FlangerState {
+                    line: DelayLine::new(Self::line_length(), channels),
+                    phase: phase_at(self.rate.get(), start_sample - pre_roll),
+                };
+                self.process(&mut state, &mut history, start_sample - pre_roll, channels);
+                state
+            }
+        };
+
+        self.input.lock().process(output, start_sample, channels);
+        self.process(&mut state, output, start_sample, channels);
+
+        self.states
+            .store(start_sample + output.len() / channels, state);
+    }
+
+    fn input_count(&self) -> usize {
+        1 + self.parameters().len()
+    }
+
+    fn output_count(&self) -> usize {
+        1
+    }
+
+    fn set_input_at_index(&self, index: usize, input: Arc<dyn Effect>) -> Result<(), EffectError> {
+        match index {
+            0 => {
+                *self.input.lock() = input;
+                Ok(())
+            }
+            _ => set_modulation_input(&self.parameters(), index, 1, input),
+        }
+    }
+
+    fn get_input_at_index(&self, index: usize) -> Result<Arc<dyn Effect>, EffectError> {
+        match index {
+            0 => Ok(self.input.lock().clone()),
+            _ => get_modulation_input(&self.parameters(), index, 1),
+        }
+    }
+
+    fn name(&self) -> &str {
+        "Flanger"
+    }
+
+    fn effect_id(&self) -> Option<&'static str> {
+        Some("flanger")
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![
+            &self.rate,
+            &self.depth,
+            &self.delay,
+            &self.feedback,
+            &self.mix,
+        ]
+    }
+
+    fn data_ui(&self, ui: &mut Ui, _style: &GraphStyle) {
+        for parameter in self.parameters() {
+            parameter.ui(ui);
+        }
+    }
+
+    /// The delays are too short to see in the overview, so this just shows the input
+    fn get_waveform_plot_data(&self, sample_plot_data: &mut SamplePlotData, channel: &Channel) {
+        self.input.lock().plot_data(sample_plot_data, channel);
+    }
+
+    fn switch_state(&self) -> Option<&SwitchState> {
+        Some(&self.switches)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audio::effects::sinewave::SineWave;
+    use crate::audio::effects::testing::{assert_blocks_carry_on, assert_relocation_matches};
+
+    fn flanger() -> Flanger {
+        let sine = Arc::new(SineWave::new(0.5, 220.0, 0.0));
+        Flanger::new(0.5, 2.0, 0.7, sine)
+    }
+
+    #[test]
+    fn test_blocks_carry_on() {
+        assert_blocks_carry_on(flanger, 10000, 1024, 2, 0.0);
+    }
+
+    #[test]
+    fn test_relocation_matches() {
+        assert_relocation_matches(flanger, 15000, 256, 1e-3);
+    }
+}
@@ -1,44 +1,53 @@
 use std::sync::Arc;
 
+use eframe::egui::Ui;
 use eframe::egui::mutex::Mutex;
-use eframe::egui::{Slider, Ui};
 
+use crate::audio::parameter::{Parameter, get_modulation_input, set_modulation_input};
 use crate::common::dB;
 use crate::ui::eqwidget::EQWidget;
 use crate::ui::nodegraph::GraphStyle;
 
 use crate::audio::effects::{Effect, EffectError};
+use crate::audio::switches::SwitchState;
 
 /// Increase/Decrease the volume by the gain in dB.
 pub struct Gain {
     // State in
-    gain: Mutex<dB>,
+    gain: Parameter,
     input: Mutex<Arc<dyn Effect>>,
+    switches: SwitchState,
 }
 
 impl Gain {
     pub fn new(gain: dB, input: Arc<dyn Effect>) -> Self {
         Self {
-            gain: Mutex::new(gain),
+            gain: Parameter::new("gain", gain.0, -18.0..=6.0),
             input: Mutex::new(input),
+            switches: SwitchState::default(),
         }
     }
 
     pub fn gain(&self) -> dB {
-        *self.gain.lock()
+        dB(self.gain.get())
     }
 }
 
 impl Effect for Gain {
     fn apply(&self, output: &mut [f32], start_sample: usize, channels: usize) {
-        self.input.lock().apply(output, start_sample, channels);
-        for j in output {
-            *j *= self.gain.lock().to_amplitude();
+        self.input.lock().process(output, start_sample, channels);
+
+        let gains = self.gain.values(start_sample, output.len() / channels);
+        for (frame, gain) in output.chunks_mut(channels).zip(gains) {
+            let amplitude = dB(gain).to_amplitude();
+            for j in frame {
+                *j *= amplitude;
+            }
         }
     }
 
     fn input_count(&self) -> usize {
-        1
+        1 + self.parameters().len()
     }
 
     fn output_count(&self) -> usize {
@@ -51,14 +60,14 @@ impl Effect for Gain {
                 *self.input.lock() = input;
                 Ok(())
             }
-            _ => Err(EffectError::OutOfBounds(index)),
+            _ => set_modulation_input(&self.parameters(), index, 1, input),
         }
     }
 
     fn get_input_at_index(&self, index: usize) -> Result<Arc<dyn Effect>, EffectError> {
         match index {
             0 => Ok(self.input.lock().clone()),
-            _ => Err(EffectError::OutOfBounds(index)),
+            _ => get_modulation_input(&self.parameters(), index, 1),
         }
     }
 
@@ -66,8 +75,16 @@ impl Effect for Gain {
         "Gain"
     }
 
+    fn effect_id(&self) -> Option<&'static str> {
+        Some("gain")
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![&self.gain]
+    }
+
     fn data_ui(&self, ui: &mut Ui, _style: &GraphStyle) {
-        ui.add(Slider::new(&mut self.gain.lock().0, -18.0..=6.0));
+        self.gain.ui(ui);
     }
 
     fn get_waveform_plot_data(
@@ -75,11 +92,9 @@ impl Effect for Gain {
         sample_plot_data: &mut crate::common::mipmapchannel::SamplePlotData,
         channel: &crate::common::Channel,
     ) {
-        self.input
-            .lock()
-            .get_waveform_plot_data(sample_plot_data, channel);
+        self.input.lock().plot_data(sample_plot_data, channel);
 
-        let gain = self.gain.lock().to_amplitude();
+        let gain = self.gain().to_amplitude();
 
         for v in &mut sample_plot_data.data {
             for j in v {
@@ -87,4 +102,8 @@ impl Effect for Gain {
             }
         }
     }
+
+    fn switch_state(&self) -> Option<&SwitchState> {
+        Some(&self.switches)
+    }
 }
@@ -0,0 +1,261 @@
+use std::sync::Arc;
+
+use eframe::egui::mutex::Mutex;
+use eframe::egui::{CollapsingHeader, Ui};
+
+use crate::audio::effects::{Effect, EffectError, output::Output, zero::Zero};
+use crate::audio::parameter::{Parameter, get_modulation_input, set_modulation_input};
+use crate::audio::switches::SwitchState;
+use crate::ui::nodegraph::GraphStyle;
+
+/// Stands in for one of a group's inputs inside the group, passing on whatever that input is connected to.
+/// It has no inputs of its own so the connection can only be changed from outside the group
+pub struct GroupInput {
+    name: String,
+    source: Mutex<Arc<dyn Effect>>,
+}
+
+impl GroupInput {
+    pub fn new(name: String, source: Arc<dyn Effect>) -> Self {
+        Self {
+            name,
+            source: Mutex::new(source),
+        }
+    }
+
+    pub fn source(&self) -> Arc<dyn Effect> {
+        self.source.lock().clone()
+    }
+
+    pub fn set_source(&self, source: Arc<dyn Effect>) {
+        *self.source.lock() = source;
+    }
+}
+
+impl Effect for GroupInput {
+    fn apply(&self, output: &mut [f32], start_sample: usize, channels: usize) {
+        self.source().process(output, start_sample, channels);
+    }
+
+    fn input_count(&self) -> usize {
+        0
+    }
+
+    fn output_count(&self) -> usize {
+        1
+    }
+
+    fn set_input_at_index(&self, index: usize, _input: Arc<dyn Effect>) -> Result<(), EffectError> {
+        Err(EffectError::OutOfBounds(index))
+    }
+
+    fn get_input_at_index(&self, index: usize) -> Result<Arc<dyn Effect>, EffectError> {
+        Err(EffectError::OutOfBounds(index))
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn get_waveform_plot_data(
+        &self,
+        sample_plot_data: &mut crate::common::mipmapchannel::SamplePlotData,
+        channel: &crate::common::Channel,
+    ) {
+        self.source().plot_data(sample_plot_data, channel);
+    }
+}
+
+/// Several effects collapsed into one node, with inputs, an output and some of their parameters brought out
+pub struct Group {
+    name: String,
+    inputs: Vec<Arc<GroupInput>>,
+    effects: Vec<Arc<dyn Effect>>,
+    /// What the group puts out, which is also the output node of the graph inside the group
+    output: Arc<Output>,
+    /// (effect, parameter) indices of the parameters shown on the group's node, in order
+    exposed: Mutex<Vec<(usize, usize)>>,
+    switches: SwitchState,
+}
+
+impl Group {
+    pub fn new(
+        name: String,
+        inputs: Vec<Arc<GroupInput>>,
+        effects: Vec<Arc<dyn Effect>>,
+        output: Arc<dyn Effect>,
+        exposed: Vec<(usize, usize)>,
+    ) -> Self {
+        Self {
+            name,
+            inputs,
+            effects,
+            output: Arc::new(Output::new(output)),
+            exposed: Mutex::new(exposed),
+            switches: SwitchState::default(),
+        }
+    }
+
+    pub fn inputs(&self) -> &[Arc<GroupInput>] {
+        &self.inputs
+    }
+
+    pub fn effects(&self) -> &[Arc<dyn Effect>] {
+        &self.effects
+    }
+
+    pub fn output(&self) -> Arc<Output> {
+        self.output.clone()
+    }
+
+    pub fn exposed(&self) -> Vec<(usize, usize)> {
+        self.exposed.lock().clone()
+    }
+
+    /// Show or hide one of the effects' parameters on the group.
+    /// Hiding it takes off any modulation it had from outside the group
+    pub fn set_exposed(&self, effect: usize, parameter: usize, exposed: bool) {
+        let mut list = self.exposed.lock();
+        let position = list.iter().position(|&e| e == (effect, parameter));
+        match (position, exposed) {
+            (None, true) => list.push((effect, parameter)),
+            (Some(i), false) => {
+                list.remove(i);
+                if let Some(parameter) = self
+                    .effects
+                    .get(effect)
+                    .and_then(|e| e.parameters().get(parameter).copied())
+                {
+                    parameter.set_modulation(Arc::new(Zero));
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Effect for Group {
+    fn apply(&self, output: &mut [f32], start_sample: usize, channels: usize) {
+        self.output.apply(output, start_sample, channels);
+    }
+
+    fn input_count(&self) -> usize {
+        self.inputs.len() + self.parameters().len()
+    }
+
+    fn output_count(&self) -> usize {
+        1
+    }
+
+    fn set_input_at_index(&self, index: usize, input: Arc<dyn Effect>) -> Result<(), EffectError> {
+        match self.inputs.get(index) {
+            Some(group_input) => {
+                group_input.set_source(input);
+                Ok(())
+            }
+            None => set_modulation_input(&self.parameters(), index, self.inputs.len(), input),
+        }
+    }
+
+    fn get_input_at_index(&self, index: usize) -> Result<Arc<dyn Effect>, EffectError> {
+        match self.inputs.get(index) {
+            Some(group_input) => Ok(group_input.source()),
+            None => get_modulation_input(&self.parameters(), index, self.inputs.len()),
+        }
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn input_name(&self, index: usize) -> String {
+        match self.inputs.get(index) {
+            Some(group_input) => group_input.name().to_string(),
+            None => {
+                let parameters = self.parameters();
+                match parameters.get(index - self.inputs.len()) {
+                    Some(parameter) => format!("{} mod", parameter.name()),
+                    None => "input".to_string(),
+                }
+            }
+        }
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        let exposed = self.exposed();
+        exposed
+            .into_iter()
+            .filter_map(|(e, p)| self.effects.get(e)?.parameters().get(p).copied())
+            .collect()
+    }
+
+    fn data_ui(&self, ui: &mut Ui, _style: &GraphStyle) {
+        for parameter in self.parameters() {
+            parameter.ui(ui);
+        }
+
+        CollapsingHeader::new("Parameters")
+            .id_salt(ui.id().with("group_parameters"))
+            .show(ui, |ui| {
+                let exposed = self.exposed();
+                for (e, effect) in self.effects.iter().enumerate() {
+                    for (p, parameter) in effect.parameters().iter().enumerate() {
+                        let mut is_exposed = exposed.contains(&(e, p));
+                        let label = format!("{} {}", effect.name(), parameter.name());
+                        if ui.checkbox(&mut is_exposed, label).changed() {
+                            self.set_exposed(e, p, is_exposed);
+                        }
+                    }
+                }
+            });
+    }
+
+    fn get_waveform_plot_data(
+        &self,
+        sample_plot_data: &mut crate::common::mipmapchannel::SamplePlotData,
+        channel: &crate::common::Channel,
+    ) {
+        self.output.plot_data(sample_plot_data, channel);
+    }
+
+    fn switch_state(&self) -> Option<&SwitchState> {
+        Some(&self.switches)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audio::effects::{gain::Gain, sinewave::SineWave};
+    use crate::common::dB;
+
+    #[test]
+    fn test_group_plays_its_insides() {
+        let sine: Arc<dyn Effect> = Arc::new(SineWave::new(0.5, 440.0, 0.0));
+        let input = Arc::new(GroupInput::new("in".to_string(), Arc::new(Zero)));
+        let gain: Arc<dyn Effect> = Arc::new(Gain::new(dB(-6.0), input.clone()));
+        let group = Group::new(
+            "Quieter".to_string(),
+            vec![input],
+            vec![gain.clone()],
+            gain.clone(),
+            vec![],
+        );
+        assert_eq!(group.input_count(), 1);
+
+        group.set_input_at_index(0, sine.clone()).unwrap();
+        let mut grouped = vec![0.0; 256];
+        group.apply(&mut grouped, 0, 2);
+        let mut expected = vec![0.0; 256];
+        Gain::new(dB(-6.0), sine).apply(&mut expected, 0, 2);
+        assert_eq!(grouped, expected);
+
+        // bringing out the gain gives the group its modulation input too
+        group.set_exposed(0, 0, true);
+        assert_eq!(group.input_count(), 2);
+        assert_eq!(group.parameters()[0].get(), -6.0);
+        assert_eq!(group.input_name(1), "gain mod");
+        group.set_exposed(0, 0, false);
+        assert_eq!(group.input_count(), 1);
+    }
+}
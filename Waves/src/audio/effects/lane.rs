@@ -0,0 +1,202 @@
+use std::sync::Arc;
+
+use eframe::egui::Ui;
+use eframe::egui::mutex::Mutex;
+
+use crate::audio::effects::{Effect, EffectError};
+use crate::common::Channel;
+use crate::common::mipmapchannel::SamplePlotData;
+use crate::common::track::Track;
+use crate::ui::nodegraph::GraphStyle;
+
+/// A row of the timeline, playing all the clips laid out along it
+pub struct Lane {
+    name: String,
+    clips: Mutex<Vec<Arc<Track>>>,
+}
+
+impl Lane {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            clips: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn clips(&self) -> Vec<Arc<Track>> {
+        self.clips.lock().clone()
+    }
+
+    pub fn contains(&self, clip: &Arc<Track>) -> bool {
+        self.clips.lock().iter().any(|c| Arc::ptr_eq(c, clip))
+    }
+
+    pub fn add_clip(&self, clip: Arc<Track>) {
+        self.clips.lock().push(clip);
+    }
+
+    pub fn remove_clip(&self, clip: &Arc<Track>) {
+        self.clips.lock().retain(|c| !Arc::ptr_eq(c, clip));
+    }
+
+    /// Cut the clip in two at the timeline sample, giving back the new second half.
+    /// Nothing happens if the clip isn't playing its first time through at that point.
+    pub fn split(&self, clip: &Arc<Track>, at: usize) -> Option<Arc<Track>> {
+        let mut trim = clip.trim();
+        let length = clip.length() as usize;
+        let (start, end) = trim.region(length);
+
+        let position = at.checked_sub(trim.offset)?;
+        if position == 0 || position >= end - start || !self.contains(clip) {
+            return None;
+        }
+
+        let second = clip.duplicate();
+        let mut second_trim = trim;
+        second_trim.offset = at;
+        second_trim.start = start + position;
+        second_trim.fade_in = 0;
+        second_trim.looping = false;
+        second.set_trim(second_trim);
+
+        trim.end = Some(start + position);
+        trim.fade_out = 0;
+        trim.looping = false;
+        clip.set_trim(trim);
+
+        let second = Arc::new(second);
+        self.add_clip(second.clone());
+        Some(second)
+    }
+
+    /// Copy the clip to straight after itself, giving back the copy
+    pub fn duplicate(&self, clip: &Arc<Track>) -> Arc<Track> {
+        let copy = clip.duplicate();
+        let mut trim = copy.trim();
+        let (start, end) = trim.region(clip.length() as usize);
+        trim.offset += end - start;
+        copy.set_trim(trim);
+
+        let copy = Arc::new(copy);
+        self.add_clip(copy.clone());
+        copy
+    }
+}
+
+impl Effect for Lane {
+    fn apply(&self, output: &mut [f32], start_sample: usize, channels: usize) {
+        output.fill(0.0);
+
+        let mut clip_output = vec![0.0; output.len()];
+        for clip in self.clips() {
+            clip.apply(&mut clip_output, start_sample, channels);
+            for (o, c) in output.iter_mut().zip(&clip_output) {
+                *o += c;
+            }
+        }
+    }
+
+    fn input_count(&self) -> usize {
+        0
+    }
+
+    fn output_count(&self) -> usize {
+        1
+    }
+
+    fn set_input_at_index(&self, index: usize, _input: Arc<dyn Effect>) -> Result<(), EffectError> {
+        Err(EffectError::OutOfBounds(index))
+    }
+
+    fn get_input_at_index(&self, index: usize) -> Result<Arc<dyn Effect>, EffectError> {
+        Err(EffectError::OutOfBounds(index))
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn data_ui(&self, ui: &mut Ui, _style: &GraphStyle) {
+        ui.label(format!("{} clips", self.clips.lock().len()));
+    }
+
+    fn get_waveform_plot_data(&self, sample_plot_data: &mut SamplePlotData, channel: &Channel) {
+        for v in &mut sample_plot_data.data {
+            v.fill(0.0);
+        }
+
+        for clip in self.clips() {
+            let mut clip_data = SamplePlotData::new(
+                sample_plot_data.step,
+                sample_plot_data.start_sample,
+                sample_plot_data.data[0].len(),
+            );
+            clip.get_waveform_plot_data(&mut clip_data, channel);
+
+            sample_plot_data.is_min_max = clip_data.is_min_max;
+            sample_plot_data.mix(&clip_data, 1.0, 1.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use symphonia::core::codecs::CodecParameters;
+
+    use super::*;
+    use crate::common::mipmapchannel::MipMapChannel;
+
+    fn ramp_clip(length: usize) -> Arc<Track> {
+        let mut parameters = CodecParameters::new();
+        parameters
+            .with_n_frames(length as u64)
+            .with_sample_rate(48000);
+
+        let data = (0..length).map(|i| i as f32).collect::<Vec<_>>();
+        Arc::new(Track::new(
+            None,
+            parameters,
+            MipMapChannel::new(data.clone(), 5),
+            MipMapChannel::new(data, 5),
+        ))
+    }
+
+    fn render(lane: &Lane) -> Vec<f32> {
+        let mut output = vec![0.0; 512];
+        lane.apply(&mut output, 0, 2);
+        output
+    }
+
+    #[test]
+    fn test_split_sounds_the_same() {
+        let lane = Lane::new("lane".to_string());
+        let clip = ramp_clip(200);
+        lane.add_clip(clip.clone());
+        let before = render(&lane);
+
+        let second = lane.split(&clip, 120).unwrap();
+        assert_eq!(lane.clips().len(), 2);
+        assert_eq!(second.trim().offset, 120);
+        assert_eq!(render(&lane), before);
+
+        // can't split where the clip isn't
+        assert!(lane.split(&second, 300).is_none());
+    }
+
+    #[test]
+    fn test_duplicate_follows_on() {
+        let lane = Lane::new("lane".to_string());
+        let clip = ramp_clip(100);
+        lane.add_clip(clip.clone());
+
+        let copy = lane.duplicate(&clip);
+        let output = render(&lane);
+
+        // frames are stereo so the copy starts playing the ramp again at frame 100
+        assert_eq!(copy.trim().offset, 100);
+        assert_eq!(output[2 * 99], 99.0);
+        assert_eq!(output[2 * 100], 0.0);
+        assert_eq!(output[2 * 150], 50.0);
+        assert_eq!(output[2 * 200], 0.0);
+    }
+}
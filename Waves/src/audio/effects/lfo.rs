@@ -0,0 +1,244 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use eframe::egui::mutex::Mutex;
+use eframe::egui::{Checkbox, ComboBox, Ui};
+
+use crate::audio::effects::{Effect, EffectError, parse_setting};
+use crate::audio::parameter::{Parameter, get_modulation_input, set_modulation_input};
+use crate::audio::port::PortType;
+use crate::audio::streamstate::StreamState;
+use crate::audio::switches::SwitchState;
+use crate::common::mipmapchannel::SamplePlotData;
+use crate::common::{Channel, SAMPLE_RATE};
+use crate::ui::nodegraph::GraphStyle;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum LfoShape {
+    Sine,
+    Triangle,
+    Saw,
+    Square,
+    SampleAndHold,
+}
+
+impl LfoShape {
+    pub const ALL: [LfoShape; 5] = [
+        LfoShape::Sine,
+        LfoShape::Triangle,
+        LfoShape::Saw,
+        LfoShape::Square,
+        LfoShape::SampleAndHold,
+    ];
+
+    /// The value at phase t between 0 and 1, starting from zero and rising where it makes sense
+    /// (sample and hold has no shape so gives zero)
+    fn value(self, t: f32) -> f32 {
+        match self {
+            LfoShape::Sine => (2.0 * PI * t).sin(),
+            LfoShape::Triangle => match t {
+                t if t < 0.25 => 4.0 * t,
+                t if t < 0.75 => 2.0 - 4.0 * t,
+                t => 4.0 * t - 4.0,
+            },
+            LfoShape::Saw => 2.0 * t - 1.0,
+            LfoShape::Square => match t < 0.5 {
+                true => 1.0,
+                false => -1.0,
+            },
+            LfoShape::SampleAndHold => 0.0,
+        }
+    }
+}
+
+/// Where something cycling at `rate` Hz would be in its cycle at this sample if the rate had never moved
+pub fn phase_at(rate: f32, sample: usize) -> f32 {
+    (rate as f64 * sample as f64 / SAMPLE_RATE as f64).fract() as f32
+}
+
+#[derive(Clone)]
+struct LfoState {
+    phase: f32,
+    held: f32,
+    rng: u32,
+}
+
+impl LfoState {
+    fn new(phase: f32, start_sample: usize) -> Self {
+        let mut s = Self {
+            phase,
+            held: 0.0,
+            rng: (start_sample as u32).wrapping_mul(2654435761) | 1,
+        };
+        s.hold_new_value();
+        s
+    }
+
+    fn hold_new_value(&mut self) {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.held = (self.rng as f32 / u32::MAX as f32) * 2.0 - 1.0;
+    }
+}
+
+/// A low frequency oscillator to plug into the parameters of other effects.
+/// Its output goes between -1 and 1, or 0 and 1 when unipolar.
+pub struct Lfo {
+    shape: Mutex<LfoShape>,
+    rate: Parameter,
+    unipolar: Mutex<bool>,
+    states: StreamState<LfoState>,
+    switches: SwitchState,
+}
+
+impl Lfo {
+    pub fn new(shape: LfoShape, rate: f32) -> Self {
+        Self {
+            shape: Mutex::new(shape),
+            rate: Parameter::new("rate", rate, 0.01..=20.0).logarithmic(true),
+            unipolar: Mutex::new(false),
+            states: StreamState::default(),
+            switches: SwitchState::default(),
+        }
+    }
+
+    pub fn shape(&self) -> LfoShape {
+        *self.shape.lock()
+    }
+
+    fn phase_at(&self, sample: usize) -> f32 {
+        phase_at(self.rate.get(), sample)
+    }
+
+    fn scale(&self, v: f32) -> f32 {
+        match *self.unipolar.lock() {
+            true => (v + 1.0) / 2.0,
+            false => v,
+        }
+    }
+}
+
+impl Effect for Lfo {
+    fn apply(&self, output: &mut [f32], start_sample: usize, channels: usize) {
+        let frames = output.len() / channels;
+        let rates = self.rate.values(start_sample, frames);
+        let shape = self.shape();
+
+        let mut state = self
+            .states
+            .take(start_sample)
+            .unwrap_or_else(|| LfoState::new(self.phase_at(start_sample), start_sample));
+
+        for (frame, rate) in output.chunks_mut(channels).zip(rates) {
+            let v = match shape {
+                LfoShape::SampleAndHold => state.held,
+                _ => shape.value(state.phase),
+            };
+
+            for f in frame {
+                *f = self.scale(v);
+            }
+
+            state.phase += rate / SAMPLE_RATE;
+            if state.phase >= 1.0 {
+                state.phase = state.phase.fract();
+                state.hold_new_value();
+            }
+        }
+
+        self.states.store(start_sample + frames, state);
+    }
+
+    fn input_count(&self) -> usize {
+        self.parameters().len()
+    }
+
+    fn output_count(&self) -> usize {
+        1
+    }
+
+    fn output_type(&self, _index: usize) -> PortType {
+        PortType::Control
+    }
+
+    fn set_input_at_index(&self, index: usize, input: Arc<dyn Effect>) -> Result<(), EffectError> {
+        set_modulation_input(&self.parameters(), index, 0, input)
+    }
+
+    fn get_input_at_index(&self, index: usize) -> Result<Arc<dyn Effect>, EffectError> {
+        get_modulation_input(&self.parameters(), index, 0)
+    }
+
+    fn name(&self) -> &str {
+        "LFO"
+    }
+
+    fn effect_id(&self) -> Option<&'static str> {
+        Some("lfo")
+    }
+
+    fn settings(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("shape", format!("{:?}", *self.shape.lock())),
+            ("unipolar", self.unipolar.lock().to_string()),
+        ]
+    }
+
+    fn set_setting(&self, name: &str, value: &str) -> Result<(), EffectError> {
+        let invalid = || EffectError::InvalidSetting {
+            name: name.to_string(),
+            value: value.to_string(),
+        };
+        match name {
+            "shape" => *self.shape.lock() = parse_setting(&LfoShape::ALL, name, value)?,
+            "unipolar" => *self.unipolar.lock() = value.parse().map_err(|_| invalid())?,
+            _ => return Err(EffectError::UnknownSetting(name.to_string())),
+        }
+        Ok(())
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![&self.rate]
+    }
+
+    fn data_ui(&self, ui: &mut Ui, _style: &GraphStyle) {
+        {
+            let mut shape = self.shape.lock();
+            ComboBox::from_id_salt(ui.id().with("lfo_shape"))
+                .selected_text(format!("{:?}", *shape))
+                .show_ui(ui, |ui| {
+                    for s in LfoShape::ALL {
+                        ui.selectable_value(&mut *shape, s, format!("{s:?}"));
+                    }
+                });
+        }
+
+        self.rate.ui(ui);
+        ui.add(Checkbox::new(&mut self.unipolar.lock(), "unipolar"));
+    }
+
+    fn get_waveform_plot_data(&self, sample_plot_data: &mut SamplePlotData, _channel: &Channel) {
+        let shape = self.shape();
+
+        let cycles_per_step = self.rate.get() * sample_plot_data.step as f32 / SAMPLE_RATE;
+        sample_plot_data.is_min_max = shape == LfoShape::SampleAndHold || cycles_per_step >= 1.0;
+
+        let (min, max) = sample_plot_data.data.split_at_mut(1);
+        for (i, (lower, upper)) in min[0].iter_mut().zip(max[0].iter_mut()).enumerate() {
+            (*lower, *upper) = match sample_plot_data.is_min_max {
+                true => (self.scale(-1.0), self.scale(1.0)),
+                false => {
+                    let t =
+                        self.phase_at(i * sample_plot_data.step + sample_plot_data.start_sample);
+                    let v = self.scale(shape.value(t));
+                    (v, v)
+                }
+            };
+        }
+    }
+
+    fn switch_state(&self) -> Option<&SwitchState> {
+        Some(&self.switches)
+    }
+}
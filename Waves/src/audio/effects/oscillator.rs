@@ -0,0 +1,411 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use eframe::egui::mutex::Mutex;
+use eframe::egui::{ComboBox, Ui};
+
+use crate::audio::effects::{Effect, EffectError, parse_setting};
+use crate::audio::parameter::{Parameter, get_modulation_input, set_modulation_input};
+use crate::audio::streamstate::StreamState;
+use crate::audio::switches::SwitchState;
+use crate::common::mipmapchannel::SamplePlotData;
+use crate::common::{Channel, SAMPLE_RATE};
+use crate::ui::nodegraph::GraphStyle;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Waveform {
+    Sine,
+    Saw,
+    Square,
+    Triangle,
+    Pulse,
+    WhiteNoise,
+    PinkNoise,
+    BrownNoise,
+}
+
+impl Waveform {
+    pub const ALL: [Waveform; 8] = [
+        Waveform::Sine,
+        Waveform::Saw,
+        Waveform::Square,
+        Waveform::Triangle,
+        Waveform::Pulse,
+        Waveform::WhiteNoise,
+        Waveform::PinkNoise,
+        Waveform::BrownNoise,
+    ];
+
+    fn is_noise(self) -> bool {
+        matches!(
+            self,
+            Waveform::WhiteNoise | Waveform::PinkNoise | Waveform::BrownNoise
+        )
+    }
+
+    /// The aliased shape at phase t (between 0 and 1), this is what the band limited versions correct
+    fn naive(self, t: f32, pulse_width: f32) -> f32 {
+        match self {
+            Waveform::Sine => (2.0 * PI * t).sin(),
+            Waveform::Saw => 2.0 * t - 1.0,
+            Waveform::Square => naive_pulse(t, 0.5),
+            Waveform::Pulse => naive_pulse(t, pulse_width),
+            Waveform::Triangle => 4.0 * (t - 0.5).abs() - 1.0,
+            Waveform::WhiteNoise | Waveform::PinkNoise | Waveform::BrownNoise => 0.0,
+        }
+    }
+}
+
+fn naive_pulse(t: f32, pulse_width: f32) -> f32 {
+    match t < pulse_width {
+        true => 1.0,
+        false => -1.0,
+    }
+}
+
+/// PolyBLEP residual for a step up of 2 at phase zero, where dt is the phase travelled per sample
+fn poly_blep(t: f32, dt: f32) -> f32 {
+    if t < dt {
+        let t = t / dt;
+        t + t - t * t - 1.0
+    } else if t > 1.0 - dt {
+        let t = (t - 1.0) / dt;
+        t * t + t + t + 1.0
+    } else {
+        0.0
+    }
+}
+
+/// PolyBLAMP residual for the slope increasing by one per sample at phase zero (the integral of the BLEP)
+fn poly_blamp(t: f32, dt: f32) -> f32 {
+    if t < dt {
+        let t = t / dt - 1.0;
+        -t * t * t / 3.0
+    } else if t > 1.0 - dt {
+        let t = (t - 1.0) / dt + 1.0;
+        t * t * t / 3.0
+    } else {
+        0.0
+    }
+}
+
+/// Everything that carries over from one sample to the next for a single stream
+#[derive(Clone)]
+struct OscillatorState {
+    phase: f32,
+    rng: u32,
+    pink: [f32; 7],
+    brown: f32,
+}
+
+impl OscillatorState {
+    /// Seeded by where we start so that the noise is the same every time we play from there
+    fn new(phase: f32, start_sample: usize) -> Self {
+        Self {
+            phase,
+            rng: (start_sample as u32).wrapping_mul(2654435761) | 1,
+            pink: [0.0; 7],
+            brown: 0.0,
+        }
+    }
+
+    /// Xorshift white noise between -1 and 1
+    fn white(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        (self.rng as f32 / u32::MAX as f32) * 2.0 - 1.0
+    }
+
+    /// Paul Kellet's filter, -3dB per octave
+    fn pink(&mut self) -> f32 {
+        let white = self.white();
+        let b = &mut self.pink;
+        b[0] = 0.99886 * b[0] + white * 0.0555179;
+        b[1] = 0.99332 * b[1] + white * 0.0750759;
+        b[2] = 0.96900 * b[2] + white * 0.153852;
+        b[3] = 0.86650 * b[3] + white * 0.3104856;
+        b[4] = 0.55000 * b[4] + white * 0.5329522;
+        b[5] = -0.7616 * b[5] - white * 0.0168980;
+        let pink = b.iter().sum::<f32>() + white * 0.5362;
+        b[6] = white * 0.115926;
+        pink * 0.11
+    }
+
+    /// Leaky integrated white noise, -6dB per octave
+    fn brown(&mut self) -> f32 {
+        let white = self.white();
+        self.brown = (self.brown + 0.02 * white) / 1.02;
+        self.brown * 3.5
+    }
+
+    /// Produce the next sample and move the phase on by dt
+    fn next(&mut self, waveform: Waveform, dt: f32, pulse_width: f32) -> f32 {
+        let t = self.phase;
+        // the corrections only care about how far we step, not the direction
+        let width = dt.abs().min(0.5);
+
+        let v = match waveform {
+            Waveform::Sine => waveform.naive(t, pulse_width),
+            Waveform::Saw => waveform.naive(t, pulse_width) - poly_blep(t, width),
+            Waveform::Square | Waveform::Pulse => {
+                let pulse_width = match waveform {
+                    Waveform::Square => 0.5,
+                    _ => pulse_width,
+                };
+                naive_pulse(t, pulse_width) + poly_blep(t, width)
+                    - poly_blep((t - pulse_width).rem_euclid(1.0), width)
+            }
+            Waveform::Triangle => {
+                // Round off the peak at 0 and the trough at 0.5, where the slope changes by 8 per cycle
+                waveform.naive(t, pulse_width)
+                    + 8.0
+                        * width
+                        * (poly_blamp((t + 0.5).rem_euclid(1.0), width) - poly_blamp(t, width))
+            }
+            Waveform::WhiteNoise => self.white(),
+            Waveform::PinkNoise => self.pink(),
+            Waveform::BrownNoise => self.brown(),
+        };
+
+        self.phase = (t + dt).rem_euclid(1.0);
+        v
+    }
+}
+
+/// A band limited oscillator, whose frequency can be modulated by its input.
+pub struct Oscillator {
+    waveform: Mutex<Waveform>,
+    amplitude: Parameter,
+    frequency: Parameter,
+    pulse_width: Parameter,
+    /// How many Hz the frequency moves by for an input of 1
+    fm_depth: Parameter,
+    input: Mutex<Arc<dyn Effect>>,
+    states: StreamState<OscillatorState>,
+    switches: SwitchState,
+}
+
+impl Oscillator {
+    pub fn new(waveform: Waveform, amplitude: f32, frequency: f32, input: Arc<dyn Effect>) -> Self {
+        Self {
+            waveform: Mutex::new(waveform),
+            amplitude: Parameter::new("amplitude", amplitude, 0.0..=1.0),
+            frequency: Parameter::new("frequency", frequency, 20.0..=22000.0).logarithmic(true),
+            pulse_width: Parameter::new("width", 0.5, 0.01..=0.99),
+            fm_depth: Parameter::new("fm depth", 0.0, 0.0..=2000.0),
+            input: Mutex::new(input),
+            states: StreamState::default(),
+            switches: SwitchState::default(),
+        }
+    }
+
+    pub fn waveform(&self) -> Waveform {
+        *self.waveform.lock()
+    }
+
+    pub fn frequency(&self) -> f32 {
+        self.frequency.get()
+    }
+
+    /// Where an unmodulated oscillator would be in its cycle at this sample
+    fn phase_at(&self, sample: usize) -> f32 {
+        (self.frequency() as f64 * sample as f64 / SAMPLE_RATE as f64).fract() as f32
+    }
+}
+
+impl Effect for Oscillator {
+    fn apply(&self, output: &mut [f32], start_sample: usize, channels: usize) {
+        // Collect the modulation first, this is silent if nothing is connected
+        self.input.lock().process(output, start_sample, channels);
+
+        let frames = output.len() / channels;
+        let waveform = self.waveform();
+        let amplitudes = self.amplitude.values(start_sample, frames);
+        let frequencies = self.frequency.values(start_sample, frames);
+        let pulse_widths = self.pulse_width.values(start_sample, frames);
+        let fm_depths = self.fm_depth.values(start_sample, frames);
+
+        let mut state = self
+            .states
+            .take(start_sample)
+            .unwrap_or_else(|| OscillatorState::new(self.phase_at(start_sample), start_sample));
+
+        for (i, frame) in output.chunks_mut(channels).enumerate() {
+            let dt = (frequencies[i] + fm_depths[i] * frame[0]) / SAMPLE_RATE;
+            let v = state.next(waveform, dt, pulse_widths[i]) * amplitudes[i];
+
+            for f in frame {
+                *f = v;
+            }
+        }
+
+        self.states.store(start_sample + frames, state);
+    }
+
+    fn input_count(&self) -> usize {
+        1 + self.parameters().len()
+    }
+
+    fn output_count(&self) -> usize {
+        1
+    }
+
+    fn set_input_at_index(&self, index: usize, input: Arc<dyn Effect>) -> Result<(), EffectError> {
+        match index {
+            0 => {
+                *self.input.lock() = input;
+                Ok(())
+            }
+            _ => set_modulation_input(&self.parameters(), index, 1, input),
+        }
+    }
+
+    fn get_input_at_index(&self, index: usize) -> Result<Arc<dyn Effect>, EffectError> {
+        match index {
+            0 => Ok(self.input.lock().clone()),
+            _ => get_modulation_input(&self.parameters(), index, 1),
+        }
+    }
+
+    fn name(&self) -> &str {
+        "Oscillator"
+    }
+
+    fn effect_id(&self) -> Option<&'static str> {
+        Some("oscillator")
+    }
+
+    fn settings(&self) -> Vec<(&'static str, String)> {
+        vec![("waveform", format!("{:?}", *self.waveform.lock()))]
+    }
+
+    fn set_setting(&self, name: &str, value: &str) -> Result<(), EffectError> {
+        match name {
+            "waveform" => *self.waveform.lock() = parse_setting(&Waveform::ALL, name, value)?,
+            _ => return Err(EffectError::UnknownSetting(name.to_string())),
+        }
+        Ok(())
+    }
+
+    fn input_name(&self, index: usize) -> String {
+        match index {
+            0 => "fm".to_string(),
+            _ => format!("{} mod", self.parameters()[index - 1].name()),
+        }
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![
+            &self.amplitude,
+            &self.frequency,
+            &self.pulse_width,
+            &self.fm_depth,
+        ]
+    }
+
+    fn data_ui(&self, ui: &mut Ui, _style: &GraphStyle) {
+        {
+            let mut waveform = self.waveform.lock();
+            ComboBox::from_id_salt(ui.id().with("waveform"))
+                .selected_text(format!("{:?}", *waveform))
+                .show_ui(ui, |ui| {
+                    for w in Waveform::ALL {
+                        ui.selectable_value(&mut *waveform, w, format!("{w:?}"));
+                    }
+                });
+        }
+
+        self.amplitude.ui(ui);
+
+        if self.waveform().is_noise() {
+            return;
+        }
+
+        self.frequency.ui(ui);
+        if self.waveform() == Waveform::Pulse {
+            self.pulse_width.ui(ui);
+        }
+        self.fm_depth.ui(ui);
+    }
+
+    fn get_waveform_plot_data(&self, sample_plot_data: &mut SamplePlotData, _channel: &Channel) {
+        let waveform = self.waveform();
+        let amplitude = self.amplitude.get();
+        let pulse_width = self.pulse_width.get();
+
+        // When each point covers a whole cycle (or it is noise) just show the range it covers
+        let cycles_per_step = self.frequency() * sample_plot_data.step as f32 / SAMPLE_RATE;
+        sample_plot_data.is_min_max = waveform.is_noise() || cycles_per_step >= 1.0;
+
+        let (min, max) = sample_plot_data.data.split_at_mut(1);
+        for (i, (lower, upper)) in min[0].iter_mut().zip(max[0].iter_mut()).enumerate() {
+            (*lower, *upper) = match sample_plot_data.is_min_max {
+                true => (-amplitude, amplitude),
+                false => {
+                    let t =
+                        self.phase_at(i * sample_plot_data.step + sample_plot_data.start_sample);
+                    let v = waveform.naive(t, pulse_width) * amplitude;
+                    (v, v)
+                }
+            };
+        }
+    }
+
+    fn switch_state(&self) -> Option<&SwitchState> {
+        Some(&self.switches)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audio::effects::zero::Zero;
+
+    fn render(waveform: Waveform, frequency: f32, start_sample: usize, len: usize) -> Vec<f32> {
+        let osc = Oscillator::new(waveform, 1.0, frequency, Arc::new(Zero));
+        let mut output = vec![0.0; len];
+        osc.apply(&mut output, start_sample, 1);
+        output
+    }
+
+    #[test]
+    fn test_stays_in_range() {
+        for waveform in Waveform::ALL {
+            let output = render(waveform, 3000.0, 0, 48000);
+            let peak = output.iter().fold(0.0f32, |m, f| m.max(f.abs()));
+            assert!(peak <= 1.1, "{waveform:?} peaks at {peak}");
+            assert!(peak >= 0.2, "{waveform:?} is too quiet, {peak}");
+        }
+    }
+
+    #[test]
+    fn test_split_calls_agree() {
+        // Asking for a block in two halves should give the same as asking for it all at once
+        let osc = Oscillator::new(Waveform::PinkNoise, 1.0, 440.0, Arc::new(Zero));
+        let mut whole = vec![0.0; 512];
+        osc.apply(&mut whole, 1000, 1);
+
+        let osc = Oscillator::new(Waveform::PinkNoise, 1.0, 440.0, Arc::new(Zero));
+        let mut halves = vec![0.0; 512];
+        let (first, second) = halves.split_at_mut(256);
+        osc.apply(first, 1000, 1);
+        // a different caller in the middle should not disturb the first
+        osc.apply(&mut [0.0; 64], 0, 1);
+        osc.apply(second, 1256, 1);
+
+        assert_eq!(whole, halves);
+    }
+
+    #[test]
+    fn test_band_limited_saw_is_smoother() {
+        // Right next to the jump the corrected saw should be partway through rather than at the extremes
+        let frequency = SAMPLE_RATE / 64.5;
+        let output = render(Waveform::Saw, frequency, 0, 256);
+        let biggest_jump = output
+            .windows(2)
+            .map(|w| (w[1] - w[0]).abs())
+            .fold(0.0f32, f32::max);
+        assert!(biggest_jump < 1.9, "saw still jumps by {biggest_jump}");
+    }
+}
@@ -4,16 +4,29 @@ use std::sync::Arc;
 use eframe::egui::mutex::Mutex;
 
 use crate::audio::effects::{Effect, EffectError};
+use crate::audio::switches;
+use crate::common::mipmapchannel::SamplePlotData;
 
 pub struct Output {
     input: Mutex<Arc<dyn Effect>>,
+    /// The soloed nodes feeding into the input, which are only looked for when the graph changes rather than every block
+    soloed: Mutex<Arc<Vec<Arc<dyn Effect>>>>,
 }
 
 impl Output {
     pub fn new(input: Arc<dyn Effect>) -> Self {
-        Self {
+        let output = Self {
             input: Mutex::new(input),
-        }
+            soloed: Default::default(),
+        };
+        output.find_soloed();
+        output
+    }
+
+    /// Look again for the soloed nodes, after a switch or connection has changed
+    pub fn find_soloed(&self) {
+        let input = self.input.lock().clone();
+        *self.soloed.lock() = Arc::new(switches::soloed(&input));
     }
 }
 
@@ -24,8 +37,21 @@ impl Debug for Output {
 }
 
 impl Effect for Output {
+    /// The input, or just the soloed nodes feeding into it if there are any
     fn apply(&self, output: &mut [f32], start_sample: usize, channels: usize) {
-        self.input.lock().apply(output, start_sample, channels);
+        let input = self.input.lock().clone();
+        let soloed = self.soloed.lock().clone();
+        if soloed.is_empty() {
+            input.process(output, start_sample, channels);
+            return;
+        }
+
+        output.fill(0.0);
+        let mut solo = vec![0.0; output.len()];
+        for effect in soloed.iter() {
+            effect.process(&mut solo, start_sample, channels);
+            output.iter_mut().zip(&solo).for_each(|(o, s)| *o += s);
+        }
     }
 
     fn input_count(&self) -> usize {
@@ -40,6 +66,7 @@ impl Effect for Output {
         match index {
             0 => {
                 *self.input.lock() = input;
+                self.find_soloed();
                 Ok(())
             }
             _ => Err(EffectError::OutOfBounds(index)),
@@ -62,8 +89,27 @@ impl Effect for Output {
         sample_plot_data: &mut crate::common::mipmapchannel::SamplePlotData,
         channel: &crate::common::Channel,
     ) {
-        self.input
-            .lock()
-            .get_waveform_plot_data(sample_plot_data, channel);
+        let input = self.input.lock().clone();
+        let soloed = self.soloed.lock().clone();
+        if soloed.is_empty() {
+            input.plot_data(sample_plot_data, channel);
+            return;
+        }
+
+        // the soloed nodes are summed, as when they are played
+        for v in sample_plot_data.data.iter_mut() {
+            v.fill(0.0);
+        }
+        for effect in soloed.iter() {
+            let mut soloed_data = SamplePlotData::new(
+                sample_plot_data.step,
+                sample_plot_data.start_sample,
+                sample_plot_data.data[0].len(),
+            );
+            effect.plot_data(&mut soloed_data, channel);
+
+            sample_plot_data.is_min_max = soloed_data.is_min_max;
+            sample_plot_data.mix(&soloed_data, 1.0, 1.0);
+        }
     }
 }
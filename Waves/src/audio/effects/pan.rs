@@ -0,0 +1,280 @@
+use std::f32::consts::FRAC_PI_4;
+use std::sync::Arc;
+
+use eframe::egui::mutex::Mutex;
+use eframe::egui::{ComboBox, Ui};
+
+use crate::audio::effects::{Effect, EffectError, parse_setting};
+use crate::audio::parameter::{Parameter, get_modulation_input, set_modulation_input};
+use crate::audio::switches::SwitchState;
+use crate::common::Channel;
+use crate::common::mipmapchannel::SamplePlotData;
+use crate::ui::nodegraph::GraphStyle;
+
+/// How the signal is shared out between the left and right channels as it is panned
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum PanLaw {
+    /// Gains fall off linearly, so the centre sits at -6dB
+    Linear,
+    /// Sine/cosine gains so the total power stays the same, the centre sits at -3dB
+    ConstantPower,
+    /// Halfway between linear and constant power, the centre sits at -4.5dB
+    Compromise,
+    /// The centre is untouched and only the opposite channel is turned down (best for stereo material)
+    Balance,
+}
+
+impl PanLaw {
+    pub const ALL: [PanLaw; 4] = [
+        PanLaw::Linear,
+        PanLaw::ConstantPower,
+        PanLaw::Compromise,
+        PanLaw::Balance,
+    ];
+
+    /// Returns the (left, right) amplitudes for a pan position between -1 (hard left) and 1 (hard right)
+    pub fn gains(self, pan: f32) -> (f32, f32) {
+        let pan = pan.clamp(-1.0, 1.0);
+
+        // the position as an angle between 0 (hard left) and pi/2 (hard right)
+        let theta = (pan + 1.0) * FRAC_PI_4;
+        let linear = ((1.0 - pan) / 2.0, (1.0 + pan) / 2.0);
+
+        match self {
+            PanLaw::Linear => linear,
+            PanLaw::ConstantPower => (theta.cos(), theta.sin()),
+            PanLaw::Compromise => (
+                (linear.0 * theta.cos()).sqrt(),
+                (linear.1 * theta.sin()).sqrt(),
+            ),
+            PanLaw::Balance => ((1.0 - pan).min(1.0), (1.0 + pan).min(1.0)),
+        }
+    }
+}
+
+/// Move the signal between the left and right channels.
+pub struct Pan {
+    pan: Parameter,
+    law: Mutex<PanLaw>,
+    input: Mutex<Arc<dyn Effect>>,
+    switches: SwitchState,
+}
+
+impl Pan {
+    pub fn new(pan: f32, law: PanLaw, input: Arc<dyn Effect>) -> Self {
+        Self {
+            pan: Parameter::new("pan", pan, -1.0..=1.0),
+            law: Mutex::new(law),
+            input: Mutex::new(input),
+            switches: SwitchState::default(),
+        }
+    }
+
+    pub fn pan(&self) -> f32 {
+        self.pan.get()
+    }
+
+    pub fn law(&self) -> PanLaw {
+        *self.law.lock()
+    }
+
+    fn gains(&self) -> (f32, f32) {
+        self.law().gains(self.pan())
+    }
+}
+
+impl Effect for Pan {
+    fn apply(&self, output: &mut [f32], start_sample: usize, channels: usize) {
+        self.input.lock().process(output, start_sample, channels);
+
+        // There is nowhere to move a mono signal to
+        if channels < 2 {
+            return;
+        }
+
+        let law = self.law();
+        let pans = self.pan.values(start_sample, output.len() / channels);
+        for (frame, pan) in output.chunks_mut(channels).zip(pans) {
+            let (left, right) = law.gains(pan);
+            frame[0] *= left;
+            frame[1] *= right;
+        }
+    }
+
+    fn input_count(&self) -> usize {
+        1 + self.parameters().len()
+    }
+
+    fn output_count(&self) -> usize {
+        1
+    }
+
+    fn set_input_at_index(&self, index: usize, input: Arc<dyn Effect>) -> Result<(), EffectError> {
+        match index {
+            0 => {
+                *self.input.lock() = input;
+                Ok(())
+            }
+            _ => set_modulation_input(&self.parameters(), index, 1, input),
+        }
+    }
+
+    fn get_input_at_index(&self, index: usize) -> Result<Arc<dyn Effect>, EffectError> {
+        match index {
+            0 => Ok(self.input.lock().clone()),
+            _ => get_modulation_input(&self.parameters(), index, 1),
+        }
+    }
+
+    fn name(&self) -> &str {
+        "Pan"
+    }
+
+    fn effect_id(&self) -> Option<&'static str> {
+        Some("pan")
+    }
+
+    fn settings(&self) -> Vec<(&'static str, String)> {
+        vec![("law", format!("{:?}", *self.law.lock()))]
+    }
+
+    fn set_setting(&self, name: &str, value: &str) -> Result<(), EffectError> {
+        match name {
+            "law" => *self.law.lock() = parse_setting(&PanLaw::ALL, name, value)?,
+            _ => return Err(EffectError::UnknownSetting(name.to_string())),
+        }
+        Ok(())
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![&self.pan]
+    }
+
+    fn data_ui(&self, ui: &mut Ui, _style: &GraphStyle) {
+        self.pan.ui(ui);
+
+        let mut law = self.law.lock();
+        ComboBox::from_id_salt(ui.id().with("pan_law"))
+            .selected_text(format!("{:?}", *law))
+            .show_ui(ui, |ui| {
+                for l in PanLaw::ALL {
+                    ui.selectable_value(&mut *law, l, format!("{l:?}"));
+                }
+            });
+    }
+
+    fn get_waveform_plot_data(&self, sample_plot_data: &mut SamplePlotData, channel: &Channel) {
+        self.input.lock().plot_data(sample_plot_data, channel);
+
+        // the gains are never negative so the min/max lines stay the right way round
+        let gain = match channel {
+            Channel::Left => self.gains().0,
+            Channel::Right => self.gains().1,
+        };
+
+        for v in &mut sample_plot_data.data {
+            for j in v {
+                *j *= gain;
+            }
+        }
+    }
+
+    fn switch_state(&self) -> Option<&SwitchState> {
+        Some(&self.switches)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_centre_levels() {
+        let expected = [
+            (PanLaw::Linear, -6.02),
+            (PanLaw::ConstantPower, -3.01),
+            (PanLaw::Compromise, -4.52),
+            (PanLaw::Balance, 0.0),
+        ];
+
+        for (law, db) in expected {
+            let (left, right) = law.gains(0.0);
+            assert_eq!(left, right, "{law:?} is not symmetric at the centre");
+            assert!(
+                (crate::common::dB::from_amplitude(left).0 - db).abs() < 0.01,
+                "{law:?} has the wrong centre level"
+            );
+        }
+    }
+
+    #[test]
+    fn test_hard_left() {
+        for law in PanLaw::ALL {
+            let (left, right) = law.gains(-1.0);
+            assert!((left - 1.0).abs() < 1e-6, "{law:?} loses level hard left");
+            assert!(right.abs() < 1e-6, "{law:?} leaks into the right channel");
+        }
+    }
+
+    #[test]
+    fn test_constant_power() {
+        for i in 0..=20 {
+            let (left, right) = PanLaw::ConstantPower.gains(i as f32 / 10.0 - 1.0);
+            assert!((left * left + right * right - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_mono_untouched() {
+        let pan = Pan::new(1.0, PanLaw::Linear, Arc::new(Dc));
+        let mut output = vec![0.0; 16];
+
+        pan.apply(&mut output, 0, 1);
+        assert!(output.iter().all(|&f| f == 1.0));
+
+        pan.apply(&mut output, 0, 2);
+        for frame in output.chunks(2) {
+            assert_eq!(frame, [0.0, 1.0]);
+        }
+    }
+
+    /// A constant signal of one on every channel
+    struct Dc;
+
+    impl Effect for Dc {
+        fn apply(&self, output: &mut [f32], _start_sample: usize, _channels: usize) {
+            output.fill(1.0);
+        }
+
+        fn input_count(&self) -> usize {
+            0
+        }
+
+        fn output_count(&self) -> usize {
+            1
+        }
+
+        fn set_input_at_index(
+            &self,
+            index: usize,
+            _input: Arc<dyn Effect>,
+        ) -> Result<(), EffectError> {
+            Err(EffectError::OutOfBounds(index))
+        }
+
+        fn get_input_at_index(&self, index: usize) -> Result<Arc<dyn Effect>, EffectError> {
+            Err(EffectError::OutOfBounds(index))
+        }
+
+        fn name(&self) -> &str {
+            "DC"
+        }
+
+        fn get_waveform_plot_data(
+            &self,
+            _sample_plot_data: &mut SamplePlotData,
+            _channel: &Channel,
+        ) {
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use eframe::egui::mutex::Mutex;
+use eframe::egui::{Slider, Ui};
+
+use crate::audio::effects::lfo::phase_at;
+use crate::audio::effects::{Effect, EffectError};
+use crate::audio::parameter::{Parameter, get_modulation_input, set_modulation_input};
+use crate::audio::streamstate::StreamState;
+use crate::audio::switches::SwitchState;
+use crate::common::mipmapchannel::SamplePlotData;
+use crate::common::{Channel, SAMPLE_RATE};
+use crate::ui::nodegraph::GraphStyle;
+
+const MAX_STAGES: usize = 12;
+
+/// How many samples before a relocation we run over to let the feedback build back up
+const PRE_ROLL: usize = 4096;
+
+#[derive(Clone)]
+struct PhaserState {
+    /// The all-pass states, `stages` of them for each channel one after the other
+    allpasses: Vec<f32>,
+    /// The last output of the chain for each channel, to feed back in
+    last: Vec<f32>,
+    phase: f32,
+}
+
+impl PhaserState {
+    fn new(stages: usize, channels: usize, phase: f32) -> Self {
+        Self {
+            allpasses: vec![0.0; stages * channels],
+            last: vec![0.0; channels],
+            phase,
+        }
+    }
+}
+
+/// A chain of all-pass filters whose corner frequency sweeps up and down,
+/// making notches that move through the spectrum when mixed back with the dry signal
+pub struct Phaser {
+    stages: Mutex<usize>,
+    rate: Parameter,
+    frequency: Parameter,
+    sweep: Parameter,
+    feedback: Parameter,
+    mix: Parameter,
+    input: Mutex<Arc<dyn Effect>>,
+    states: StreamState<PhaserState>,
+    switches: SwitchState,
+}
+
+impl Phaser {
+    pub fn new(stages: usize, rate: f32, frequency: f32, input: Arc<dyn Effect>) -> Self {
+        Self {
+            stages: Mutex::new(stages.clamp(1, MAX_STAGES)),
+            rate: Parameter::new("rate", rate, 0.01..=5.0).logarithmic(true),
+            frequency: Parameter::new("frequency", frequency, 100.0..=4000.0).logarithmic(true),
+            sweep: Parameter::new("sweep", 2.0, 0.0..=4.0),
+            feedback: Parameter::new("feedback", 0.5, -0.95..=0.95),
+            mix: Parameter::new("mix", 0.5, 0.0..=1.0),
+            input: Mutex::new(input),
+            states: StreamState::default(),
+            switches: SwitchState::default(),
+        }
+    }
+
+    pub fn stages(&self) -> usize {
+        *self.stages.lock()
+    }
+
+    /// Run the phaser over the frames in place, carrying on from the given state
+    fn process(
+        &self,
+        state: &mut PhaserState,
+        frames: &mut [f32],
+        start_sample: usize,
+        channels: usize,
+    ) {
+        let count = frames.len() / channels;
+        let rates = self.rate.values(start_sample, count);
+        let frequencies = self.frequency.values(start_sample, count);
+        let sweeps = self.sweep.values(start_sample, count);
+        let feedbacks = self.feedback.values(start_sample, count);
+        let mixes = self.mix.values(start_sample, count);
+        let stages = state.allpasses.len() / channels;
+
+        for (i, frame) in frames.chunks_mut(channels).enumerate() {
+            for (c, f) in frame.iter_mut().enumerate() {
+                // the sweep is in octaves either side of the frequency, the channels a quarter cycle apart
+                let lfo = (2.0 * PI * (state.phase + c as f32 * 0.25)).sin();
+                let corner =
+                    (frequencies[i] * 2f32.powf(sweeps[i] / 2.0 * lfo)).min(SAMPLE_RATE * 0.49);
+                let t = (PI * corner / SAMPLE_RATE).tan();
+                let a = (t - 1.0) / (t + 1.0);
+
+                let mut x = *f + feedbacks[i] * state.last[c];
+                for s in &mut state.allpasses[c * stages..(c + 1) * stages] {
+                    let y = a * x + *s;
+                    *s = x - a * y;
+                    x = y;
+                }
+
+                state.last[c] = x;
+                *f = *f * (1.0 - mixes[i]) + x * mixes[i];
+            }
+
+            state.phase = (state.phase + rates[i] / SAMPLE_RATE).fract();
+        }
+    }
+}
+
+impl Effect for Phaser {
+    fn apply(&self, output: &mut [f32], start_sample: usize, channels: usize) {
+        let stages = self.stages();
+
+        let mut state = match self.states.take(start_sample) {
+            Some(state) if state.allpasses.len() == stages * channels => state,
+            _ => {
+                // We have been moved (or the stages changed) so run over what came just before to settle down
+                let pre_roll = PRE_ROLL.min(start_sample);
+                let mut history = vec![0.0; pre_roll * channels];
+                self.input
+                    .lock()
+                    .process(&mut history, start_sample - pre_roll, channels);
+
+                let phase = phase_at(self.rate.get(), start_sample - pre_roll);
+                let mut state = PhaserState::new(stages, channels, phase);
+                self.process(&mut state, &mut history, start_sample - pre_roll, channels);
+                state
+            }
+        };
+
+        self.input.lock().process(output, start_sample, channels);
+        self.process(&mut state, output, start_sample, channels);
+
+        self.states
+            .store(start_sample + output.len() / channels, state);
+    }
+
+    fn input_count(&self) -> usize {
+        1 + self.parameters().len()
+    }
+
+    fn output_count(&self) -> usize {
+        1
+    }
+
+    fn set_input_at_index(&self, index: usize, input: Arc<dyn Effect>) -> Result<(), EffectError> {
+        match index {
+            0 => {
+                *self.input.lock() = input;
+                Ok(())
+            }
+            _ => set_modulation_input(&self.parameters(), index, 1, input),
+        }
+    }
+
+    fn get_input_at_index(&self, index: usize) -> Result<Arc<dyn Effect>, EffectError> {
+        match index {
+            0 => Ok(self.input.lock().clone()),
+            _ => get_modulation_input(&self.parameters(), index, 1),
+        }
+    }
+
+    fn name(&self) -> &str {
+        "Phaser"
+    }
+
+    fn effect_id(&self) -> Option<&'static str> {
+        Some("phaser")
+    }
+
+    fn settings(&self) -> Vec<(&'static str, String)> {
+        vec![("stages", self.stages.lock().to_string())]
+    }
+
+    fn set_setting(&self, name: &str, value: &str) -> Result<(), EffectError> {
+        let invalid = || EffectError::InvalidSetting {
+            name: name.to_string(),
+            value: value.to_string(),
+        };
+        match name {
+            "stages" => {
+                let count = value.parse::<usize>().map_err(|_| invalid())?;
+                *self.stages.lock() = count.clamp(1, MAX_STAGES);
+            }
+            _ => return Err(EffectError::UnknownSetting(name.to_string())),
+        }
+        Ok(())
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![
+            &self.rate,
+            &self.frequency,
+            &self.sweep,
+            &self.feedback,
+            &self.mix,
+        ]
+    }
+
+    fn data_ui(&self, ui: &mut Ui, _style: &GraphStyle) {
+        ui.add(Slider::new(&mut *self.stages.lock(), 1..=MAX_STAGES).text("stages"));
+        for parameter in self.parameters() {
+            parameter.ui(ui);
+        }
+    }
+
+    /// The overview can't see phase, so this just shows the input
+    fn get_waveform_plot_data(&self, sample_plot_data: &mut SamplePlotData, channel: &Channel) {
+        self.input.lock().plot_data(sample_plot_data, channel);
+    }
+
+    fn switch_state(&self) -> Option<&SwitchState> {
+        Some(&self.switches)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audio::effects::oscillator::{Oscillator, Waveform};
+    use crate::audio::effects::sinewave::SineWave;
+    use crate::audio::effects::testing::{assert_blocks_carry_on, assert_relocation_matches};
+    use crate::audio::effects::zero::Zero;
+
+    fn phaser() -> Phaser {
+        let saw = Arc::new(Oscillator::new(Waveform::Saw, 0.5, 220.0, Arc::new(Zero)));
+        Phaser::new(4, 0.5, 800.0, saw)
+    }
+
+    #[test]
+    fn test_blocks_carry_on() {
+        assert_blocks_carry_on(phaser, 10000, 1024, 2, 0.0);
+    }
+
+    #[test]
+    fn test_relocation_matches() {
+        // a sine, as the oscillator's own phase drifts a little playing through
+        let phaser = || Phaser::new(4, 0.5, 800.0, Arc::new(SineWave::new(0.5, 220.0, 0.0)));
+        assert_relocation_matches(phaser, 15000, 256, 1e-3);
+    }
+
+    #[test]
+    fn test_stages_changed() {
+        let p = phaser();
+        let mut before = vec![0.0; 512];
+        p.apply(&mut before, 10000, 2);
+
+        // carrying on with a different number of stages should start again rather than reuse the old state
+        *p.stages.lock() = 8;
+        let mut after = vec![0.0; 512];
+        p.apply(&mut after, 10256, 2);
+        assert!(after.iter().all(|f| f.is_finite()));
+    }
+}
@@ -1,34 +1,39 @@
+use std::f32::consts::PI;
 use std::sync::Arc;
-use std::{f32::consts::PI, sync::Mutex};
-
-use eframe::egui::Slider;
 
 use crate::audio::effects::{Effect, EffectError};
+use crate::audio::parameter::{Parameter, get_modulation_input, set_modulation_input};
+use crate::audio::switches::SwitchState;
 
 pub struct SineWave {
-    amplitude: Mutex<f32>,
-    frequency: Mutex<f32>,
-    phase: Mutex<f32>,
+    amplitude: Parameter,
+    frequency: Parameter,
+    phase: Parameter,
+    switches: SwitchState,
 }
 
 impl SineWave {
     pub fn new(amplitude: f32, frequency: f32, phase: f32) -> Self {
         Self {
-            amplitude: Mutex::new(amplitude),
-            frequency: Mutex::new(frequency),
-            phase: Mutex::new(phase),
+            amplitude: Parameter::new("amplitude", amplitude, 0.0..=1.0),
+            frequency: Parameter::new("frequency", frequency, 20.0..=22000.0).logarithmic(true),
+            phase: Parameter::new("phase", phase, 0.0..=2.0 * PI),
+            switches: SwitchState::default(),
         }
     }
 }
 
 impl Effect for SineWave {
     fn apply(&self, output: &mut [f32], start_sample: usize, channels: usize) {
+        let frames = output.len() / channels;
+        let amplitudes = self.amplitude.values(start_sample, frames);
+        let frequencies = self.frequency.values(start_sample, frames);
+        let phases = self.phase.values(start_sample, frames);
+
         for (i, frame) in output.chunks_mut(channels).enumerate() {
-            let v = ((2.0 * PI * (i + start_sample) as f32 / 48000.0)
-                * *self.frequency.lock().unwrap()
-                - *self.phase.lock().unwrap())
-            .sin()
-                * *self.amplitude.lock().unwrap();
+            let v = ((2.0 * PI * (i + start_sample) as f32 / 48000.0) * frequencies[i] - phases[i])
+                .sin()
+                * amplitudes[i];
 
             for f in frame {
                 *f = v;
@@ -37,25 +42,33 @@ impl Effect for SineWave {
     }
 
     fn input_count(&self) -> usize {
-        0
+        self.parameters().len()
     }
 
     fn output_count(&self) -> usize {
         1
     }
 
-    fn set_input_at_index(&self, index: usize, _input: Arc<dyn Effect>) -> Result<(), EffectError> {
-        Err(EffectError::OutOfBounds(index))
+    fn set_input_at_index(&self, index: usize, input: Arc<dyn Effect>) -> Result<(), EffectError> {
+        set_modulation_input(&self.parameters(), index, 0, input)
     }
 
     fn get_input_at_index(&self, index: usize) -> Result<Arc<dyn Effect>, EffectError> {
-        Err(EffectError::OutOfBounds(index))
+        get_modulation_input(&self.parameters(), index, 0)
     }
 
     fn name(&self) -> &str {
         "Sine Wave"
     }
 
+    fn effect_id(&self) -> Option<&'static str> {
+        Some("sine_wave")
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![&self.amplitude, &self.phase, &self.frequency]
+    }
+
     fn get_waveform_plot_data(
         &self,
         sample_plot_data: &mut crate::common::mipmapchannel::SamplePlotData,
@@ -67,10 +80,10 @@ impl Effect for SineWave {
                     * PI
                     * (i * sample_plot_data.step + sample_plot_data.start_sample) as f32
                     / 48000.0)
-                    * *self.frequency.lock().unwrap()
-                    - *self.phase.lock().unwrap())
+                    * self.frequency.get()
+                    - self.phase.get())
                 .sin()
-                    * *self.amplitude.lock().unwrap();
+                    * self.amplitude.get();
 
                 match j {
                     0 => *f = -v,
@@ -82,11 +95,12 @@ impl Effect for SineWave {
     }
 
     fn data_ui(&self, ui: &mut eframe::egui::Ui, _style: &crate::ui::nodegraph::GraphStyle) {
-        ui.add(Slider::new(&mut *self.amplitude.lock().unwrap(), 0.0..=1.0));
-        ui.add(Slider::new(
-            &mut *self.phase.lock().unwrap(),
-            0.0..=2.0 * PI,
-        ));
-        ui.add(Slider::new(&mut *self.frequency.lock().unwrap(), 20.0..=22000.0).logarithmic(true));
+        self.amplitude.ui(ui);
+        self.phase.ui(ui);
+        self.frequency.ui(ui);
+    }
+
+    fn switch_state(&self) -> Option<&SwitchState> {
+        Some(&self.switches)
     }
 }
@@ -0,0 +1,422 @@
+use std::f32::consts::PI;
+use std::sync::{Arc, Weak};
+
+use eframe::egui::Ui;
+use eframe::egui::mutex::Mutex;
+
+use crate::audio::effects::{
+    Effect, EffectError,
+    tap::{MultiOutput, OutputTap},
+};
+use crate::audio::parameter::{Parameter, get_modulation_input, set_modulation_input};
+use crate::audio::port::PortType;
+use crate::audio::streamstate::StreamState;
+use crate::audio::switches::SwitchState;
+use crate::common::mipmapchannel::SamplePlotData;
+use crate::common::{Channel, SAMPLE_RATE};
+use crate::ui::nodegraph::GraphStyle;
+
+/// How many samples before a relocation we run the crossovers over to get them into the right state
+const PRE_ROLL: usize = 2048;
+
+/// The outputs of the band splitter, from the bottom up
+const BANDS: [&str; 3] = ["low", "mid", "high"];
+
+/// Make a tap for each output of an effect that is still being made
+fn taps<T: MultiOutput>(source: &Weak<T>, count: usize) -> Vec<Arc<OutputTap>> {
+    let source: Weak<dyn MultiOutput> = source.clone();
+    (0..count)
+        .map(|i| Arc::new(OutputTap::new(source.clone(), i)))
+        .collect()
+}
+
+/// The one pole low pass states of a channel, for the low and high crossovers
+#[derive(Clone, Copy, Default)]
+struct CrossoverState {
+    low: f32,
+    high: f32,
+}
+
+/// A block split into all its bands, kept for the other outputs asking for the same block
+struct SplitBlock {
+    channels: usize,
+    bands: [Vec<f32>; BANDS.len()],
+    /// Which outputs have had their band, so one asking again is a new pass and gets it worked out afresh
+    served: [bool; BANDS.len()],
+}
+
+/// Splits the sound into low, mid and high bands, which add back up to what went in
+pub struct BandSplitter {
+    low: Parameter,
+    high: Parameter,
+    input: Mutex<Arc<dyn Effect>>,
+    states: StreamState<Vec<CrossoverState>>,
+    blocks: StreamState<SplitBlock>,
+    taps: Vec<Arc<OutputTap>>,
+    switches: SwitchState,
+}
+
+impl BandSplitter {
+    pub fn new(low: f32, high: f32, input: Arc<dyn Effect>) -> Arc<Self> {
+        Arc::new_cyclic(|source| Self {
+            low: Parameter::new("low", low, 20.0..=20000.0).logarithmic(true),
+            high: Parameter::new("high", high, 20.0..=20000.0).logarithmic(true),
+            input: Mutex::new(input),
+            states: StreamState::default(),
+            blocks: StreamState::default(),
+            taps: taps(source, BANDS.len()),
+            switches: SwitchState::default(),
+        })
+    }
+
+    /// Split the frames into every band, carrying on from the given state
+    fn split(
+        &self,
+        states: &mut [CrossoverState],
+        frames: &[f32],
+        start_sample: usize,
+        channels: usize,
+    ) -> [Vec<f32>; BANDS.len()] {
+        let count = frames.len() / channels;
+        let lows = self.low.values(start_sample, count);
+        let highs = self.high.values(start_sample, count);
+        let coefficient = |cutoff: f32| 1.0 - (-2.0 * PI * cutoff / SAMPLE_RATE).exp();
+
+        let mut bands = [(); BANDS.len()].map(|_| Vec::with_capacity(frames.len()));
+        for ((frame, low), high) in frames.chunks(channels).zip(lows).zip(highs) {
+            let (a_low, a_high) = (coefficient(low), coefficient(high.max(low)));
+            for (f, s) in frame.iter().zip(states.iter_mut()) {
+                s.low += a_low * (f - s.low);
+                s.high += a_high * (f - s.high);
+                bands[0].push(s.low);
+                bands[1].push(s.high - s.low);
+                bands[2].push(f - s.high);
+            }
+        }
+        bands
+    }
+
+    /// Work out every band of the block from the input, carrying the crossovers on from the block before
+    fn split_block(&self, len: usize, start_sample: usize, channels: usize) -> SplitBlock {
+        let mut states = match self.states.take(start_sample) {
+            Some(states) if states.len() == channels => states,
+            _ => {
+                // We have been moved so run over what came just before to settle down
+                let mut states = vec![CrossoverState::default(); channels];
+                let pre_roll = PRE_ROLL.min(start_sample);
+                if pre_roll > 0 {
+                    let mut history = vec![0.0; pre_roll * channels];
+                    self.input
+                        .lock()
+                        .process(&mut history, start_sample - pre_roll, channels);
+                    self.split(&mut states, &history, start_sample - pre_roll, channels);
+                }
+                states
+            }
+        };
+
+        let mut input = vec![0.0; len];
+        self.input
+            .lock()
+            .process(&mut input, start_sample, channels);
+        let bands = self.split(&mut states, &input, start_sample, channels);
+        self.states.store(start_sample + len / channels, states);
+
+        SplitBlock {
+            channels,
+            bands,
+            served: [false; BANDS.len()],
+        }
+    }
+}
+
+impl MultiOutput for BandSplitter {
+    /// The band out of the block split for whichever output asked first, so the input is only worked out once
+    fn apply_output(&self, index: usize, output: &mut [f32], start_sample: usize, channels: usize) {
+        let index = index.min(BANDS.len() - 1);
+        let mut block = match self.blocks.take(start_sample) {
+            Some(block)
+                if block.channels == channels
+                    && block.bands[index].len() == output.len()
+                    && !block.served[index] =>
+            {
+                block
+            }
+            _ => self.split_block(output.len(), start_sample, channels),
+        };
+
+        output.copy_from_slice(&block.bands[index]);
+        block.served[index] = true;
+        self.blocks.store(start_sample, block);
+    }
+
+    /// The overview can't see frequencies, so every band just shows the input
+    fn output_plot_data(
+        &self,
+        _index: usize,
+        sample_plot_data: &mut SamplePlotData,
+        channel: &Channel,
+    ) {
+        self.get_waveform_plot_data(sample_plot_data, channel);
+    }
+}
+
+impl Effect for BandSplitter {
+    /// All the bands together, which is the input
+    fn apply(&self, output: &mut [f32], start_sample: usize, channels: usize) {
+        self.input.lock().process(output, start_sample, channels);
+    }
+
+    fn input_count(&self) -> usize {
+        1 + self.parameters().len()
+    }
+
+    fn output_count(&self) -> usize {
+        BANDS.len()
+    }
+
+    fn set_input_at_index(&self, index: usize, input: Arc<dyn Effect>) -> Result<(), EffectError> {
+        match index {
+            0 => {
+                *self.input.lock() = input;
+                Ok(())
+            }
+            _ => set_modulation_input(&self.parameters(), index, 1, input),
+        }
+    }
+
+    fn get_input_at_index(&self, index: usize) -> Result<Arc<dyn Effect>, EffectError> {
+        match index {
+            0 => Ok(self.input.lock().clone()),
+            _ => get_modulation_input(&self.parameters(), index, 1),
+        }
+    }
+
+    fn name(&self) -> &str {
+        "Band Splitter"
+    }
+
+    fn output_name(&self, index: usize) -> String {
+        BANDS.get(index).unwrap_or(&"output").to_string()
+    }
+
+    fn output_at_index(&self, index: usize) -> Option<Arc<dyn Effect>> {
+        Some(self.taps.get(index)?.clone())
+    }
+
+    fn effect_id(&self) -> Option<&'static str> {
+        Some("band_splitter")
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![&self.low, &self.high]
+    }
+
+    fn data_ui(&self, ui: &mut Ui, _style: &GraphStyle) {
+        self.low.ui(ui);
+        self.high.ui(ui);
+    }
+
+    fn get_waveform_plot_data(&self, sample_plot_data: &mut SamplePlotData, channel: &Channel) {
+        self.input.lock().plot_data(sample_plot_data, channel);
+    }
+
+    fn switch_state(&self) -> Option<&SwitchState> {
+        Some(&self.switches)
+    }
+}
+
+/// Splits the left and right channels into outputs of their own
+pub struct StereoSplitter {
+    input: Mutex<Arc<dyn Effect>>,
+    taps: Vec<Arc<OutputTap>>,
+    switches: SwitchState,
+}
+
+impl StereoSplitter {
+    pub fn new(input: Arc<dyn Effect>) -> Arc<Self> {
+        Arc::new_cyclic(|source| Self {
+            input: Mutex::new(input),
+            taps: taps(source, 2),
+            switches: SwitchState::default(),
+        })
+    }
+}
+
+impl MultiOutput for StereoSplitter {
+    /// The one channel, in every channel of the output
+    fn apply_output(&self, index: usize, output: &mut [f32], start_sample: usize, channels: usize) {
+        let frames = output.len() / channels;
+        let mut stereo = vec![0.0; frames * 2];
+        self.input.lock().process(&mut stereo, start_sample, 2);
+
+        for (frame, pair) in output.chunks_mut(channels).zip(stereo.chunks(2)) {
+            frame.fill(pair[index.min(1)]);
+        }
+    }
+
+    fn output_plot_data(
+        &self,
+        index: usize,
+        sample_plot_data: &mut SamplePlotData,
+        _channel: &Channel,
+    ) {
+        let channel = match index {
+            0 => Channel::Left,
+            _ => Channel::Right,
+        };
+        self.input.lock().plot_data(sample_plot_data, &channel);
+    }
+}
+
+impl Effect for StereoSplitter {
+    /// Both channels together, which is the input
+    fn apply(&self, output: &mut [f32], start_sample: usize, channels: usize) {
+        self.input.lock().process(output, start_sample, channels);
+    }
+
+    fn input_count(&self) -> usize {
+        1
+    }
+
+    fn output_count(&self) -> usize {
+        2
+    }
+
+    fn set_input_at_index(&self, index: usize, input: Arc<dyn Effect>) -> Result<(), EffectError> {
+        match index {
+            0 => {
+                *self.input.lock() = input;
+                Ok(())
+            }
+            _ => Err(EffectError::OutOfBounds(index)),
+        }
+    }
+
+    fn get_input_at_index(&self, index: usize) -> Result<Arc<dyn Effect>, EffectError> {
+        match index {
+            0 => Ok(self.input.lock().clone()),
+            _ => Err(EffectError::OutOfBounds(index)),
+        }
+    }
+
+    fn name(&self) -> &str {
+        "Stereo Splitter"
+    }
+
+    fn output_type(&self, _index: usize) -> PortType {
+        PortType::Audio { channels: 1 }
+    }
+
+    fn output_name(&self, index: usize) -> String {
+        match index {
+            0 => "left".to_string(),
+            _ => "right".to_string(),
+        }
+    }
+
+    fn output_at_index(&self, index: usize) -> Option<Arc<dyn Effect>> {
+        Some(self.taps.get(index)?.clone())
+    }
+
+    fn effect_id(&self) -> Option<&'static str> {
+        Some("stereo_splitter")
+    }
+
+    fn get_waveform_plot_data(&self, sample_plot_data: &mut SamplePlotData, channel: &Channel) {
+        self.input.lock().plot_data(sample_plot_data, channel);
+    }
+
+    fn switch_state(&self) -> Option<&SwitchState> {
+        Some(&self.switches)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audio::effects::{output_of, output_source, sinewave::SineWave};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Silence that counts how many times it has been asked for
+    #[derive(Default)]
+    struct Counted(AtomicUsize);
+
+    impl Effect for Counted {
+        fn apply(&self, output: &mut [f32], _start_sample: usize, _channels: usize) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            output.fill(0.0);
+        }
+
+        fn input_count(&self) -> usize {
+            0
+        }
+
+        fn output_count(&self) -> usize {
+            1
+        }
+
+        fn set_input_at_index(
+            &self,
+            index: usize,
+            _input: Arc<dyn Effect>,
+        ) -> Result<(), EffectError> {
+            Err(EffectError::OutOfBounds(index))
+        }
+
+        fn get_input_at_index(&self, index: usize) -> Result<Arc<dyn Effect>, EffectError> {
+            Err(EffectError::OutOfBounds(index))
+        }
+
+        fn name(&self) -> &str {
+            "Counted"
+        }
+
+        fn get_waveform_plot_data(&self, _data: &mut SamplePlotData, _channel: &Channel) {}
+    }
+
+    #[test]
+    fn test_input_worked_out_once_per_block() {
+        let input = Arc::new(Counted::default());
+        let splitter: Arc<dyn Effect> = BandSplitter::new(200.0, 2000.0, input.clone());
+
+        let mut output = vec![0.0; 512];
+        for band in 0..3 {
+            output_of(&splitter, band).apply(&mut output, 0, 2);
+        }
+        assert_eq!(input.0.load(Ordering::Relaxed), 1);
+
+        for band in 0..3 {
+            output_of(&splitter, band).apply(&mut output, 256, 2);
+        }
+        assert_eq!(input.0.load(Ordering::Relaxed), 2);
+
+        // asking for the same block again is a new pass, which has to see the input as it is now
+        output_of(&splitter, 0).apply(&mut output, 0, 2);
+        assert_eq!(input.0.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn test_bands_add_back_up() {
+        let sine: Arc<dyn Effect> = Arc::new(SineWave::new(0.5, 440.0, 0.0));
+        let splitter: Arc<dyn Effect> = BandSplitter::new(200.0, 2000.0, sine.clone());
+
+        let mut sum = vec![0.0; 512];
+        for band in 0..3 {
+            let tap = output_of(&splitter, band);
+            let (source, index) = output_source(tap.clone());
+            assert!(Arc::ptr_eq(&source, &splitter));
+            assert_eq!(index, band);
+
+            let mut output = vec![0.0; 512];
+            tap.apply(&mut output, 0, 2);
+            sum.iter_mut().zip(output).for_each(|(s, o)| *s += o);
+        }
+
+        let mut expected = vec![0.0; 512];
+        sine.apply(&mut expected, 0, 2);
+        for (s, e) in sum.iter().zip(expected) {
+            assert!((s - e).abs() < 1e-5);
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use std::sync::{Arc, Weak};
+
+use crate::audio::effects::{Effect, EffectError, zero::Zero};
+use crate::audio::port::PortType;
+use crate::common::Channel;
+use crate::common::mipmapchannel::SamplePlotData;
+
+/// An effect with more than one output, each of which is taken through its own tap
+pub trait MultiOutput: Effect {
+    fn apply_output(&self, index: usize, output: &mut [f32], start_sample: usize, channels: usize);
+
+    fn output_plot_data(
+        &self,
+        index: usize,
+        sample_plot_data: &mut SamplePlotData,
+        channel: &Channel,
+    );
+}
+
+/// One output of an effect with several. Inputs are connected to the tap rather than the effect
+/// so they know which output they take. It only holds on to the effect weakly as the effect holds its taps
+pub struct OutputTap {
+    source: Weak<dyn MultiOutput>,
+    index: usize,
+}
+
+impl OutputTap {
+    pub fn new(source: Weak<dyn MultiOutput>, index: usize) -> Self {
+        Self { source, index }
+    }
+}
+
+impl Effect for OutputTap {
+    fn apply(&self, output: &mut [f32], start_sample: usize, channels: usize) {
+        match self.source.upgrade() {
+            // a bypassed or muted effect puts the same out of every output
+            Some(source) if source.switches().bypass || source.switches().mute => {
+                source.process(output, start_sample, channels)
+            }
+            Some(source) => source.apply_output(self.index, output, start_sample, channels),
+            None => Zero.apply(output, start_sample, channels),
+        }
+    }
+
+    fn input_count(&self) -> usize {
+        0
+    }
+
+    fn output_count(&self) -> usize {
+        1
+    }
+
+    fn set_input_at_index(&self, index: usize, _input: Arc<dyn Effect>) -> Result<(), EffectError> {
+        Err(EffectError::OutOfBounds(index))
+    }
+
+    fn get_input_at_index(&self, index: usize) -> Result<Arc<dyn Effect>, EffectError> {
+        Err(EffectError::OutOfBounds(index))
+    }
+
+    fn name(&self) -> &str {
+        "Output"
+    }
+
+    fn output_type(&self, _index: usize) -> PortType {
+        match self.source.upgrade() {
+            Some(source) => source.output_type(self.index),
+            None => PortType::STEREO,
+        }
+    }
+
+    fn tap_source(&self) -> Option<(Arc<dyn Effect>, usize)> {
+        let source: Arc<dyn Effect> = self.source.upgrade()?;
+        Some((source, self.index))
+    }
+
+    fn get_waveform_plot_data(&self, sample_plot_data: &mut SamplePlotData, channel: &Channel) {
+        match self.source.upgrade() {
+            Some(source) if source.switches().bypass || source.switches().mute => {
+                source.plot_data(sample_plot_data, channel)
+            }
+            Some(source) => source.output_plot_data(self.index, sample_plot_data, channel),
+            None => (),
+        }
+    }
+}
@@ -0,0 +1,50 @@
+//! Checks shared by the tests of effects that keep state from one block to the next
+
+use crate::audio::effects::Effect;
+
+/// An effect asked for a stretch in two blocks should give the same as when asked for it all at once.
+/// `make` gives a fresh effect each time so the two runs don't share any state.
+pub fn assert_blocks_carry_on<E: Effect>(
+    make: impl Fn() -> E,
+    start_sample: usize,
+    frames: usize,
+    channels: usize,
+    tolerance: f32,
+) {
+    let mut whole = vec![0.0; frames * channels];
+    make().apply(&mut whole, start_sample, channels);
+
+    let effect = make();
+    let mut halves = vec![0.0; frames * channels];
+    let (first, second) = halves.split_at_mut(frames / 2 * channels);
+    effect.apply(first, start_sample, channels);
+    effect.apply(second, start_sample + frames / 2, channels);
+
+    for (i, (a, b)) in whole.iter().zip(&halves).enumerate() {
+        assert!(
+            (a - b).abs() <= tolerance,
+            "sample {i} was {b} in blocks but {a} all at once"
+        );
+    }
+}
+
+/// An effect jumped straight to `at` should settle down to what it gives having played through from the start
+pub fn assert_relocation_matches<E: Effect>(
+    make: impl Fn() -> E,
+    at: usize,
+    frames: usize,
+    tolerance: f32,
+) {
+    let mut played = vec![0.0; at + frames];
+    make().apply(&mut played, 0, 1);
+
+    let mut jumped = vec![0.0; frames];
+    make().apply(&mut jumped, at, 1);
+
+    for (i, (a, b)) in played[at..].iter().zip(&jumped).enumerate() {
+        assert!(
+            (a - b).abs() <= tolerance,
+            "sample {i} was {b} after jumping but {a} played through"
+        );
+    }
+}
@@ -0,0 +1,491 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use eframe::egui::Ui;
+use eframe::egui::mutex::Mutex;
+use num_complex::Complex;
+
+use crate::audio::effects::{Effect, EffectError};
+use crate::audio::parameter::{Parameter, get_modulation_input, set_modulation_input};
+use crate::audio::streamstate::StreamState;
+use crate::audio::switches::SwitchState;
+use crate::common::mipmapchannel::SamplePlotData;
+use crate::common::{Channel, complex_fft};
+use crate::ui::nodegraph::GraphStyle;
+
+/// The length of each grain of input that gets overlapped
+const WINDOW: usize = 1024;
+/// How far apart the grains are laid down (half a window so the hann windows add up to one)
+const HOP: usize = WINDOW / 2;
+/// How far either side of where it should be a grain can be moved to line up with the last one
+const SEARCH: usize = 256;
+/// Big enough for the correlation of a grain over the whole search area without wrapping around
+const FFT_SIZE: usize = 2048;
+
+/// Where a grain was taken from in the input
+#[derive(Clone, Copy)]
+struct Grain {
+    index: usize,
+    /// Where it should have come from if the input was just played faster or slower
+    nominal: f64,
+    /// Where it actually came from after lining it up
+    start: isize,
+}
+
+#[derive(Clone)]
+struct StretchState {
+    channels: usize,
+    /// Input already pulled from upstream, starting at `buffer_start`
+    buffer: Vec<f32>,
+    buffer_start: isize,
+    /// The last few grains laid down
+    grains: Vec<Grain>,
+    /// Where the next output sample reads from in the stretched (but not yet pitched) signal
+    position: f64,
+}
+
+impl StretchState {
+    fn new(channels: usize, position: f64) -> Self {
+        Self {
+            channels,
+            buffer: vec![],
+            buffer_start: 0,
+            grains: vec![],
+            position,
+        }
+    }
+
+    fn buffer_end(&self) -> isize {
+        self.buffer_start + (self.buffer.len() / self.channels) as isize
+    }
+
+    fn sample(&self, at: isize, channel: usize) -> f32 {
+        self.buffer[(at - self.buffer_start) as usize * self.channels + channel]
+    }
+
+    /// Let go of the input from before anything the grains we kept could need
+    fn forget_old_input(&mut self) {
+        let keep_from = self
+            .grains
+            .iter()
+            .map(|g| g.start.min(g.nominal.round() as isize - SEARCH as isize))
+            .min()
+            .unwrap_or(self.buffer_start);
+
+        if keep_from > self.buffer_start {
+            let drop =
+                ((keep_from - self.buffer_start) as usize * self.channels).min(self.buffer.len());
+            self.buffer.drain(..drop);
+            self.buffer_start += (drop / self.channels) as isize;
+        }
+    }
+}
+
+fn hann(i: usize) -> f32 {
+    0.5 - 0.5 * (2.0 * PI * i as f32 / WINDOW as f32).cos()
+}
+
+/// Changes the speed and the pitch of its input independently,
+/// by laying down overlapping grains of the input that are lined up to join smoothly (WSOLA)
+/// and then resampling the result to move the pitch.
+pub struct TimeStretch {
+    tempo: Parameter,
+    semitones: Parameter,
+    input: Mutex<Arc<dyn Effect>>,
+    states: StreamState<StretchState>,
+    switches: SwitchState,
+}
+
+impl TimeStretch {
+    pub fn new(tempo: f32, semitones: f32, input: Arc<dyn Effect>) -> Self {
+        Self {
+            tempo: Parameter::new("tempo", tempo, 0.25..=4.0).logarithmic(true),
+            semitones: Parameter::new("semitones", semitones, -24.0..=24.0),
+            input: Mutex::new(input),
+            states: StreamState::default(),
+            switches: SwitchState::default(),
+        }
+    }
+
+    /// Make sure the input between lo and hi has been pulled, only ever asking upstream for what follows on
+    /// from what it was last asked for so it doesn't think it has been moved
+    fn fetch(&self, state: &mut StretchState, lo: isize, hi: isize) {
+        let channels = state.channels;
+        let pull = |from: isize, to: isize| {
+            let mut data = vec![0.0; (to - from).max(0) as usize * channels];
+            let start = from.max(0);
+            if to > start {
+                self.input.lock().process(
+                    &mut data[(start - from) as usize * channels..],
+                    start as usize,
+                    channels,
+                );
+            }
+            data
+        };
+
+        if state.buffer.is_empty() || lo < state.buffer_start {
+            state.buffer = pull(lo, hi);
+            state.buffer_start = lo;
+        } else if hi > state.buffer_end() {
+            let more = pull(state.buffer_end(), hi);
+            state.buffer.extend(more);
+        }
+    }
+
+    /// Find where near `nominal` a grain best carries on from the input just after the last grain
+    fn best_start(&self, state: &mut StretchState, continuation: isize, nominal: isize) -> isize {
+        let lo = nominal - SEARCH as isize;
+        self.fetch(
+            state,
+            lo.min(continuation),
+            (nominal + (SEARCH + WINDOW) as isize).max(continuation + WINDOW as isize),
+        );
+
+        // mixed down to mono so every channel moves together
+        let mono = |at: isize| {
+            (0..state.channels)
+                .map(|c| state.sample(at, c))
+                .sum::<f32>()
+        };
+        let mut target = vec![Complex::ZERO; FFT_SIZE];
+        for (i, t) in target.iter_mut().take(WINDOW).enumerate() {
+            t.re = mono(continuation + i as isize);
+        }
+        let mut region = vec![Complex::ZERO; FFT_SIZE];
+        for (i, r) in region.iter_mut().take(WINDOW + 2 * SEARCH).enumerate() {
+            r.re = mono(lo + i as isize);
+        }
+
+        let mut target_freqs = vec![Complex::ZERO; FFT_SIZE];
+        let mut region_freqs = vec![Complex::ZERO; FFT_SIZE];
+        complex_fft(&target, &mut target_freqs, false);
+        complex_fft(&region, &mut region_freqs, false);
+
+        let product = region_freqs
+            .iter()
+            .zip(&target_freqs)
+            .map(|(r, t)| r * t.conj())
+            .collect::<Vec<_>>();
+        let mut correlation = vec![Complex::ZERO; FFT_SIZE];
+        complex_fft(&product, &mut correlation, true);
+
+        let best = (0..=2 * SEARCH)
+            .max_by(|&a, &b| correlation[a].re.total_cmp(&correlation[b].re))
+            .unwrap_or(SEARCH);
+        lo + best as isize
+    }
+
+    /// The starts of the grains from `first` to `last`, carrying on from the ones already laid down where we can
+    fn grains(
+        &self,
+        state: &mut StretchState,
+        first: usize,
+        last: usize,
+        alpha: f64,
+    ) -> Vec<isize> {
+        let mut grain = match state.grains.iter().find(|g| g.index == first) {
+            Some(grain) => *grain,
+            None => {
+                // We have been moved, so start again from where we should be without lining anything up
+                let nominal = first as f64 * HOP as f64 * alpha;
+                Grain {
+                    index: first,
+                    nominal,
+                    start: nominal.round() as isize,
+                }
+            }
+        };
+        self.fetch(state, grain.start, grain.start + WINDOW as isize);
+
+        let mut laid = vec![grain];
+        for index in first + 1..=last {
+            grain = match state.grains.iter().find(|g| g.index == index) {
+                Some(grain) => *grain,
+                None => {
+                    let nominal = grain.nominal + HOP as f64 * alpha;
+                    let start = self.best_start(
+                        state,
+                        grain.start + HOP as isize,
+                        nominal.round() as isize,
+                    );
+                    Grain {
+                        index,
+                        nominal,
+                        start,
+                    }
+                }
+            };
+            laid.push(grain);
+        }
+
+        let starts = laid.iter().map(|g| g.start).collect();
+
+        // only the last couple are needed to carry on
+        state.grains = laid.split_off(laid.len().saturating_sub(2));
+        starts
+    }
+}
+
+impl Effect for TimeStretch {
+    fn apply(&self, output: &mut [f32], start_sample: usize, channels: usize) {
+        let frames = output.len() / channels;
+        if frames == 0 {
+            return;
+        }
+
+        // read once a block, they can't sensibly change faster than a grain anyway
+        let tempo = self.tempo.values(start_sample, 1)[0] as f64;
+        let pitch = 2f64.powf(self.semitones.values(start_sample, 1)[0] as f64 / 12.0);
+        // how far through the input we go for each sample of the stretched signal
+        let alpha = tempo / pitch;
+
+        let mut state = match self.states.take(start_sample) {
+            Some(state) if state.channels == channels => state,
+            _ => StretchState::new(channels, start_sample as f64 * pitch),
+        };
+
+        // the stretched signal between these is needed to resample from
+        let lo = state.position.floor() as usize;
+        let hi = (state.position + (frames - 1) as f64 * pitch).floor() as usize + 1;
+
+        let first = (lo / HOP).saturating_sub(1);
+        let starts = self.grains(&mut state, first, hi / HOP, alpha);
+
+        // overlap the grains, each sample made of the back half of one and the front half of the next
+        let mut stretched = vec![0.0; (hi - lo + 1) * channels];
+        for (m, frame) in (lo..=hi).zip(stretched.chunks_mut(channels)) {
+            let k = m / HOP - first;
+            let j = m % HOP;
+            for (c, s) in frame.iter_mut().enumerate() {
+                *s = hann(j) * state.sample(starts[k] + j as isize, c);
+                if k > 0 {
+                    *s += hann(j + HOP) * state.sample(starts[k - 1] + (j + HOP) as isize, c);
+                }
+            }
+        }
+
+        for (n, frame) in output.chunks_mut(channels).enumerate() {
+            let at = state.position + n as f64 * pitch - lo as f64;
+            let i = at as usize;
+            let t = (at - i as f64) as f32;
+            for (c, f) in frame.iter_mut().enumerate() {
+                let a = stretched[i * channels + c];
+                let b = stretched[(i + 1).min(hi - lo) * channels + c];
+                *f = a + (b - a) * t;
+            }
+        }
+
+        state.position += frames as f64 * pitch;
+        state.forget_old_input();
+        self.states.store(start_sample + frames, state);
+    }
+
+    fn input_count(&self) -> usize {
+        1 + self.parameters().len()
+    }
+
+    fn output_count(&self) -> usize {
+        1
+    }
+
+    fn set_input_at_index(&self, index: usize, input: Arc<dyn Effect>) -> Result<(), EffectError> {
+        match index {
+            0 => {
+                *self.input.lock() = input;
+                Ok(())
+            }
+            _ => set_modulation_input(&self.parameters(), index, 1, input),
+        }
+    }
+
+    fn get_input_at_index(&self, index: usize) -> Result<Arc<dyn Effect>, EffectError> {
+        match index {
+            0 => Ok(self.input.lock().clone()),
+            _ => get_modulation_input(&self.parameters(), index, 1),
+        }
+    }
+
+    fn name(&self) -> &str {
+        "Time Stretch"
+    }
+
+    fn effect_id(&self) -> Option<&'static str> {
+        Some("time_stretch")
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![&self.tempo, &self.semitones]
+    }
+
+    fn data_ui(&self, ui: &mut Ui, _style: &GraphStyle) {
+        self.tempo.ui(ui);
+        self.semitones.ui(ui);
+    }
+
+    /// The input spread out (or squashed up) in time, the pitch can't be seen from this far out
+    fn get_waveform_plot_data(&self, sample_plot_data: &mut SamplePlotData, channel: &Channel) {
+        let tempo = self.tempo.get() as f64;
+        let (start_sample, step) = (sample_plot_data.start_sample, sample_plot_data.step);
+        let width = sample_plot_data.data[0].len();
+
+        // each point covers step * tempo of the input, which is read in the largest power of two
+        // (as the mipmaps only have those) that fits, then gathered back up into points
+        let covered = step as f64 * tempo;
+        let read_step = 1usize << (covered.max(1.0) as usize).ilog2();
+        let read_start = (start_sample as f64 * tempo) as usize / read_step * read_step;
+        let read_width = (width as f64 * covered / read_step as f64) as usize + 2;
+        let mut read = SamplePlotData::new(read_step, read_start, read_width);
+        self.input.lock().plot_data(&mut read, channel);
+
+        sample_plot_data.is_min_max = read.is_min_max;
+        for i in 0..width {
+            let from = (start_sample + i * step) as f64 * tempo - read_start as f64;
+            let first = ((from / read_step as f64) as usize).min(read_width - 1);
+            let last = (((from + covered) / read_step as f64).ceil() as usize)
+                .clamp(first + 1, read_width);
+
+            match read.is_min_max {
+                true => {
+                    let points = first..last;
+                    sample_plot_data.data[0][i] = read.data[0][points.clone()]
+                        .iter()
+                        .fold(f32::MAX, |a, b| a.min(*b));
+                    sample_plot_data.data[1][i] =
+                        read.data[1][points].iter().fold(f32::MIN, |a, b| a.max(*b));
+                }
+                // just samples, so the one at the start of the point
+                false => {
+                    sample_plot_data.data[0][i] = read.data[0][first];
+                    sample_plot_data.data[1][i] = read.data[1][first];
+                }
+            }
+        }
+    }
+
+    fn switch_state(&self) -> Option<&SwitchState> {
+        Some(&self.switches)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audio::effects::sinewave::SineWave;
+    use crate::audio::effects::testing::assert_blocks_carry_on;
+    use crate::common::SAMPLE_RATE;
+    use crate::common::mipmapchannel::MipMapChannel;
+    use crate::common::track::Track;
+    use symphonia::core::codecs::CodecParameters;
+
+    fn crossings(samples: &[f32]) -> usize {
+        samples
+            .windows(2)
+            .filter(|w| (w[0] < 0.0) != (w[1] < 0.0))
+            .count()
+    }
+
+    fn stretched(tempo: f32, semitones: f32) -> Vec<f32> {
+        let sine = Arc::new(SineWave::new(0.5, 440.0, 0.0));
+        let s = TimeStretch::new(tempo, semitones, sine);
+
+        let mut output = vec![0.0; 9600];
+        s.apply(&mut output, 48000, 1);
+        output
+    }
+
+    #[test]
+    fn test_tempo_keeps_pitch() {
+        // 440Hz crosses zero 88 times every 4800 samples
+        for tempo in [0.5, 1.0, 1.5] {
+            let output = stretched(tempo, 0.0);
+            let count = crossings(&output[4800..]);
+            assert!(count.abs_diff(88) <= 3, "{tempo} gave {count} crossings");
+        }
+    }
+
+    #[test]
+    fn test_semitones_move_pitch() {
+        let output = stretched(1.0, 12.0);
+        let count = crossings(&output[4800..]);
+        assert!(
+            count.abs_diff(176) <= 4,
+            "an octave up gave {count} crossings"
+        );
+
+        let output = stretched(1.0, -12.0);
+        let count = crossings(&output[4800..]);
+        assert!(
+            count.abs_diff(44) <= 3,
+            "an octave down gave {count} crossings"
+        );
+    }
+
+    #[test]
+    fn test_tempo_sets_length_not_pitch() {
+        // a fifth of a second of 440Hz, which crosses zero 44 times every 2400 samples
+        let length = 9600;
+        let mut parameters = CodecParameters::new();
+        parameters
+            .with_n_frames(length as u64)
+            .with_sample_rate(48000);
+        let data = (0..length)
+            .map(|i| 0.5 * (2.0 * PI * 440.0 * i as f32 / SAMPLE_RATE).sin())
+            .collect::<Vec<_>>();
+        let track = Arc::new(Track::new(
+            None,
+            parameters,
+            MipMapChannel::new(data.clone(), 5),
+            MipMapChannel::new(data, 5),
+        ));
+
+        for (tempo, semitones, expected) in [
+            (0.5, 0.0, 44),
+            (1.0, 0.0, 44),
+            (2.0, 0.0, 44),
+            (1.0, 12.0, 88),
+        ] {
+            let s = TimeStretch::new(tempo, semitones, track.clone());
+            let mut output = vec![0.0; 4 * length];
+            s.apply(&mut output, 0, 1);
+
+            let sounding = output.iter().rposition(|s| s.abs() > 0.05).unwrap_or(0) + 1;
+            let stretched = (length as f32 / tempo) as usize;
+            assert!(
+                sounding.abs_diff(stretched) <= WINDOW,
+                "{tempo}x lasted {sounding} rather than {stretched}"
+            );
+
+            let count = crossings(&output[512..2912]);
+            assert!(
+                count.abs_diff(expected) <= 3,
+                "{tempo}x at {semitones} semitones gave {count} crossings"
+            );
+        }
+    }
+
+    #[test]
+    fn test_blocks_carry_on() {
+        let stretch = || {
+            let sine = Arc::new(SineWave::new(0.5, 440.0, 0.0));
+            TimeStretch::new(0.8, 3.0, sine)
+        };
+        assert_blocks_carry_on(stretch, 10000, 2048, 2, 1e-5);
+    }
+
+    #[test]
+    fn test_plot_spread_out() {
+        // slow enough that where in a point it is read from hardly matters
+        let sine: Arc<dyn Effect> = Arc::new(SineWave::new(0.5, 5.0, 0.0));
+        let s = TimeStretch::new(1.5, 0.0, sine.clone());
+        let mut plot = SamplePlotData::new(64, 4800, 100);
+        s.get_waveform_plot_data(&mut plot, &Channel::Left);
+
+        // every sample of the plot is one and a half of the input's
+        let mut expected = SamplePlotData::new(96, 7200, 100);
+        sine.get_waveform_plot_data(&mut expected, &Channel::Left);
+        for (p, e) in plot.data[0].iter().zip(&expected.data[0]) {
+            assert!((p - e).abs() < 0.02, "{p} should be {e}");
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use std::sync::Arc;
+
+use eframe::egui::Ui;
+use eframe::egui::mutex::Mutex;
+
+use crate::audio::effects::{Effect, EffectError};
+use crate::audio::parameter::{Parameter, get_modulation_input, set_modulation_input};
+use crate::audio::switches::SwitchState;
+use crate::common::Channel;
+use crate::common::mipmapchannel::SamplePlotData;
+use crate::ui::nodegraph::GraphStyle;
+
+/// Narrow or widen the stereo image by scaling the side (left - right) signal against the mid (left + right).
+/// A width of 0 is mono, 1 leaves the signal untouched and 2 doubles the side signal.
+pub struct StereoWidth {
+    width: Parameter,
+    input: Mutex<Arc<dyn Effect>>,
+    switches: SwitchState,
+}
+
+impl StereoWidth {
+    pub fn new(width: f32, input: Arc<dyn Effect>) -> Self {
+        Self {
+            width: Parameter::new("width", width, 0.0..=2.0),
+            input: Mutex::new(input),
+            switches: SwitchState::default(),
+        }
+    }
+
+    pub fn width(&self) -> f32 {
+        self.width.get()
+    }
+}
+
+/// The (same side, opposite side) coefficients, as
+/// left' = mid + width * side = a * left + b * right (and the same for right with the roles swapped)
+fn coefficients(width: f32) -> (f32, f32) {
+    ((1.0 + width) / 2.0, (1.0 - width) / 2.0)
+}
+
+impl Effect for StereoWidth {
+    fn apply(&self, output: &mut [f32], start_sample: usize, channels: usize) {
+        self.input.lock().process(output, start_sample, channels);
+
+        if channels < 2 {
+            return;
+        }
+
+        let widths = self.width.values(start_sample, output.len() / channels);
+        for (frame, width) in output.chunks_mut(channels).zip(widths) {
+            let (a, b) = coefficients(width);
+            let (left, right) = (frame[0], frame[1]);
+            frame[0] = a * left + b * right;
+            frame[1] = a * right + b * left;
+        }
+    }
+
+    fn input_count(&self) -> usize {
+        1 + self.parameters().len()
+    }
+
+    fn output_count(&self) -> usize {
+        1
+    }
+
+    fn set_input_at_index(&self, index: usize, input: Arc<dyn Effect>) -> Result<(), EffectError> {
+        match index {
+            0 => {
+                *self.input.lock() = input;
+                Ok(())
+            }
+            _ => set_modulation_input(&self.parameters(), index, 1, input),
+        }
+    }
+
+    fn get_input_at_index(&self, index: usize) -> Result<Arc<dyn Effect>, EffectError> {
+        match index {
+            0 => Ok(self.input.lock().clone()),
+            _ => get_modulation_input(&self.parameters(), index, 1),
+        }
+    }
+
+    fn name(&self) -> &str {
+        "Stereo Width"
+    }
+
+    fn effect_id(&self) -> Option<&'static str> {
+        Some("stereo_width")
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![&self.width]
+    }
+
+    fn data_ui(&self, ui: &mut Ui, _style: &GraphStyle) {
+        self.width.ui(ui);
+    }
+
+    fn get_waveform_plot_data(&self, sample_plot_data: &mut SamplePlotData, channel: &Channel) {
+        let other_channel = match channel {
+            Channel::Left => Channel::Right,
+            Channel::Right => Channel::Left,
+        };
+
+        let mut other_plot_data = SamplePlotData::new(
+            sample_plot_data.step,
+            sample_plot_data.start_sample,
+            sample_plot_data.data[0].len(),
+        );
+
+        let input = self.input.lock().clone();
+        input.plot_data(sample_plot_data, channel);
+        input.plot_data(&mut other_plot_data, &other_channel);
+
+        let (a, b) = coefficients(self.width());
+        sample_plot_data.mix(&other_plot_data, a, b);
+    }
+
+    fn switch_state(&self) -> Option<&SwitchState> {
+        Some(&self.switches)
+    }
+}
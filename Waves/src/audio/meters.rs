@@ -0,0 +1,249 @@
+use std::cell::{Cell, RefCell};
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant};
+
+use crate::audio::effects::Effect;
+use crate::common::SAMPLE_RATE;
+
+/// How long the levels and times are averaged over, in seconds
+const SMOOTHING: f32 = 0.3;
+/// How fast the peak falls back, in dB a second
+const PEAK_FALL: f32 = 20.0;
+/// How long a meter is kept once its effect stops being played
+const STALE: Duration = Duration::from_millis(500);
+/// How many effects can be metered at once
+const SLOT_COUNT: usize = 1024;
+
+/// How loud an effect's output is and how long the effect takes, as measured while playing
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct Meter {
+    pub peak: f32,
+    pub rms: f32,
+    /// Seconds spent in the effect itself (not its inputs) for each block
+    pub time: f32,
+    /// The time as a fraction of how long the block lasts
+    pub load: f32,
+}
+
+impl Meter {
+    /// Fold in a block of output that took time to work out
+    fn update(&mut self, output: &[f32], channels: usize, time: Duration) {
+        let frames = output.len() / channels.max(1);
+        if frames == 0 {
+            return;
+        }
+        let seconds = frames as f32 / SAMPLE_RATE;
+        let peak = output.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        let rms = (output.iter().map(|s| s * s).sum::<f32>() / output.len() as f32).sqrt();
+        let time = time.as_secs_f32();
+
+        let a = 1.0 - (-seconds / SMOOTHING).exp();
+        self.peak = peak.max(self.peak * 10.0f32.powf(-PEAK_FALL * seconds / 20.0));
+        self.rms += a * (rms - self.rms);
+        self.time += a * (time - self.time);
+        self.load += a * (time / seconds - self.load);
+    }
+
+    /// Put together the meters of the outputs of one effect
+    fn merge(self, other: Self) -> Self {
+        Self {
+            peak: self.peak.max(other.peak),
+            rms: self.rms.max(other.rms),
+            time: self.time + other.time,
+            load: self.load + other.load,
+        }
+    }
+}
+
+/// The meter of one effect, written by the audio thread and read by the ui without either waiting on the other
+struct Slot {
+    /// The address of the effect, zero while the slot has never been used
+    address: AtomicUsize,
+    /// One past the start sample of the block last measured, so an effect taken from twice in a block counts once
+    block: AtomicUsize,
+    /// Microseconds since EPOCH that it was last measured
+    updated: AtomicU64,
+    /// The bits of the meter's peak, rms, time and load
+    meter: [AtomicU32; 4],
+}
+
+impl Slot {
+    const fn new() -> Self {
+        Self {
+            address: AtomicUsize::new(0),
+            block: AtomicUsize::new(0),
+            updated: AtomicU64::new(0),
+            meter: [const { AtomicU32::new(0) }; 4],
+        }
+    }
+
+    fn is_stale(&self) -> bool {
+        Duration::from_micros(self.updated.load(Ordering::Relaxed)) + STALE < EPOCH.elapsed()
+    }
+
+    fn meter(&self) -> Meter {
+        let [peak, rms, time, load] = self
+            .meter
+            .each_ref()
+            .map(|bits| f32::from_bits(bits.load(Ordering::Relaxed)));
+        Meter {
+            peak,
+            rms,
+            time,
+            load,
+        }
+    }
+
+    fn set_meter(&self, meter: Meter) {
+        let values = [meter.peak, meter.rms, meter.time, meter.load];
+        for (bits, value) in self.meter.iter().zip(values) {
+            bits.store(value.to_bits(), Ordering::Relaxed);
+        }
+        let updated = EPOCH.elapsed().as_micros() as u64;
+        self.updated.store(updated, Ordering::Relaxed);
+    }
+}
+
+/// The meters, found by the address of their effect starting from where it hashes to.
+/// Only the audio thread takes slots, so no more than one thread ever writes to them
+static SLOTS: [Slot; SLOT_COUNT] = [const { Slot::new() }; SLOT_COUNT];
+static EPOCH: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// The slots in the order they are looked through for an address
+fn probe(address: usize) -> impl Iterator<Item = &'static Slot> {
+    let start = (address >> 4) % SLOT_COUNT;
+    (0..SLOT_COUNT).map(move |i| &SLOTS[(start + i) % SLOT_COUNT])
+}
+
+/// The slot that has the address, which takes over an unused or stale slot if there isn't one yet
+fn slot_for(address: usize) -> Option<&'static Slot> {
+    let mut free = None;
+    for slot in probe(address) {
+        match slot.address.load(Ordering::Relaxed) {
+            a if a == address => return Some(slot),
+            0 => {
+                free = free.or(Some(slot));
+                break;
+            }
+            _ if free.is_none() && slot.is_stale() => free = Some(slot),
+            _ => (),
+        }
+    }
+
+    let slot = free?;
+    slot.set_meter(Meter::default());
+    slot.block.store(0, Ordering::Relaxed);
+    slot.address.store(address, Ordering::Release);
+    Some(slot)
+}
+
+thread_local! {
+    /// Whether the effects being applied are being played, rather than worked out for a plot
+    static METERING: Cell<bool> = const { Cell::new(false) };
+    /// For each effect being applied, the time taken so far by its inputs
+    static INPUT_TIMES: RefCell<Vec<Duration>> = const { RefCell::new(vec![]) };
+}
+
+/// Play through f, measuring every effect that is processed
+pub fn metering<R>(f: impl FnOnce() -> R) -> R {
+    METERING.set(true);
+    let result = f();
+    METERING.set(false);
+    result
+}
+
+/// Run apply on output for the effect at the address, timing it if it is being played
+pub fn measure(
+    address: *const (),
+    output: &mut [f32],
+    start_sample: usize,
+    channels: usize,
+    apply: impl FnOnce(&mut [f32]),
+) {
+    if !METERING.get() {
+        apply(output);
+        return;
+    }
+
+    INPUT_TIMES.with_borrow_mut(|times| times.push(Duration::ZERO));
+    let start = Instant::now();
+    apply(output);
+    let elapsed = start.elapsed();
+    let inputs = INPUT_TIMES.with_borrow_mut(|times| {
+        let inputs = times.pop().unwrap_or_default();
+        // this all counts as time taken by the inputs of whatever asked for it
+        if let Some(parent) = times.last_mut() {
+            *parent += elapsed;
+        }
+        inputs
+    });
+
+    let Some(slot) = slot_for(address as usize) else {
+        return;
+    };
+    if slot.block.swap(start_sample + 1, Ordering::Relaxed) == start_sample + 1 {
+        return;
+    }
+    let mut meter = slot.meter();
+    meter.update(output, channels, elapsed.saturating_sub(inputs));
+    slot.set_meter(meter);
+}
+
+/// The meter of an effect and any outputs it has of its own, if it has been played just now
+pub fn of(effect: &Arc<dyn Effect>) -> Option<Meter> {
+    let meter = |effect: &Arc<dyn Effect>| {
+        let address = Arc::as_ptr(effect).cast::<()>() as usize;
+        probe(address)
+            .take_while(|slot| slot.address.load(Ordering::Acquire) != 0)
+            .find(|slot| slot.address.load(Ordering::Acquire) == address)
+            .filter(|slot| !slot.is_stale())
+            .map(Slot::meter)
+    };
+
+    (0..effect.output_count())
+        .filter_map(|i| effect.output_at_index(i))
+        .filter_map(|tap| meter(&tap))
+        .fold(meter(effect), |total, m| match total {
+            Some(total) => Some(total.merge(m)),
+            None => Some(m),
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audio::effects::{gain::Gain, sinewave::SineWave};
+    use crate::common::dB;
+
+    #[test]
+    fn test_meters() {
+        let sine: Arc<dyn Effect> = Arc::new(SineWave::new(0.5, 440.0, 0.0));
+        let gain: Arc<dyn Effect> = Arc::new(Gain::new(dB(-6.0), sine.clone()));
+
+        let mut output = vec![0.0; 4800];
+        gain.process(&mut output, 0, 2);
+        assert_eq!(of(&gain), None, "plots aren't measured");
+
+        metering(|| gain.process(&mut output, 0, 2));
+        let loud = of(&sine).unwrap();
+        let quiet = of(&gain).unwrap();
+        assert!((loud.peak - 0.5).abs() < 1e-3);
+        assert!((quiet.peak - 0.5 * dB(-6.0).to_amplitude()).abs() < 1e-3);
+        assert!(quiet.rms < loud.rms);
+    }
+
+    #[test]
+    fn test_measured_once_a_block() {
+        let once: Arc<dyn Effect> = Arc::new(SineWave::new(0.5, 440.0, 0.0));
+        let twice: Arc<dyn Effect> = Arc::new(SineWave::new(0.5, 440.0, 0.0));
+
+        let mut output = vec![0.0; 4800];
+        metering(|| {
+            once.process(&mut output, 4800, 2);
+            twice.process(&mut output, 4800, 2);
+            twice.process(&mut output, 4800, 2);
+        });
+        assert_eq!(of(&once).unwrap().rms, of(&twice).unwrap().rms);
+    }
+}
@@ -0,0 +1,301 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::ops::{Deref, RangeInclusive};
+use std::sync::Arc;
+
+use eframe::egui::mutex::Mutex;
+use eframe::egui::{Slider, Ui};
+
+use crate::audio::effects::{Effect, EffectError, zero::Zero};
+
+/// How many samples pass between a modulated parameter being worked out again
+pub const CONTROL_BLOCK: usize = 32;
+
+thread_local! {
+    /// Buffers that values have been worked out in, kept once they've been used so playing needn't allocate
+    static POOL: RefCell<Vec<Vec<f32>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A parameter's values for a run of frames, in a buffer that goes back to the pool once dropped
+pub struct Values(Vec<f32>);
+
+impl Values {
+    fn new(frames: usize) -> Self {
+        let mut buffer = POOL.with_borrow_mut(|pool| pool.pop()).unwrap_or_default();
+        buffer.clear();
+        buffer.resize(frames, 0.0);
+        Self(buffer)
+    }
+}
+
+impl Deref for Values {
+    type Target = [f32];
+
+    fn deref(&self) -> &[f32] {
+        &self.0
+    }
+}
+
+impl Drop for Values {
+    fn drop(&mut self) {
+        let buffer = std::mem::take(&mut self.0);
+        // the thread might be going away, in which case there's no pool to go back to
+        let _ = POOL.try_with(|pool| pool.borrow_mut().push(buffer));
+    }
+}
+
+impl IntoIterator for Values {
+    type Item = f32;
+    type IntoIter = ValuesIter;
+
+    fn into_iter(self) -> ValuesIter {
+        ValuesIter {
+            values: self,
+            index: 0,
+        }
+    }
+}
+
+/// Goes through the values one frame at a time
+pub struct ValuesIter {
+    values: Values,
+    index: usize,
+}
+
+impl Iterator for ValuesIter {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let value = *self.values.get(self.index)?;
+        self.index += 1;
+        Some(value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.values.len() - self.index;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for ValuesIter {}
+
+/// What a parameter is, without its value, for listing what each kind of effect has
+#[derive(PartialEq, Debug, Clone)]
+pub struct ParameterSchema {
+    pub name: &'static str,
+    pub default: f32,
+    pub range: RangeInclusive<f32>,
+    pub logarithmic: bool,
+}
+
+/// A numeric setting of an effect.
+/// Each one has an input that another effect (eg an LFO) can be plugged into to move it around its set value.
+pub struct Parameter {
+    name: &'static str,
+    range: RangeInclusive<f32>,
+    logarithmic: bool,
+    value: Mutex<f32>,
+    /// How far the parameter moves for a modulation signal of 1
+    depth: Mutex<f32>,
+    modulation: Mutex<Arc<dyn Effect>>,
+}
+
+impl Parameter {
+    pub fn new(name: &'static str, value: f32, range: RangeInclusive<f32>) -> Self {
+        Self {
+            name,
+            value: Mutex::new(value),
+            range,
+            logarithmic: false,
+            depth: Mutex::new(0.0),
+            modulation: Mutex::new(Arc::new(Zero)),
+        }
+    }
+
+    pub fn logarithmic(mut self, logarithmic: bool) -> Self {
+        self.logarithmic = logarithmic;
+        self
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The description of the parameter, taking its current value as the default
+    pub fn schema(&self) -> ParameterSchema {
+        ParameterSchema {
+            name: self.name,
+            default: self.get(),
+            range: self.range.clone(),
+            logarithmic: self.logarithmic,
+        }
+    }
+
+    /// The value as set by hand, without any modulation
+    pub fn get(&self) -> f32 {
+        *self.value.lock()
+    }
+
+    pub fn set(&self, value: f32) {
+        *self.value.lock() = value;
+    }
+
+    pub fn depth(&self) -> f32 {
+        *self.depth.lock()
+    }
+
+    pub fn set_depth(&self, depth: f32) {
+        *self.depth.lock() = depth;
+    }
+
+    pub fn modulation(&self) -> Arc<dyn Effect> {
+        self.modulation.lock().clone()
+    }
+
+    pub fn set_modulation(&self, modulation: Arc<dyn Effect>) {
+        *self.modulation.lock() = modulation;
+    }
+
+    pub fn is_modulated(&self) -> bool {
+        !(&*self.modulation() as &dyn Any).is::<Zero>()
+    }
+
+    /// The value of the parameter for each of the frames starting at start_sample.
+    /// The modulation is only read every CONTROL_BLOCK samples and ramped in between.
+    pub fn values(&self, start_sample: usize, frames: usize) -> Values {
+        let mut values = Values::new(frames);
+        let value = self.get();
+        if !self.is_modulated() || frames == 0 {
+            values.0.fill(value);
+            return values;
+        }
+
+        // the ramps are written over the signal, each block only once the start of the next has been read
+        let signal = &mut values.0;
+        self.modulation().process(signal, start_sample, 1);
+
+        let depth = self.depth();
+        let (min, max) = (*self.range.start(), *self.range.end());
+        let at = |signal: f32| (value + depth * signal).clamp(min, max);
+
+        for block_start in (0..frames).step_by(CONTROL_BLOCK) {
+            // ramp towards the start of the next block, or the last frame if there is none
+            let block_end = (block_start + CONTROL_BLOCK).min(frames - 1);
+            let (from, to) = (at(signal[block_start]), at(signal[block_end]));
+            let ramp_len = (block_end - block_start).max(1) as f32;
+
+            let block_len = (block_start + CONTROL_BLOCK).min(frames) - block_start;
+            for (i, v) in signal[block_start..block_start + block_len]
+                .iter_mut()
+                .enumerate()
+            {
+                *v = from + (to - from) * i as f32 / ramp_len;
+            }
+        }
+
+        values
+    }
+
+    /// A slider for the value, and one for the depth if something is plugged in
+    pub fn ui(&self, ui: &mut Ui) {
+        ui.add(
+            Slider::new(&mut *self.value.lock(), self.range.clone())
+                .logarithmic(self.logarithmic)
+                .text(self.name),
+        );
+
+        if self.is_modulated() {
+            let span = self.range.end() - self.range.start();
+            ui.add(Slider::new(&mut *self.depth.lock(), -span..=span).text("depth"));
+        }
+    }
+}
+
+/// For effects to share: the modulation inputs come straight after the audio_inputs,
+/// one for each parameter in order
+pub fn get_modulation_input(
+    parameters: &[&Parameter],
+    index: usize,
+    audio_inputs: usize,
+) -> Result<Arc<dyn Effect>, EffectError> {
+    match index
+        .checked_sub(audio_inputs)
+        .and_then(|i| parameters.get(i))
+    {
+        Some(parameter) => Ok(parameter.modulation()),
+        None => Err(EffectError::OutOfBounds(index)),
+    }
+}
+
+pub fn set_modulation_input(
+    parameters: &[&Parameter],
+    index: usize,
+    audio_inputs: usize,
+    input: Arc<dyn Effect>,
+) -> Result<(), EffectError> {
+    match index
+        .checked_sub(audio_inputs)
+        .and_then(|i| parameters.get(i))
+    {
+        Some(parameter) => {
+            parameter.set_modulation(input);
+            Ok(())
+        }
+        None => Err(EffectError::OutOfBounds(index)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audio::effects::lfo::{Lfo, LfoShape};
+
+    #[test]
+    fn test_unmodulated_is_constant() {
+        let p = Parameter::new("gain", 2.0, -18.0..=6.0);
+        assert!(p.values(0, 100).iter().all(|&v| v == 2.0));
+    }
+
+    #[test]
+    fn test_modulation_is_control_rate() {
+        let p = Parameter::new("gain", 0.0, -18.0..=6.0);
+        p.set_modulation(Arc::new(Lfo::new(LfoShape::Square, 1.0)));
+        p.set_depth(3.0);
+
+        let values = p.values(0, 256);
+
+        // a square lfo at 1Hz is high for the whole first half second
+        assert!(values.iter().all(|&v| v == 3.0));
+
+        // Just before the square flips the ramp should carry on through each block
+        let values = p.values(24000 - 16, CONTROL_BLOCK);
+        assert_eq!(values[0], 3.0);
+        assert!(values[CONTROL_BLOCK - 1] < 0.0);
+    }
+
+    #[test]
+    fn test_clamped_to_range() {
+        let p = Parameter::new("gain", 5.0, -18.0..=6.0);
+        p.set_modulation(Arc::new(Lfo::new(LfoShape::Square, 1.0)));
+        p.set_depth(10.0);
+
+        assert!(p.values(0, 64).iter().all(|&v| v == 6.0));
+    }
+
+    #[test]
+    fn test_buffers_reused() {
+        let p = Parameter::new("gain", 0.0, -18.0..=6.0);
+        p.set_modulation(Arc::new(Lfo::new(LfoShape::Sine, 1.0)));
+
+        let buffer = p.values(0, 512).as_ptr();
+        let again = p.values(512, 512).as_ptr();
+        assert_eq!(
+            buffer, again,
+            "the first buffer should have gone back to be used again"
+        );
+
+        // both in use at once can't share
+        let (a, b) = (p.values(0, 64), p.values(64, 64));
+        assert_ne!(a.as_ptr(), b.as_ptr());
+    }
+}
@@ -0,0 +1,134 @@
+use std::fmt::Display;
+use std::sync::Arc;
+
+use crate::audio::effects::{
+    Effect,
+    channels::{ChannelMode, ChannelUtility},
+    envelope::EnvelopeFollower,
+};
+
+/// What goes through an effect's input or output, so only things that make sense get connected
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum PortType {
+    /// Sound, with how many channels it has
+    Audio { channels: usize },
+    /// A slow signal for modulating parameters, eg from an LFO
+    Control,
+    /// Notes and other events rather than a signal, each a single frame holding its velocity
+    Midi,
+}
+
+/// What happens when an output is connected to an input
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Conversion {
+    /// They're the same sort of thing, or close enough
+    Direct,
+    /// An effect is put in between to turn one into the other
+    Through(Converter),
+    /// They can't be connected
+    Rejected,
+}
+
+/// The effects that can be put between an output and an input to make them fit
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Converter {
+    /// Audio to control, following how loud it is
+    Envelope,
+    /// Several channels down to one
+    MonoSum,
+    /// One channel out to several
+    Duplicate,
+}
+
+impl PortType {
+    pub const ALL: [PortType; 4] = [
+        PortType::Audio { channels: 1 },
+        PortType::Audio { channels: 2 },
+        PortType::Control,
+        PortType::Midi,
+    ];
+
+    pub const STEREO: PortType = PortType::Audio { channels: 2 };
+
+    /// How an output of this type gets into an input of the other
+    pub fn conversion_to(self, input: PortType) -> Conversion {
+        match (self, input) {
+            (PortType::Audio { channels: from }, PortType::Audio { channels: to }) => {
+                match (from, to) {
+                    _ if from == to => Conversion::Direct,
+                    (_, 1) => Conversion::Through(Converter::MonoSum),
+                    (1, _) => Conversion::Through(Converter::Duplicate),
+                    // the rest of the channels are left as they are
+                    _ => Conversion::Direct,
+                }
+            }
+            (PortType::Audio { .. }, PortType::Control) => Conversion::Through(Converter::Envelope),
+            // a control signal can be listened to, it's just slow
+            (PortType::Control, PortType::Audio { .. } | PortType::Control) => Conversion::Direct,
+            (PortType::Midi, PortType::Midi) => Conversion::Direct,
+            (PortType::Midi, _) | (_, PortType::Midi) => Conversion::Rejected,
+        }
+    }
+}
+
+impl Display for PortType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PortType::Audio { channels: 1 } => write!(f, "audio (mono)"),
+            PortType::Audio { channels: 2 } => write!(f, "audio (stereo)"),
+            PortType::Audio { channels } => write!(f, "audio ({channels} channels)"),
+            PortType::Control => write!(f, "control"),
+            PortType::Midi => write!(f, "MIDI"),
+        }
+    }
+}
+
+impl Converter {
+    /// Make the effect that does the conversion, taking input
+    pub fn create(self, input: Arc<dyn Effect>) -> Arc<dyn Effect> {
+        match self {
+            Converter::Envelope => Arc::new(EnvelopeFollower::new(10.0, 100.0, input)),
+            Converter::MonoSum => Arc::new(ChannelUtility::new(ChannelMode::MonoSum, input)),
+            // a mono output played into more than one channel may only fill the left one,
+            // so copying the left into the rest is all duplicating needs
+            Converter::Duplicate => Arc::new(ChannelUtility::new(ChannelMode::LeftOnly, input)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_conversions() {
+        let mono = PortType::Audio { channels: 1 };
+        assert_eq!(
+            PortType::STEREO.conversion_to(PortType::STEREO),
+            Conversion::Direct
+        );
+        assert_eq!(
+            PortType::STEREO.conversion_to(mono),
+            Conversion::Through(Converter::MonoSum)
+        );
+        assert_eq!(
+            mono.conversion_to(PortType::STEREO),
+            Conversion::Through(Converter::Duplicate)
+        );
+        assert_eq!(
+            PortType::STEREO.conversion_to(PortType::Control),
+            Conversion::Through(Converter::Envelope)
+        );
+        assert_eq!(
+            PortType::Control.conversion_to(PortType::STEREO),
+            Conversion::Direct
+        );
+        for port in PortType::ALL {
+            let expected = match port {
+                PortType::Midi => Conversion::Direct,
+                _ => Conversion::Rejected,
+            };
+            assert_eq!(PortType::Midi.conversion_to(port), expected);
+        }
+    }
+}
@@ -0,0 +1,740 @@
+use std::{any::Any, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audio::{
+        effects::{
+            Effect, EffectError,
+            add::Add,
+            channels::{ChannelMode, ChannelUtility},
+            chorus::Chorus,
+            distortion::{Curve, Distortion, Oversampling},
+            envelope::EnvelopeFollower,
+            events::{Clock, TriggerEnvelope},
+            feedback::{FeedbackReceive, FeedbackSend},
+            filter::{Filter, FilterMode},
+            flanger::Flanger,
+            gain::Gain,
+            group::{Group, GroupInput},
+            lfo::{Lfo, LfoShape},
+            oscillator::{Oscillator, Waveform},
+            output_of, output_source,
+            pan::{Pan, PanLaw},
+            phaser::Phaser,
+            sinewave::SineWave,
+            splitter::{BandSplitter, StereoSplitter},
+            timestretch::TimeStretch,
+            width::StereoWidth,
+            zero::Zero,
+        },
+        parameter::ParameterSchema,
+    },
+    common::dB,
+};
+
+#[derive(Debug)]
+pub enum RegistryError {
+    UnknownType(String),
+    DuplicateId(&'static str),
+    /// The effect has no id so can't be saved, eg a lane or the output
+    NotRegistered(String),
+    Effect(EffectError),
+}
+
+impl std::fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegistryError::UnknownType(id) => write!(f, "no effect has the id {id}"),
+            RegistryError::DuplicateId(id) => write!(f, "an effect already has the id {id}"),
+            RegistryError::NotRegistered(name) => write!(f, "{name} can't be saved"),
+            RegistryError::Effect(error) => error.fmt(f),
+        }
+    }
+}
+
+impl From<EffectError> for RegistryError {
+    fn from(error: EffectError) -> Self {
+        RegistryError::Effect(error)
+    }
+}
+
+/// Parameters (and settings) that come with the app for one kind of effect, by name
+#[derive(Debug)]
+pub struct FactoryPreset {
+    pub name: &'static str,
+    pub parameters: &'static [(&'static str, f32)],
+    pub settings: &'static [(&'static str, &'static str)],
+}
+
+impl FactoryPreset {
+    /// As if it had been saved from the effect, leaving any modulation depths as they are
+    pub fn data(&self, id: &str, effect: &dyn Effect) -> EffectData {
+        let depth = |name: &str| {
+            effect
+                .parameters()
+                .iter()
+                .find(|p| p.name() == name)
+                .map_or(0.0, |p| p.depth())
+        };
+
+        EffectData {
+            id: id.to_string(),
+            parameters: self
+                .parameters
+                .iter()
+                .map(|&(name, value)| ParameterData {
+                    name: name.to_string(),
+                    value,
+                    depth: depth(name),
+                })
+                .collect(),
+            settings: self
+                .settings
+                .iter()
+                .map(|&(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            group: None,
+        }
+    }
+}
+
+/// A kind of effect that can be added to the graph
+#[derive(Clone)]
+pub struct EffectType {
+    /// What scenes and plugins know this kind by, which must never change once released
+    pub id: &'static str,
+    pub name: &'static str,
+    pub category: &'static str,
+    /// Make one with its default settings, given the effect all of its inputs should take to begin with
+    pub create: fn(Arc<dyn Effect>) -> Arc<dyn Effect>,
+    pub presets: &'static [FactoryPreset],
+}
+
+impl EffectType {
+    /// Whether a search (ignoring case) turns up this type, by its name or category
+    pub fn matches(&self, search: &str) -> bool {
+        let search = search.to_lowercase();
+        self.name.to_lowercase().contains(&search) || self.category.to_lowercase().contains(&search)
+    }
+
+    /// The parameters a new one of these starts with
+    pub fn parameter_schema(&self) -> Vec<ParameterSchema> {
+        let effect = (self.create)(Arc::new(Zero));
+        effect.parameters().iter().map(|p| p.schema()).collect()
+    }
+}
+
+/// Every effect that comes with the app, in the order they are listed.
+/// The zero, output and lanes aren't here as the graph and timeline make those themselves
+const BUILTIN: &[EffectType] = &[
+    EffectType {
+        id: "sine_wave",
+        name: "Sine Wave",
+        category: "Generators",
+        presets: &[
+            FactoryPreset {
+                name: "Concert A",
+                parameters: &[("amplitude", 0.5), ("frequency", 440.0), ("phase", 0.0)],
+                settings: &[],
+            },
+            FactoryPreset {
+                name: "Sub",
+                parameters: &[("amplitude", 0.7), ("frequency", 55.0)],
+                settings: &[],
+            },
+            FactoryPreset {
+                name: "Test Tone",
+                parameters: &[("amplitude", 0.25), ("frequency", 1000.0)],
+                settings: &[],
+            },
+        ],
+        create: |_| Arc::new(SineWave::new(0.5, 440.0, 0.0)),
+    },
+    EffectType {
+        id: "oscillator",
+        name: "Oscillator",
+        category: "Generators",
+        presets: &[],
+        create: |zero| Arc::new(Oscillator::new(Waveform::Saw, 0.5, 220.0, zero)),
+    },
+    EffectType {
+        id: "lfo",
+        name: "LFO",
+        category: "Modulation Sources",
+        presets: &[],
+        create: |_| Arc::new(Lfo::new(LfoShape::Sine, 5.0)),
+    },
+    EffectType {
+        id: "envelope_follower",
+        name: "Envelope Follower",
+        category: "Modulation Sources",
+        presets: &[],
+        create: |zero| Arc::new(EnvelopeFollower::new(10.0, 200.0, zero)),
+    },
+    EffectType {
+        id: "clock",
+        name: "Clock",
+        category: "Events",
+        presets: &[],
+        create: |_| Arc::new(Clock::new(120.0, 1.0)),
+    },
+    EffectType {
+        id: "trigger_envelope",
+        name: "Trigger Envelope",
+        category: "Modulation Sources",
+        presets: &[],
+        create: |zero| Arc::new(TriggerEnvelope::new(5.0, 200.0, zero)),
+    },
+    EffectType {
+        id: "gain",
+        name: "Gain",
+        category: "Utility",
+        presets: &[
+            FactoryPreset {
+                name: "Unity",
+                parameters: &[("gain", 0.0)],
+                settings: &[],
+            },
+            FactoryPreset {
+                name: "Half",
+                parameters: &[("gain", -6.0)],
+                settings: &[],
+            },
+            FactoryPreset {
+                name: "Boost",
+                parameters: &[("gain", 6.0)],
+                settings: &[],
+            },
+        ],
+        create: |zero| Arc::new(Gain::new(dB(0.0), zero)),
+    },
+    EffectType {
+        id: "add",
+        name: "Add",
+        category: "Utility",
+        presets: &[],
+        create: |zero| Arc::new(Add::new(zero.clone(), zero)),
+    },
+    EffectType {
+        id: "pan",
+        name: "Pan",
+        category: "Utility",
+        presets: &[],
+        create: |zero| Arc::new(Pan::new(0.0, PanLaw::ConstantPower, zero)),
+    },
+    EffectType {
+        id: "stereo_width",
+        name: "Stereo Width",
+        category: "Utility",
+        presets: &[],
+        create: |zero| Arc::new(StereoWidth::new(1.0, zero)),
+    },
+    EffectType {
+        id: "channels",
+        name: "Channels",
+        category: "Utility",
+        presets: &[],
+        create: |zero| Arc::new(ChannelUtility::new(ChannelMode::Stereo, zero)),
+    },
+    EffectType {
+        id: "stereo_splitter",
+        name: "Stereo Splitter",
+        category: "Utility",
+        presets: &[],
+        create: |zero| StereoSplitter::new(zero),
+    },
+    EffectType {
+        id: "feedback_send",
+        name: "Feedback Send",
+        category: "Utility",
+        presets: &[],
+        create: |zero| Arc::new(FeedbackSend::new("bus 1", zero)),
+    },
+    EffectType {
+        id: "feedback_receive",
+        name: "Feedback Receive",
+        category: "Utility",
+        presets: &[],
+        create: |_| Arc::new(FeedbackReceive::new("bus 1", 0.0)),
+    },
+    EffectType {
+        id: "band_splitter",
+        name: "Band Splitter",
+        category: "Filters",
+        presets: &[],
+        create: |zero| BandSplitter::new(200.0, 2000.0, zero),
+    },
+    EffectType {
+        id: "filter",
+        name: "Filter",
+        category: "Filters",
+        presets: &[
+            FactoryPreset {
+                name: "Low Cut",
+                parameters: &[("cutoff", 100.0), ("resonance", 0.707)],
+                settings: &[("mode", "HighPass")],
+            },
+            FactoryPreset {
+                name: "Telephone",
+                parameters: &[("cutoff", 1500.0), ("resonance", 1.5)],
+                settings: &[("mode", "BandPass")],
+            },
+            FactoryPreset {
+                name: "Muffled",
+                parameters: &[("cutoff", 400.0), ("resonance", 0.707)],
+                settings: &[("mode", "LowPass")],
+            },
+        ],
+        create: |zero| Arc::new(Filter::new(FilterMode::LowPass, 1000.0, 0.707, zero)),
+    },
+    EffectType {
+        id: "chorus",
+        name: "Chorus",
+        category: "Modulation",
+        presets: &[],
+        create: |zero| Arc::new(Chorus::new(3, 0.8, 4.0, zero)),
+    },
+    EffectType {
+        id: "flanger",
+        name: "Flanger",
+        category: "Modulation",
+        presets: &[],
+        create: |zero| Arc::new(Flanger::new(0.3, 2.0, 0.5, zero)),
+    },
+    EffectType {
+        id: "phaser",
+        name: "Phaser",
+        category: "Modulation",
+        presets: &[],
+        create: |zero| Arc::new(Phaser::new(4, 0.4, 800.0, zero)),
+    },
+    EffectType {
+        id: "distortion",
+        name: "Distortion",
+        category: "Distortion",
+        presets: &[
+            FactoryPreset {
+                name: "Warm",
+                parameters: &[("drive", 6.0), ("level", -3.0)],
+                settings: &[("curve", "Tanh")],
+            },
+            FactoryPreset {
+                name: "Crushed",
+                parameters: &[("bits", 4.0), ("reduction", 8.0), ("level", 0.0)],
+                settings: &[("curve", "BitCrush")],
+            },
+        ],
+        create: |zero| {
+            Arc::new(Distortion::new(
+                Curve::Tanh,
+                dB(12.0),
+                Oversampling::X4,
+                zero,
+            ))
+        },
+    },
+    EffectType {
+        id: "time_stretch",
+        name: "Time Stretch",
+        category: "Time",
+        presets: &[],
+        create: |zero| Arc::new(TimeStretch::new(1.0, 0.0, zero)),
+    },
+];
+
+/// A parameter's (value, depth) as saved in a scene
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct ParameterData {
+    pub name: String,
+    pub value: f32,
+    #[serde(default)]
+    pub depth: f32,
+}
+
+/// Everything needed to make an effect again, other than its inputs
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct EffectData {
+    pub id: String,
+    #[serde(default)]
+    pub parameters: Vec<ParameterData>,
+    #[serde(default)]
+    pub settings: Vec<(String, String)>,
+    /// What is inside, if this is a group
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<Box<GroupData>>,
+}
+
+/// The id groups are saved under. They aren't made through an EffectType as they are made of other effects
+pub const GROUP_ID: &str = "group";
+
+/// Where something inside a group takes its input from
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Copy)]
+pub enum GroupSource {
+    Input(usize),
+    Effect(usize),
+    /// (effect, which output) for effects with more than one
+    Output(usize, usize),
+}
+
+/// The effects inside a group and how they are wired up
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct GroupData {
+    pub name: String,
+    /// The name of each of the group's inputs
+    pub inputs: Vec<String>,
+    pub effects: Vec<EffectData>,
+    /// (effect, input index, where from) for every input inside the group that isn't on zero
+    #[serde(default)]
+    pub connections: Vec<(usize, usize, GroupSource)>,
+    pub output: Option<GroupSource>,
+    /// (effect, parameter) indices of the parameters brought out onto the group
+    #[serde(default)]
+    pub exposed: Vec<(usize, usize)>,
+}
+
+/// Every kind of effect that can be made by its id, so scenes, the add node menus and plugins all agree
+pub struct Registry {
+    types: Vec<EffectType>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        let mut registry = Self { types: vec![] };
+        for effect_type in BUILTIN {
+            registry
+                .register(effect_type.clone())
+                .expect("built in effect ids are unique");
+        }
+        registry
+    }
+}
+
+impl Registry {
+    /// Add a new kind of effect, eg from a plugin
+    pub fn register(&mut self, effect_type: EffectType) -> Result<(), RegistryError> {
+        if self.find(effect_type.id).is_some() {
+            return Err(RegistryError::DuplicateId(effect_type.id));
+        }
+        self.types.push(effect_type);
+        Ok(())
+    }
+
+    pub fn types(&self) -> &[EffectType] {
+        &self.types
+    }
+
+    pub fn find(&self, id: &str) -> Option<&EffectType> {
+        self.types.iter().find(|t| t.id == id)
+    }
+
+    /// The categories in the order they first turn up in
+    pub fn categories(&self) -> Vec<&'static str> {
+        let mut categories = vec![];
+        for effect_type in &self.types {
+            if !categories.contains(&effect_type.category) {
+                categories.push(effect_type.category);
+            }
+        }
+        categories
+    }
+
+    pub fn create(
+        &self,
+        id: &str,
+        zero: Arc<dyn Effect>,
+    ) -> Result<Arc<dyn Effect>, RegistryError> {
+        let effect_type = self
+            .find(id)
+            .ok_or_else(|| RegistryError::UnknownType(id.to_string()))?;
+        Ok((effect_type.create)(zero))
+    }
+
+    /// The parameters and settings of an effect, leaving its inputs for whoever is saving the graph
+    pub fn save(&self, effect: &dyn Effect) -> Result<EffectData, RegistryError> {
+        if let Some(group) = (effect as &dyn Any).downcast_ref::<Group>() {
+            return Ok(EffectData {
+                id: GROUP_ID.to_string(),
+                parameters: vec![],
+                settings: vec![],
+                group: Some(Box::new(self.save_group(group)?)),
+            });
+        }
+
+        let id = effect
+            .effect_id()
+            .filter(|id| self.find(id).is_some())
+            .ok_or_else(|| RegistryError::NotRegistered(effect.name().to_string()))?;
+
+        Ok(EffectData {
+            id: id.to_string(),
+            parameters: effect
+                .parameters()
+                .iter()
+                .map(|p| ParameterData {
+                    name: p.name().to_string(),
+                    value: p.get(),
+                    depth: p.depth(),
+                })
+                .collect(),
+            settings: effect
+                .settings()
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+            group: None,
+        })
+    }
+
+    fn save_group(&self, group: &Group) -> Result<GroupData, RegistryError> {
+        let source_of = |input: &Arc<dyn Effect>| {
+            let is_tap = input.tap_source().is_some();
+            let (effect, output) = output_source(input.clone());
+            let is = |other: *const ()| std::ptr::addr_eq(Arc::as_ptr(&effect), other);
+            if let Some(i) = group
+                .inputs()
+                .iter()
+                .position(|g| is(Arc::as_ptr(g).cast()))
+            {
+                Some(GroupSource::Input(i))
+            } else {
+                let effects = group.effects().iter();
+                let e = effects.map(|e| Arc::as_ptr(e).cast::<()>()).position(is)?;
+                match is_tap {
+                    true => Some(GroupSource::Output(e, output)),
+                    false => Some(GroupSource::Effect(e)),
+                }
+            }
+        };
+
+        let mut connections = vec![];
+        for (e, effect) in group.effects().iter().enumerate() {
+            for i in 0..effect.input_count() {
+                if let Some(source) = source_of(&effect.get_input_at_index(i)?) {
+                    connections.push((e, i, source));
+                }
+            }
+        }
+
+        Ok(GroupData {
+            name: group.name().to_string(),
+            inputs: group
+                .inputs()
+                .iter()
+                .map(|g| g.name().to_string())
+                .collect(),
+            effects: group
+                .effects()
+                .iter()
+                .map(|e| self.save(&**e))
+                .collect::<Result<_, _>>()?,
+            connections,
+            output: source_of(&group.output().get_input_at_index(0)?),
+            exposed: group.exposed(),
+        })
+    }
+
+    fn load_group(
+        &self,
+        data: &GroupData,
+        zero: Arc<dyn Effect>,
+    ) -> Result<Arc<dyn Effect>, RegistryError> {
+        let inputs = data
+            .inputs
+            .iter()
+            .map(|name| Arc::new(GroupInput::new(name.clone(), zero.clone())))
+            .collect::<Vec<_>>();
+        let effects = data
+            .effects
+            .iter()
+            .map(|e| self.load(e, zero.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let effect_of = |source: GroupSource| -> Option<Arc<dyn Effect>> {
+            match source {
+                GroupSource::Input(i) => Some(inputs.get(i)?.clone()),
+                GroupSource::Effect(e) => effects.get(e).cloned(),
+                GroupSource::Output(e, output) => Some(output_of(effects.get(e)?, output)),
+            }
+        };
+        for &(e, i, source) in &data.connections {
+            if let (Some(effect), Some(input)) = (effects.get(e), effect_of(source)) {
+                effect.set_input_at_index(i, input)?;
+            }
+        }
+        let output = data.output.and_then(effect_of).unwrap_or(zero);
+
+        Ok(Arc::new(Group::new(
+            data.name.clone(),
+            inputs,
+            effects,
+            output,
+            data.exposed.clone(),
+        )))
+    }
+
+    /// Make an effect from saved data, with all of its inputs on the zero
+    pub fn load(
+        &self,
+        data: &EffectData,
+        zero: Arc<dyn Effect>,
+    ) -> Result<Arc<dyn Effect>, RegistryError> {
+        if let Some(group) = &data.group {
+            return self.load_group(group, zero);
+        }
+        let effect = self.create(&data.id, zero)?;
+        self.restore(&*effect, data)?;
+        Ok(effect)
+    }
+
+    /// Put saved parameters and settings back on an effect that's already there, eg from a preset.
+    /// Parameters are matched by name so ones added since the data was saved keep their values
+    pub fn restore(&self, effect: &dyn Effect, data: &EffectData) -> Result<(), RegistryError> {
+        if effect.effect_id() != Some(data.id.as_str()) {
+            return Err(RegistryError::UnknownType(data.id.clone()));
+        }
+
+        for parameter in effect.parameters() {
+            if let Some(saved) = data.parameters.iter().find(|p| p.name == parameter.name()) {
+                parameter.set(saved.value);
+                parameter.set_depth(saved.depth);
+            }
+        }
+        for (name, value) in &data.settings {
+            effect.set_setting(name, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_every_type_can_be_made() {
+        let registry = Registry::default();
+        let zero: Arc<dyn Effect> = Arc::new(Zero);
+
+        for effect_type in registry.types() {
+            let effect = (effect_type.create)(zero.clone());
+            assert_eq!(effect.name(), effect_type.name);
+            assert_eq!(effect.effect_id(), Some(effect_type.id));
+
+            // the modulation inputs each start with a zero of their own
+            let audio_inputs = effect.input_count() - effect.parameters().len();
+            for i in 0..audio_inputs {
+                let input = effect.get_input_at_index(i).unwrap();
+                assert!(Arc::ptr_eq(&input, &zero), "{} input {i}", effect_type.name);
+            }
+
+            let mut output = vec![0.0; 512];
+            effect.apply(&mut output, 0, 2);
+        }
+    }
+
+    #[test]
+    fn test_factory_presets_apply() {
+        let registry = Registry::default();
+        let zero: Arc<dyn Effect> = Arc::new(Zero);
+
+        for effect_type in registry.types() {
+            let effect = (effect_type.create)(zero.clone());
+            for parameter in effect.parameters() {
+                parameter.set_depth(0.25);
+            }
+            for preset in effect_type.presets {
+                registry
+                    .restore(&*effect, &preset.data(effect_type.id, &*effect))
+                    .unwrap();
+                for &(name, value) in preset.parameters {
+                    let parameter = effect.parameters().into_iter().find(|p| p.name() == name);
+                    assert_eq!(parameter.map(|p| p.get()), Some(value), "{}", preset.name);
+                }
+                // the modulation is left alone
+                assert!(effect.parameters().iter().all(|p| p.depth() == 0.25));
+            }
+        }
+    }
+
+    #[test]
+    fn test_search() {
+        let registry = Registry::default();
+        let found = |search: &str| {
+            registry
+                .types()
+                .iter()
+                .filter(|t| t.matches(search))
+                .map(|t| t.name)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(found("FLANG"), ["Flanger"]);
+        assert_eq!(found("modulation").len(), 6);
+        assert_eq!(found("").len(), registry.types().len());
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let mut registry = Registry::default();
+        let zero: Arc<dyn Effect> = Arc::new(Zero);
+
+        let distortion = registry.create("distortion", zero.clone()).unwrap();
+        distortion.parameters()[0].set(20.0);
+        distortion.set_setting("curve", "HardClip").unwrap();
+
+        let data = registry.save(&*distortion).unwrap();
+        let text = ron::to_string(&data).unwrap();
+        let loaded = registry
+            .load(&ron::from_str(&text).unwrap(), zero.clone())
+            .unwrap();
+
+        assert_eq!(registry.save(&*loaded).unwrap(), data);
+        assert!(matches!(
+            loaded.set_setting("curve", "Wobbly"),
+            Err(EffectError::InvalidSetting { .. })
+        ));
+        assert!(matches!(
+            registry.register(BUILTIN[0].clone()),
+            Err(RegistryError::DuplicateId("sine_wave"))
+        ));
+        assert!(matches!(
+            registry.save(&Zero),
+            Err(RegistryError::NotRegistered(_))
+        ));
+    }
+
+    #[test]
+    fn test_save_and_load_group() {
+        let registry = Registry::default();
+        let zero: Arc<dyn Effect> = Arc::new(Zero);
+
+        let input = Arc::new(GroupInput::new("in".to_string(), zero.clone()));
+        let gain = registry.create("gain", input.clone()).unwrap();
+        let pan = registry.create("pan", gain.clone()).unwrap();
+        let group = Group::new(
+            "Quiet left".to_string(),
+            vec![input],
+            vec![gain, pan.clone()],
+            pan,
+            vec![(1, 0)],
+        );
+
+        let data = registry.save(&group).unwrap();
+        let data: EffectData = ron::from_str(&ron::to_string(&data).unwrap()).unwrap();
+        let loaded = registry.load(&data, zero).unwrap();
+
+        assert_eq!(loaded.name(), "Quiet left");
+        assert_eq!(loaded.input_count(), 2);
+        assert_eq!(registry.save(&*loaded).unwrap(), data);
+        let group = data.group.unwrap();
+        assert_eq!(
+            group.connections,
+            [
+                (0, 0, GroupSource::Input(0)),
+                (1, 0, GroupSource::Effect(0))
+            ]
+        );
+        assert_eq!(group.output, Some(GroupSource::Effect(1)));
+    }
+}
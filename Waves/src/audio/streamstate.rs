@@ -0,0 +1,117 @@
+use std::cell::Cell;
+
+use eframe::egui::mutex::Mutex;
+
+/// How many callers we remember the state for before forgetting the least recently used
+const MAX_STREAMS: usize = 8;
+
+/// Effects are asked for audio by the player and by every plot in the graph, each wanting its own stretch of time.
+/// This keeps a copy of an effect's running state for each of them, keyed by the sample that caller should ask for next,
+/// so a stateful effect can carry on where it left off, or know it has been relocated and must start again.
+pub struct StreamState<T> {
+    // most recently used at the back
+    states: Mutex<Vec<(usize, T)>>,
+    /// The same for the previews, kept apart so however many there are they can't push out the player's
+    previews: Mutex<Vec<(usize, T)>>,
+}
+
+impl<T> Default for StreamState<T> {
+    fn default() -> Self {
+        Self {
+            states: Mutex::new(Vec::with_capacity(MAX_STREAMS)),
+            previews: Mutex::new(Vec::with_capacity(MAX_STREAMS)),
+        }
+    }
+}
+
+thread_local! {
+    /// Whether the effects being applied are being worked out for a preview, rather than played
+    static PREVIEWING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Work out a preview through f, keeping the states the effects leave apart from those of playing
+pub fn previewing<R>(f: impl FnOnce() -> R) -> R {
+    PREVIEWING.set(true);
+    let result = f();
+    PREVIEWING.set(false);
+    result
+}
+
+/// Whether this is being worked out for a preview, which mustn't change anything that is heard
+pub fn is_previewing() -> bool {
+    PREVIEWING.get()
+}
+
+impl<T> StreamState<T> {
+    fn states(&self) -> &Mutex<Vec<(usize, T)>> {
+        match is_previewing() {
+            true => &self.previews,
+            false => &self.states,
+        }
+    }
+
+    /// Removes and returns the state left by whoever was expected to ask for `start_sample` next
+    pub fn take(&self, start_sample: usize) -> Option<T> {
+        let mut states = self.states().lock();
+        let index = states.iter().rposition(|(next, _)| *next == start_sample)?;
+        Some(states.remove(index).1)
+    }
+
+    /// Looks at the state left by whoever was expected to ask for `start_sample` next, leaving it there
+    pub fn peek<R>(&self, start_sample: usize, f: impl FnOnce(&T) -> R) -> Option<R> {
+        let states = self.states().lock();
+        let (_, state) = states
+            .iter()
+            .rev()
+            .find(|(next, _)| *next == start_sample)?;
+        Some(f(state))
+    }
+
+    /// Stores the state to carry on from once `next_sample` is asked for
+    pub fn store(&self, next_sample: usize, state: T) {
+        let mut states = self.states().lock();
+        if states.len() >= MAX_STREAMS {
+            states.remove(0);
+        }
+        states.push((next_sample, state));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_streams_kept_apart() {
+        let s = StreamState::default();
+        s.store(100, "player");
+        s.store(356, "plot");
+
+        assert_eq!(s.take(356), Some("plot"));
+        assert_eq!(s.take(356), None, "state should be used up when taken");
+        assert_eq!(s.take(100), Some("player"));
+    }
+
+    #[test]
+    fn test_oldest_forgotten() {
+        let s = StreamState::default();
+        for i in 0..=MAX_STREAMS {
+            s.store(i, i);
+        }
+
+        assert_eq!(s.take(0), None);
+        assert_eq!(s.take(MAX_STREAMS), Some(MAX_STREAMS));
+    }
+
+    #[test]
+    fn test_previews_kept_apart() {
+        let s = StreamState::default();
+        s.store(100, "player");
+        for i in 0..=MAX_STREAMS {
+            previewing(|| s.store(i, "preview"));
+        }
+
+        assert_eq!(previewing(|| s.take(100)), None);
+        assert_eq!(s.take(100), Some("player"));
+    }
+}
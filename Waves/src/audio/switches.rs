@@ -0,0 +1,193 @@
+use std::any::Any;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+use crate::audio::effects::{
+    Effect,
+    group::{Group, GroupInput},
+};
+
+/// How a node is switched in the graph, so it can be taken out or listened to on its own without rewiring anything
+#[derive(PartialEq, Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Switches {
+    /// Pass the first input straight through, as though the effect wasn't there
+    pub bypass: bool,
+    /// Put out silence
+    pub mute: bool,
+    /// While anything is soloed the output plays just the soloed nodes, straight into it
+    pub solo: bool,
+}
+
+impl Switches {
+    fn bits(self) -> u8 {
+        self.bypass as u8 | (self.mute as u8) << 1 | (self.solo as u8) << 2
+    }
+
+    fn from_bits(bits: u8) -> Self {
+        Self {
+            bypass: bits & 1 != 0,
+            mute: bits & 2 != 0,
+            solo: bits & 4 != 0,
+        }
+    }
+}
+
+/// The switches kept in an effect, so playing can read them each block without waiting on the ui
+#[derive(Default)]
+pub struct SwitchState(AtomicU8);
+
+impl SwitchState {
+    pub fn get(&self) -> Switches {
+        Switches::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    /// Switch the node, which is heard from the next block on
+    pub fn set(&self, switches: Switches) {
+        self.0.store(switches.bits(), Ordering::Relaxed);
+    }
+}
+
+/// Switch the effect's node, if it is one that can be switched. The outputs playing it have to look
+/// for what is soloed again afterwards
+pub fn set(effect: &Arc<dyn Effect>, switches: Switches) {
+    if let Some(state) = effect.switch_state() {
+        state.set(switches);
+    }
+}
+
+/// The soloed effects that feed into root, however far back
+pub fn soloed(root: &Arc<dyn Effect>) -> Vec<Arc<dyn Effect>> {
+    // everything upstream of root, each only looked at the once
+    let mut upstream: Vec<Arc<dyn Effect>> = vec![];
+    let mut stack = vec![root.clone()];
+    while let Some(effect) = stack.pop() {
+        let effect = match effect.tap_source() {
+            Some((source, _)) => source,
+            None => effect,
+        };
+        if upstream
+            .iter()
+            .any(|e| std::ptr::addr_eq(Arc::as_ptr(e), Arc::as_ptr(&effect)))
+        {
+            continue;
+        }
+        stack.extend((0..effect.input_count()).filter_map(|i| effect.get_input_at_index(i).ok()));
+        // the nodes inside a group play through it too
+        let any = &*effect as &dyn Any;
+        if let Some(group) = any.downcast_ref::<Group>() {
+            stack.push(group.output());
+        } else if let Some(input) = any.downcast_ref::<GroupInput>() {
+            stack.push(input.source());
+        }
+        upstream.push(effect);
+    }
+
+    upstream.retain(|effect| effect.switches().solo);
+    upstream
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audio::effects::{gain::Gain, output::Output, sinewave::SineWave};
+    use crate::common::dB;
+
+    #[test]
+    fn test_switches() {
+        let sine: Arc<dyn Effect> = Arc::new(SineWave::new(0.5, 440.0, 0.0));
+        let gain: Arc<dyn Effect> = Arc::new(Gain::new(dB(-6.0), sine.clone()));
+        let output = Output::new(gain.clone());
+
+        let mut dry = vec![0.0; 128];
+        sine.apply(&mut dry, 0, 2);
+        let mut wet = vec![0.0; 128];
+        gain.apply(&mut wet, 0, 2);
+        let played = || {
+            let mut played = vec![0.0; 128];
+            output.apply(&mut played, 0, 2);
+            played
+        };
+        // as the graph does whenever a node is switched
+        let switch = |effect: &Arc<dyn Effect>, switches: Switches| {
+            set(effect, switches);
+            output.find_soloed();
+        };
+        assert_eq!(played(), wet);
+
+        switch(
+            &gain,
+            Switches {
+                bypass: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(played(), dry, "bypassing the gain leaves the sine");
+
+        switch(
+            &gain,
+            Switches {
+                mute: true,
+                ..Default::default()
+            },
+        );
+        assert!(played().iter().all(|s| *s == 0.0));
+
+        // soloing the sine skips the muted gain after it, soloing one that isn't upstream does nothing
+        switch(
+            &sine,
+            Switches {
+                solo: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(played(), dry);
+        switch(&sine, Switches::default());
+        let elsewhere: Arc<dyn Effect> = Arc::new(SineWave::new(0.5, 220.0, 0.0));
+        switch(
+            &elsewhere,
+            Switches {
+                solo: true,
+                ..Default::default()
+            },
+        );
+        assert!(played().iter().all(|s| *s == 0.0));
+
+        switch(&gain, Switches::default());
+        assert_eq!(played(), wet);
+    }
+
+    #[test]
+    fn test_solo_inside_group() {
+        let sine: Arc<dyn Effect> = Arc::new(SineWave::new(0.5, 440.0, 0.0));
+        let input = Arc::new(GroupInput::new("sine in".to_string(), sine.clone()));
+        let inside: Arc<dyn Effect> = Arc::new(Gain::new(dB(-6.0), input.clone()));
+        let group: Arc<dyn Effect> = Arc::new(Group::new(
+            "Group".to_string(),
+            vec![input],
+            vec![inside.clone()],
+            inside.clone(),
+            vec![],
+        ));
+        let after: Arc<dyn Effect> = Arc::new(Gain::new(dB(-12.0), group));
+        let output = Output::new(after.clone());
+
+        let mut wet = vec![0.0; 128];
+        inside.apply(&mut wet, 0, 2);
+        set(
+            &inside,
+            Switches {
+                solo: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(soloed(&after).len(), 1);
+        output.find_soloed();
+        let mut played = vec![0.0; 128];
+        output.apply(&mut played, 0, 2);
+        assert_eq!(played, wet, "the gain after the group isn't heard");
+        set(&inside, Switches::default());
+    }
+}
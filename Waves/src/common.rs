@@ -2,9 +2,14 @@ use std::{f32::consts::PI, thread};
 
 use num_complex::{Complex, ComplexFloat};
 
+pub mod edit;
 pub mod mipmapchannel;
+pub mod presets;
 pub mod track;
 
+/// The rate everything is processed at, in samples per second
+pub const SAMPLE_RATE: f32 = 48000.0;
+
 #[allow(non_camel_case_types)]
 #[repr(transparent)]
 #[derive(Clone, Copy)]
@@ -28,7 +33,7 @@ pub enum Channel {
 
 /// This is the maths involving complex numbers doing the actual computations
 /// Fix this up by passing an iterator for samples along with the size.
-fn complex_fft(samples: &[Complex<f32>], frequencies: &mut [Complex<f32>], inverse: bool) {
+pub fn complex_fft(samples: &[Complex<f32>], frequencies: &mut [Complex<f32>], inverse: bool) {
     let size = samples.len();
     if !size.is_power_of_two() {
         println!("Power of 2 samples not provided!");
@@ -0,0 +1,175 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::common::dB;
+
+/// Some stereo audio cut or copied out of a track, ready to be pasted back in
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct AudioClip {
+    pub left: Vec<f32>,
+    pub right: Vec<f32>,
+}
+
+impl AudioClip {
+    pub fn len(&self) -> usize {
+        self.left.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.left.is_empty()
+    }
+}
+
+/// A change to a track's audio. The track keeps a list of these rather than changing the file's data,
+/// with ranges in samples of the audio as it was just before the edit was made
+#[derive(PartialEq, Debug, Clone)]
+pub enum Edit {
+    Delete(Range<usize>),
+    Insert(usize, Arc<AudioClip>),
+    /// Paste over the range, which is one edit rather than a delete and an insert
+    Replace(Range<usize>, Arc<AudioClip>),
+    Silence(Range<usize>),
+    Reverse(Range<usize>),
+    /// Turn the range up (or down) so its loudest sample is at full volume
+    Normalize(Range<usize>),
+    /// Change the volume of the range by some decibels
+    Gain(Range<usize>, f32),
+}
+
+impl Edit {
+    /// Make the edit to both channels of some audio, ranges going past the end stop at the end
+    pub fn apply(&self, audio: &mut AudioClip) {
+        let len = audio.len();
+        let clamp =
+            |range: &Range<usize>| range.start.min(len)..range.end.clamp(range.start.min(len), len);
+
+        for (c, channel) in [&mut audio.left, &mut audio.right].into_iter().enumerate() {
+            match self {
+                Edit::Delete(range) => {
+                    channel.drain(clamp(range));
+                }
+                Edit::Insert(at, clip) => {
+                    let inserted = [&clip.left, &clip.right][c];
+                    let at = (*at).min(len);
+                    channel.splice(at..at, inserted.iter().copied());
+                }
+                Edit::Replace(range, clip) => {
+                    let inserted = [&clip.left, &clip.right][c];
+                    channel.splice(clamp(range), inserted.iter().copied());
+                }
+                Edit::Silence(range) => channel[clamp(range)].fill(0.0),
+                Edit::Reverse(range) => channel[clamp(range)].reverse(),
+                Edit::Gain(range, gain) => {
+                    let amplitude = dB(*gain).to_amplitude();
+                    channel[clamp(range)]
+                        .iter_mut()
+                        .for_each(|s| *s *= amplitude);
+                }
+                Edit::Normalize(_) => (),
+            }
+        }
+
+        // both channels go up by the same amount so the balance stays the same
+        if let Edit::Normalize(range) = self {
+            let range = clamp(range);
+            let peak = audio.left[range.clone()]
+                .iter()
+                .chain(&audio.right[range.clone()])
+                .fold(0.0f32, |peak, s| peak.max(s.abs()));
+
+            if peak > 0.0 {
+                for channel in [&mut audio.left, &mut audio.right] {
+                    channel[range.clone()].iter_mut().for_each(|s| *s /= peak);
+                }
+            }
+        }
+    }
+
+    /// Where a position in the audio from before the edit ends up after it. Something inserted right at
+    /// a position goes after it, unless it is the end of a range (one past its last sample),
+    /// which then takes in what was inserted
+    pub fn moved(&self, position: usize, is_end: bool) -> usize {
+        let inserted = |position: usize, at: usize, len: usize| match position > at
+            || (is_end && position == at)
+        {
+            true => position + len,
+            false => position,
+        };
+        let deleted = |position: usize, range: &Range<usize>| match position {
+            p if p <= range.start => p,
+            p if p >= range.end => p - range.len(),
+            _ => range.start,
+        };
+
+        match self {
+            Edit::Delete(range) => deleted(position, range),
+            Edit::Insert(at, clip) => inserted(position, *at, clip.len()),
+            Edit::Replace(range, clip) => {
+                inserted(deleted(position, range), range.start, clip.len())
+            }
+            _ => position,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ramp(length: usize) -> AudioClip {
+        let left = (0..length).map(|i| i as f32).collect::<Vec<_>>();
+        let right = left.iter().map(|s| -s).collect();
+        AudioClip { left, right }
+    }
+
+    #[test]
+    fn test_cut_and_paste_back() {
+        let mut audio = ramp(10);
+        let cut = Arc::new(AudioClip {
+            left: audio.left[2..5].to_vec(),
+            right: audio.right[2..5].to_vec(),
+        });
+
+        Edit::Delete(2..5).apply(&mut audio);
+        assert_eq!(audio.left, [0.0, 1.0, 5.0, 6.0, 7.0, 8.0, 9.0]);
+
+        Edit::Insert(2, cut).apply(&mut audio);
+        assert_eq!(audio, ramp(10));
+    }
+
+    #[test]
+    fn test_ranges_stop_at_the_end() {
+        let mut audio = ramp(4);
+
+        Edit::Reverse(2..100).apply(&mut audio);
+        assert_eq!(audio.left, [0.0, 1.0, 3.0, 2.0]);
+        assert_eq!(audio.right, [-0.0, -1.0, -3.0, -2.0]);
+
+        Edit::Silence(3..100).apply(&mut audio);
+        Edit::Normalize(0..100).apply(&mut audio);
+        assert_eq!(audio.left, [0.0, 1.0 / 3.0, 1.0, 0.0]);
+        assert_eq!(audio.right, [-0.0, -1.0 / 3.0, -1.0, -0.0]);
+
+        Edit::Gain(0..100, -6.0206).apply(&mut audio);
+        assert!((audio.left[2] - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_replace() {
+        let mut audio = ramp(6);
+        let pasted = Arc::new(AudioClip {
+            left: vec![9.0; 3],
+            right: vec![-9.0; 3],
+        });
+        let edit = Edit::Replace(1..3, pasted);
+        edit.apply(&mut audio);
+        assert_eq!(audio.left, [0.0, 9.0, 9.0, 9.0, 3.0, 4.0, 5.0]);
+
+        // what was after the range is one further on, what was in it is at the start of what was pasted
+        assert_eq!(edit.moved(0, false), 0);
+        assert_eq!(edit.moved(2, false), 1);
+        assert_eq!(edit.moved(2, true), 4);
+        assert_eq!(edit.moved(4, false), 5);
+        assert_eq!(Edit::Delete(1..3).moved(4, true), 2);
+    }
+}
@@ -59,6 +59,10 @@ impl MipMapChannel {
             cutoff_index,
         }
     }
+    pub fn cutoff_index(&self) -> usize {
+        self.cutoff_index
+    }
+
     pub fn get_full_data(&self) -> &[f32] {
         &self.pyramid_data[0]
     }
@@ -163,6 +167,31 @@ impl SamplePlotData {
             data: vec![vec![0.0; data_width]; 2],
         }
     }
+
+    /// Sets self to a * self + b * other, where both were filled in with the same step and start.
+    /// When the data is min/max this is only an envelope as the extremes of the two need not line up,
+    /// and a negative b swaps which of other's lines bounds which.
+    pub fn mix(&mut self, other: &SamplePlotData, a: f32, b: f32) {
+        if !self.is_min_max {
+            for (v, w) in self.data.iter_mut().zip(&other.data) {
+                for (i, j) in v.iter_mut().zip(w) {
+                    *i = a * *i + b * j;
+                }
+            }
+            return;
+        }
+
+        let (other_min, other_max) = match b < 0.0 {
+            false => (&other.data[0], &other.data[1]),
+            true => (&other.data[1], &other.data[0]),
+        };
+
+        let (min, max) = self.data.split_at_mut(1);
+        for i in 0..min[0].len() {
+            min[0][i] = a * min[0][i] + b * other_min[i];
+            max[0][i] = a * max[0][i] + b * other_max[i];
+        }
+    }
 }
 
 #[cfg(test)]
@@ -177,9 +206,11 @@ mod test {
         }
 
         let m = MipMapChannel::new(vec, 10);
-        let v = m.get_presampled_data_from_step_and_start(5, 3, 20);
+        let mut sample_plot_data = SamplePlotData::new(3, 5, 20);
+        let is_min_max = m.get_presampled_data_from_step_and_start(&mut sample_plot_data);
 
-        assert_eq!(v.0.len(), 0)
+        assert!(!is_min_max);
+        assert!(sample_plot_data.data[0].iter().all(|&f| f == 0.0))
     }
 
     #[test]
@@ -189,14 +220,16 @@ mod test {
             vec[i] = i as f32;
         }
 
-        let start = 5;
+        let start = 8;
         let len = 20;
 
         let m = MipMapChannel::new(vec.clone(), 10);
-        let v = m.get_presampled_data_from_step_and_start(start, 4, len);
+        let mut sample_plot_data = SamplePlotData::new(4, start, len);
+        m.get_presampled_data_from_step_and_start(&mut sample_plot_data);
 
+        // each entry is the loudest sample of its block of 4
         for i in 0..len {
-            assert_eq!(v.0[0][i], vec[start + i * 4]);
+            assert_eq!(sample_plot_data.data[0][i], vec[start + i * 4 + 3]);
         }
     }
 
@@ -208,6 +241,7 @@ mod test {
         }
 
         let m = MipMapChannel::new(vec, 10);
-        let v = m.get_presampled_data_from_step_and_start(5, 16, 20);
+        let mut sample_plot_data = SamplePlotData::new(16, 5, 20);
+        m.get_presampled_data_from_step_and_start(&mut sample_plot_data);
     }
 }
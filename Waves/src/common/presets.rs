@@ -0,0 +1,125 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::audio::registry::EffectData;
+
+/// Where presets are kept, alongside the scene folder
+const PRESET_DIRECTORY: &str = "presets";
+
+fn group_directory() -> PathBuf {
+    Path::new(PRESET_DIRECTORY).join("groups")
+}
+
+/// Each type of effect has its own folder, named by its registry id
+fn effect_directory(id: &str) -> PathBuf {
+    Path::new(PRESET_DIRECTORY).join("effects").join(id)
+}
+
+/// The names of the presets in a folder in alphabetical order, none if the folder isn't there yet
+fn list(directory: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return vec![];
+    };
+
+    let mut names = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            match path.extension()?.to_str()? {
+                "ron" => Some(path.file_stem()?.to_string_lossy().to_string()),
+                _ => None,
+            }
+        })
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+/// Names are typed in by the user and become file names, so they can't reach outside their folder
+/// or be hidden files
+pub fn check_name(name: &str) -> io::Result<()> {
+    let is_valid = !name.trim().is_empty()
+        && !name.starts_with('.')
+        && !name.contains(['/', '\\', ':'])
+        && !name.contains("..");
+    match is_valid {
+        true => Ok(()),
+        false => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("\"{name}\" can't be used as a name"),
+        )),
+    }
+}
+
+/// The file for a preset in a folder, as long as the name is allowed
+fn path(directory: &Path, name: &str) -> io::Result<PathBuf> {
+    check_name(name)?;
+    Ok(directory.join(format!("{name}.ron")))
+}
+
+fn save(directory: &Path, name: &str, data: &EffectData) -> io::Result<()> {
+    let path = path(directory, name)?;
+    fs::create_dir_all(directory)?;
+    let file = File::create(path)?;
+    ron::Options::default()
+        .to_io_writer_pretty(file, data, ron::ser::PrettyConfig::new())
+        .map_err(io::Error::other)
+}
+
+fn load(directory: &Path, name: &str) -> io::Result<EffectData> {
+    let file = File::open(path(directory, name)?)?;
+    ron::Options::default()
+        .from_reader(file)
+        .map_err(io::Error::other)
+}
+
+/// The names of the saved groups in alphabetical order
+pub fn group_presets() -> Vec<String> {
+    list(&group_directory())
+}
+
+/// Save a group (as saved by the registry) under its name, replacing any with the same name
+pub fn save_group_preset(name: &str, data: &EffectData) -> io::Result<()> {
+    save(&group_directory(), name, data)
+}
+
+pub fn load_group_preset(name: &str) -> io::Result<EffectData> {
+    load(&group_directory(), name)
+}
+
+/// The names of the user's presets for one type of effect in alphabetical order
+pub fn effect_presets(id: &str) -> Vec<String> {
+    list(&effect_directory(id))
+}
+
+/// Save an effect's parameters and settings (as saved by the registry) under a name,
+/// replacing any with the same name
+pub fn save_effect_preset(name: &str, data: &EffectData) -> io::Result<()> {
+    save(&effect_directory(&data.id), name, data)
+}
+
+pub fn load_effect_preset(id: &str, name: &str) -> io::Result<EffectData> {
+    load(&effect_directory(id), name)
+}
+
+pub fn delete_effect_preset(id: &str, name: &str) -> io::Result<()> {
+    fs::remove_file(path(&effect_directory(id), name)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_name() {
+        assert!(check_name("Warm pad").is_ok());
+        assert!(check_name("v1.2").is_ok());
+        for name in [
+            "", " ", "../scene", "a/b", "a\\b", "..", ".hidden", "C:evil",
+        ] {
+            assert!(check_name(name).is_err(), "{name} should be refused");
+        }
+    }
+}
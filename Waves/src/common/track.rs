@@ -1,18 +1,143 @@
+use std::f32::consts::PI;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
+use eframe::egui::mutex::Mutex;
+use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 use symphonia::core::codecs::CodecParameters;
 
+use crate::audio::switches::SwitchState;
+use crate::common::edit::{AudioClip, Edit};
 use crate::common::mipmapchannel::MipMapChannel;
 
+/// The shape of a fade, going from silent at 0 to full volume at 1
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum FadeCurve {
+    #[default]
+    Linear,
+    /// Keeps the power constant when crossfading two tracks
+    EqualPower,
+    SCurve,
+    Exponential,
+}
+
+impl FadeCurve {
+    pub const ALL: [FadeCurve; 4] = [
+        FadeCurve::Linear,
+        FadeCurve::EqualPower,
+        FadeCurve::SCurve,
+        FadeCurve::Exponential,
+    ];
+
+    pub fn gain(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            FadeCurve::Linear => t,
+            FadeCurve::EqualPower => (t * PI / 2.0).sin(),
+            FadeCurve::SCurve => 0.5 - 0.5 * (t * PI).cos(),
+            FadeCurve::Exponential => (2f32.powf(10.0 * t) - 1.0) / 1023.0,
+        }
+    }
+}
+
+/// Which part of the file is played and where it sits on the timeline, all in samples
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrackTrim {
+    /// Where on the timeline the (trimmed) track starts
+    pub offset: usize,
+    /// The first sample of the file that is played
+    pub start: usize,
+    /// One past the last sample of the file that is played, or the end of the file if None
+    pub end: Option<usize>,
+    pub fade_in: usize,
+    pub fade_out: usize,
+    pub fade_in_curve: FadeCurve,
+    pub fade_out_curve: FadeCurve,
+    /// Play the trimmed part over and over rather than just the once
+    pub looping: bool,
+}
+
+impl TrackTrim {
+    /// The (start, end) of the part of a file this long that is played
+    pub fn region(&self, length: usize) -> (usize, usize) {
+        let end = self.end.unwrap_or(length).min(length);
+        (self.start.min(end), end)
+    }
+
+    /// How far into the played part of the file the timeline sample is, if it is heard at all
+    pub fn position(&self, sample: usize, length: usize) -> Option<usize> {
+        let (start, end) = self.region(length);
+        let len = end - start;
+        let position = sample.checked_sub(self.offset)?;
+
+        match self.looping {
+            _ if len == 0 => None,
+            true => Some(position % len),
+            false => (position < len).then_some(position),
+        }
+    }
+
+    /// The sample of the file heard at the timeline sample, or the nearest end of the played part if it isn't heard
+    pub fn file_position(&self, sample: usize, length: usize) -> usize {
+        let (start, end) = self.region(length);
+        match self.position(sample, length) {
+            Some(position) => start + position,
+            None if sample < self.offset => start,
+            None => end,
+        }
+    }
+
+    /// The trim moved along with the audio under it after the edit, with the fades still fitting in
+    pub fn follow(&self, edit: &Edit) -> Self {
+        let start = edit.moved(self.start, false);
+        let end = self.end.map(|end| edit.moved(end, true));
+        let len = end.map(|end| end.saturating_sub(start));
+        let fade_in = len.map_or(self.fade_in, |len| self.fade_in.min(len));
+        let fade_out = len.map_or(self.fade_out, |len| self.fade_out.min(len - fade_in));
+        Self {
+            start,
+            end,
+            fade_in,
+            fade_out,
+            ..*self
+        }
+    }
+
+    /// The volume from the fades at a position into a played part this long
+    pub fn fade_gain(&self, position: usize, len: usize) -> f32 {
+        let mut gain = 1.0;
+        if position < self.fade_in {
+            gain *= self
+                .fade_in_curve
+                .gain(position as f32 / self.fade_in as f32);
+        }
+        let left = len.saturating_sub(position);
+        if left <= self.fade_out {
+            gain *= self.fade_out_curve.gain(left as f32 / self.fade_out as f32);
+        }
+        gain
+    }
+}
+
 #[derive(Default)]
 pub struct Track {
     file_path: Option<PathBuf>,
     file_codec_parameters: CodecParameters,
     length: u64,
     sample_rate: u32,
-    file_data_left: MipMapChannel,
-    file_data_right: MipMapChannel,
+    // shared between the clips split or duplicated from the same file
+    file_data_left: Arc<MipMapChannel>,
+    file_data_right: Arc<MipMapChannel>,
+    /// Everything done to the file's audio, in the order it was done
+    edits: Mutex<Vec<Edit>>,
+    /// The file's audio with the edits made to it, which is what gets played
+    edited_data: Mutex<(Arc<MipMapChannel>, Arc<MipMapChannel>)>,
+    trim: Mutex<TrackTrim>,
+    /// How the track's node is switched, which is read by its Effect impl
+    pub(crate) switches: SwitchState,
 }
 
 impl Debug for Track {
@@ -20,6 +145,8 @@ impl Debug for Track {
         f.debug_struct("Track")
             .field("file_path", &self.file_path)
             .field("file_codec_parameters", &self.file_codec_parameters)
+            .field("edits", &self.edits())
+            .field("trim", &self.trim())
             .finish()
     }
 }
@@ -31,13 +158,35 @@ impl Track {
         file_data_left: MipMapChannel,
         file_data_right: MipMapChannel,
     ) -> Self {
+        let file_data_left = Arc::new(file_data_left);
+        let file_data_right = Arc::new(file_data_right);
         Self {
             file_path,
             length: file_codec_parameters.n_frames.unwrap(),
             sample_rate: file_codec_parameters.sample_rate.unwrap(),
             file_codec_parameters,
+            edits: Mutex::new(vec![]),
+            edited_data: Mutex::new((file_data_left.clone(), file_data_right.clone())),
             file_data_left,
             file_data_right,
+            trim: Mutex::new(TrackTrim::default()),
+            switches: SwitchState::default(),
+        }
+    }
+
+    /// Another track playing the same file (without copying its audio), starting off with the same edits and trim
+    pub fn duplicate(&self) -> Self {
+        Self {
+            file_path: self.file_path.clone(),
+            file_codec_parameters: self.file_codec_parameters.clone(),
+            length: self.length,
+            sample_rate: self.sample_rate,
+            file_data_left: self.file_data_left.clone(),
+            file_data_right: self.file_data_right.clone(),
+            edits: Mutex::new(self.edits()),
+            edited_data: Mutex::new(self.data()),
+            trim: Mutex::new(self.trim()),
+            switches: SwitchState::default(),
         }
     }
 
@@ -45,11 +194,9 @@ impl Track {
         &self.file_codec_parameters
     }
 
-    pub fn sample_data(&self) -> (&[f32], &[f32]) {
-        (
-            self.file_data_left.get_full_data(),
-            self.file_data_right.get_full_data(),
-        )
+    /// The (left, right) audio that is played, with the edits made to it
+    pub fn data(&self) -> (Arc<MipMapChannel>, Arc<MipMapChannel>) {
+        self.edited_data.lock().clone()
     }
 
     //pub fn mipmap_file_data(&self) -> (&[f32], &[f32]) {}
@@ -61,19 +208,230 @@ impl Track {
         }
     }
 
-    pub fn file_data_left(&self) -> &MipMapChannel {
-        &self.file_data_left
+    /// How long the audio is once it has been edited
+    pub fn length(&self) -> u64 {
+        match self.edits.lock().is_empty() {
+            true => self.length,
+            false => self.edited_data.lock().0.get_full_data().len() as u64,
+        }
     }
 
-    pub fn file_data_right(&self) -> &MipMapChannel {
-        &self.file_data_right
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
     }
 
-    pub fn length(&self) -> u64 {
-        self.length
+    pub fn trim(&self) -> TrackTrim {
+        *self.trim.lock()
     }
 
-    pub fn sample_rate(&self) -> u32 {
-        self.sample_rate
+    pub fn set_trim(&self, trim: TrackTrim) {
+        *self.trim.lock() = trim;
+    }
+
+    pub fn edits(&self) -> Vec<Edit> {
+        self.edits.lock().clone()
+    }
+
+    /// A copy of part of the (edited) audio
+    pub fn copy(&self, range: Range<usize>) -> AudioClip {
+        let (left, right) = self.data();
+        let (left, right) = (left.get_full_data(), right.get_full_data());
+        let start = range.start.min(left.len());
+        let range = start..range.end.clamp(start, left.len());
+        AudioClip {
+            left: left[range.clone()].to_vec(),
+            right: right[range].to_vec(),
+        }
+    }
+
+    /// Replace all the edits, working the audio out again from the file's.
+    /// This is how edits are undone
+    pub fn set_edits(&self, edits: Vec<Edit>) {
+        let mut audio = AudioClip {
+            left: self.file_data_left.get_full_data().to_vec(),
+            right: self.file_data_right.get_full_data().to_vec(),
+        };
+        for edit in &edits {
+            edit.apply(&mut audio);
+        }
+
+        let edited = (
+            Arc::new(MipMapChannel::new(
+                audio.left,
+                self.file_data_left.cutoff_index(),
+            )),
+            Arc::new(MipMapChannel::new(
+                audio.right,
+                self.file_data_right.cutoff_index(),
+            )),
+        );
+
+        let mut current = self.edits.lock();
+        *current = edits;
+        *self.edited_data.lock() = edited;
+    }
+
+    /// Make an edit on top of the ones already made, rebuilding the mipmaps of the audio that is played.
+    /// The file's own audio is left as it is, and the trim moves along with the audio it was around
+    pub fn edit(&self, edit: Edit) {
+        let (left, right) = self.data();
+        let mut audio = AudioClip {
+            left: left.get_full_data().to_vec(),
+            right: right.get_full_data().to_vec(),
+        };
+        edit.apply(&mut audio);
+
+        let edited = (
+            Arc::new(MipMapChannel::new(audio.left, left.cutoff_index())),
+            Arc::new(MipMapChannel::new(audio.right, right.cutoff_index())),
+        );
+
+        // hold the edits while swapping the audio so the length never sees one without the other
+        let mut edits = self.edits.lock();
+        let trim = self.trim().follow(&edit);
+        edits.push(edit);
+        *self.edited_data.lock() = edited;
+        self.set_trim(trim);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_offset_and_trim() {
+        let trim = TrackTrim {
+            offset: 100,
+            start: 10,
+            end: Some(60),
+            ..Default::default()
+        };
+
+        assert_eq!(trim.position(99, 1000), None);
+        assert_eq!(trim.position(100, 1000), Some(0));
+        assert_eq!(trim.position(149, 1000), Some(49));
+        assert_eq!(trim.position(150, 1000), None);
+
+        // the end can't go past the end of the file
+        assert_eq!(trim.region(40), (10, 40));
+    }
+
+    #[test]
+    fn test_looping() {
+        let trim = TrackTrim {
+            start: 10,
+            end: Some(60),
+            looping: true,
+            ..Default::default()
+        };
+
+        assert_eq!(trim.position(49, 1000), Some(49));
+        assert_eq!(trim.position(50, 1000), Some(0));
+        assert_eq!(trim.position(1234, 1000), Some(34));
+    }
+
+    #[test]
+    fn test_fades() {
+        let trim = TrackTrim {
+            fade_in: 10,
+            fade_out: 20,
+            ..Default::default()
+        };
+
+        assert_eq!(trim.fade_gain(0, 100), 0.0);
+        assert_eq!(trim.fade_gain(5, 100), 0.5);
+        assert_eq!(trim.fade_gain(50, 100), 1.0);
+        assert_eq!(trim.fade_gain(90, 100), 0.5);
+
+        for curve in FadeCurve::ALL {
+            assert!(
+                curve.gain(0.0).abs() < 1e-6,
+                "{curve:?} doesn't start silent"
+            );
+            assert!(
+                (curve.gain(1.0) - 1.0).abs() < 1e-6,
+                "{curve:?} doesn't finish at full volume"
+            );
+        }
+    }
+
+    #[test]
+    fn test_set_edits_goes_back_to_the_file() {
+        let mut parameters = CodecParameters::new();
+        parameters.with_n_frames(8).with_sample_rate(48000);
+        let data = (0..8).map(|i| i as f32).collect::<Vec<_>>();
+        let track = Track::new(
+            None,
+            parameters,
+            MipMapChannel::new(data.clone(), 5),
+            MipMapChannel::new(data.clone(), 5),
+        );
+
+        track.edit(Edit::Reverse(0..4));
+        track.edit(Edit::Delete(6..8));
+        assert_eq!(track.length(), 6);
+        assert_eq!(
+            track.data().0.get_full_data(),
+            [3.0, 2.0, 1.0, 0.0, 4.0, 5.0]
+        );
+
+        // undoing the last edit
+        let mut edits = track.edits();
+        edits.pop();
+        track.set_edits(edits);
+        assert_eq!(track.length(), 8);
+        assert_eq!(
+            track.data().0.get_full_data(),
+            [3.0, 2.0, 1.0, 0.0, 4.0, 5.0, 6.0, 7.0]
+        );
+
+        track.set_edits(vec![]);
+        assert_eq!(track.data().1.get_full_data(), data);
+    }
+
+    #[test]
+    fn test_trim_follows_edits() {
+        let mut parameters = CodecParameters::new();
+        parameters.with_n_frames(100).with_sample_rate(48000);
+        let data = vec![0.5; 100];
+        let track = Track::new(
+            None,
+            parameters,
+            MipMapChannel::new(data.clone(), 5),
+            MipMapChannel::new(data, 5),
+        );
+        track.set_trim(TrackTrim {
+            start: 20,
+            end: Some(60),
+            fade_in: 10,
+            fade_out: 20,
+            ..Default::default()
+        });
+        let trim = || {
+            let trim = track.trim();
+            (trim.start, trim.end, trim.fade_in, trim.fade_out)
+        };
+
+        // cutting from before the start brings it all forward
+        track.edit(Edit::Delete(0..10));
+        assert_eq!(trim(), (10, Some(50), 10, 20));
+
+        // pasting inside makes it longer
+        let clip = Arc::new(AudioClip {
+            left: vec![1.0; 5],
+            right: vec![1.0; 5],
+        });
+        track.edit(Edit::Insert(30, clip.clone()));
+        assert_eq!(trim(), (10, Some(55), 10, 20));
+
+        // taking out most of it leaves the fades no longer than what's left
+        track.edit(Edit::Delete(15..50));
+        assert_eq!(trim(), (10, Some(20), 10, 0));
+
+        // pasting over the end takes in all that was pasted
+        track.edit(Edit::Replace(18..25, clip));
+        assert_eq!(trim(), (10, Some(23), 10, 0));
+        assert_eq!(track.length(), 58);
     }
 }
@@ -1,4 +1,4 @@
-use eframe::egui::{self, Button, Color32, Image, Pos2, Rect};
+use eframe::egui::{self, Color32, Image, Key, KeyboardShortcut, Modifiers};
 use symphonia::core::errors::Error;
 
 use std::{
@@ -18,13 +18,26 @@ use crate::{
     common::track::Track,
     player::{AudioThread, AudioUpdate},
     ui::{
-        nodegraph::NodeGraph, playpausebutton::PlayPauseButton, progresstracker::ProgressTracker,
-        threadtracker::ThreadTracker, waveformwidget::WaveformWidget,
+        nodegraph::{NodeGraph, history::Command},
+        playpausebutton::PlayPauseButton,
+        progresstracker::ProgressTracker,
+        threadtracker::ThreadTracker,
+        timeline::Timeline,
+        waveformwidget::WaveformWidget,
     },
 };
 
+/// Which of the big views fills the middle of the window
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum View {
+    NodeGraph,
+    Timeline,
+}
+
 struct MyEguiApp {
     node_graph: NodeGraph,
+    timeline: Timeline,
+    view: View,
     effect_dag: Arc<EffectDAG>,
     active_track: Option<Arc<Track>>,
     tx_loader: mpsc::Sender<Track>,
@@ -52,6 +65,8 @@ impl MyEguiApp {
 
         let mut s = Self {
             node_graph: NodeGraph::new_non_trivial(),
+            timeline: Timeline::default(),
+            view: View::NodeGraph,
             effect_dag: Arc::new(EffectDAG::new(0, vec![Arc::new(Zero)])),
             tx_loader: tx,
             rx_loader: rx,
@@ -81,6 +96,15 @@ impl eframe::App for MyEguiApp {
         // force it to update every frame even if nothing is happening
         ctx.request_repaint();
 
+        // check for redo first as ctrl+z would also match ctrl+shift+z
+        let redo = KeyboardShortcut::new(Modifiers::COMMAND | Modifiers::SHIFT, Key::Z);
+        let undo = KeyboardShortcut::new(Modifiers::COMMAND, Key::Z);
+        if ctx.input_mut(|i| i.consume_shortcut(&redo)) {
+            self.node_graph.redo();
+        } else if ctx.input_mut(|i| i.consume_shortcut(&undo)) {
+            self.node_graph.undo();
+        }
+
         // Bottom Panel
         egui::TopBottomPanel::bottom("status").show(ctx, |ui| {
             ui.vertical_centered(|ui| {
@@ -154,9 +178,21 @@ impl eframe::App for MyEguiApp {
                 (500.0, 200.0),
                 true,
                 Some(self.audio_thread.commands.clone()),
-            );
+            )
+            .editing(self.active_track.clone());
 
+            let edited = |track: &Arc<Track>| (track.edits(), track.trim());
+            let before = self.active_track.as_ref().map(edited);
             main_waveform.ui(ui, true);
+            if let (Some(track), Some(before)) = (&self.active_track, before)
+                && track.edits() != before.0
+            {
+                self.node_graph.history.push(Command::TrackEdits {
+                    track: track.clone(),
+                    before,
+                    after: edited(track),
+                });
+            }
 
             // Iterate through all progress bars and display on the bottom of the screen
 
@@ -174,10 +210,35 @@ impl eframe::App for MyEguiApp {
             });
         });
 
+        egui::TopBottomPanel::top("views").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut self.view, View::NodeGraph, "Node Graph");
+                ui.selectable_value(&mut self.view, View::Timeline, "Timeline");
+            });
+        });
+
         egui::CentralPanel::default().show(ctx, |ui| {
             // UI
 
-            self.node_graph.node_graph_ui(ui);
+            match self.view {
+                View::NodeGraph => {
+                    self.node_graph.node_graph_ui(ui);
+                }
+                View::Timeline => {
+                    if let Some(s) = self.timeline.ui(ui, self.current_sample) {
+                        self.current_sample = s;
+                        self.audio_thread
+                            .send_command(player::AudioCommand::RelocateTo(
+                                self.node_graph.output.clone(),
+                                self.current_sample,
+                            ));
+                    }
+                    // edits on the main waveform go to the clip picked out on the timeline
+                    if let Some(clip) = self.timeline.selected() {
+                        self.active_track = Some(clip);
+                    }
+                }
+            }
 
             while let Ok(update) = self.audio_thread.updates.try_recv() {
                 match update {
@@ -193,7 +254,10 @@ impl eframe::App for MyEguiApp {
                 // Go to the beginning to ensure no nasty crashes.
                 //self.current_sample = 0;
 
-                self.node_graph.add_track(Arc::new(rx));
+                let track = Arc::new(rx);
+                self.active_track = Some(track.clone());
+                let lane = self.timeline.add_track(track);
+                self.node_graph.add_lane(lane);
             }
 
             // if let Some(t) = self.active_track.as_ref() {
@@ -1,5 +1,6 @@
 use crate::audio::effects::Effect;
 use crate::audio::effects::output::Output;
+use crate::audio::meters;
 
 use std::{
     sync::{Arc, mpsc},
@@ -49,7 +50,7 @@ fn get_stream_from_sample(
         .build_output_stream(
             &config,
             move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                output.apply(data, sample_clock, channels);
+                meters::metering(|| output.process(data, sample_clock, channels));
                 sample_clock += data.len() / channels;
                 tx.send(AudioUpdate::CurrentSample(sample_clock))
                     .expect("Channel Closed");
@@ -1,8 +1,12 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{any::Any, collections::HashMap, path::PathBuf, sync::Arc};
 
-use crate::audio::effects::{Effect, gain::Gain};
+use crate::audio::effects::{
+    Effect, feedback::FeedbackBuses, gain::Gain, output_of, output_source,
+};
+use crate::audio::registry::{EffectData, Registry, RegistryError};
+use crate::audio::switches::{self, Switches};
 use crate::audio::{dag::EffectDAG, effects::zero::Zero};
-use crate::common::track::Track;
+use crate::common::track::{Track, TrackTrim};
 
 use serde::{Deserialize, Serialize};
 
@@ -12,13 +16,28 @@ pub enum NodeType {
     Zero,
     Track {
         file_path: PathBuf,
-        // start : usize,
+        #[serde(default)]
+        trim: TrackTrim,
+        #[serde(default)]
+        switches: Switches,
     },
     Gain {
         #[allow(non_snake_case)]
         dB: f32,
         input: usize, // The index of our element
     },
+    /// Any effect in the registry, with the index of the node for each of its inputs (modulation ones too)
+    Effect {
+        data: EffectData,
+        #[serde(default)]
+        inputs: Vec<usize>,
+        /// (input index, which output) for the inputs taking one of the outputs of an effect with several
+        #[serde(default)]
+        outputs: Vec<(usize, usize)>,
+        /// Whether the node is bypassed, muted or soloed
+        #[serde(default)]
+        switches: Switches,
+    },
 }
 
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
@@ -28,20 +47,65 @@ pub struct Scene {
 }
 
 impl Scene {
-    fn expand_dag(&self, index: usize, dag: &mut EffectDAG) -> Arc<dyn Effect> {
-        match &self.nodes[index] {
+    fn expand_dag(
+        &self,
+        index: usize,
+        dag: &mut EffectDAG,
+        registry: &Registry,
+        built: &mut HashMap<usize, Arc<dyn Effect>>,
+    ) -> Arc<dyn Effect> {
+        // a node feeding into more than one other is only made the once
+        if let Some(effect) = built.get(&index) {
+            return effect.clone();
+        }
+
+        let effect = match &self.nodes[index] {
             NodeType::Zero => dag.add_effect(Zero),
             NodeType::Gain { dB, input } => {
-                let input = self.expand_dag(*input, dag);
+                let input = self.expand_dag(*input, dag, registry, built);
                 dag.add_effect(Gain::new(crate::common::dB(*dB), input))
             }
-            NodeType::Track { file_path } => {
-                dag.add_effect(Track::get_data_from_mp3_path(file_path.clone(), None).unwrap())
+            NodeType::Track {
+                file_path,
+                trim,
+                switches,
+            } => {
+                let track = Track::get_data_from_mp3_path(file_path.clone(), None).unwrap();
+                track.set_trim(*trim);
+                let track = dag.add_effect(track);
+                switches::set(&track, *switches);
+                track
             }
-        }
+            NodeType::Effect {
+                data,
+                inputs,
+                outputs,
+                switches,
+            } => {
+                let effect = match registry.load(data, Arc::new(Zero)) {
+                    Ok(effect) => effect,
+                    Err(e) => {
+                        println!("could not load effect: {e}");
+                        return dag.add_effect(Zero);
+                    }
+                };
+                for (i, input) in inputs.iter().enumerate() {
+                    let input = self.expand_dag(*input, dag, registry, built);
+                    let input = match outputs.iter().find(|(index, _)| *index == i) {
+                        Some(&(_, output)) => output_of(&input, output),
+                        None => input,
+                    };
+                    let _ = effect.set_input_at_index(i, input);
+                }
+                switches::set(&effect, *switches);
+                dag.add_arc_effect(effect)
+            }
+        };
+        built.insert(index, effect.clone());
+        effect
     }
 
-    pub fn generate_effect_dag(&self) -> EffectDAG {
+    pub fn generate_effect_dag(&self, registry: &Registry) -> EffectDAG {
         match self.start_index {
             None => {
                 println!("there is no start index");
@@ -49,21 +113,93 @@ impl Scene {
             }
             Some(i) => {
                 let mut dag = EffectDAG::new(i, vec![]);
-                self.expand_dag(i, &mut dag);
+                self.expand_dag(i, &mut dag, registry, &mut HashMap::new());
+                // the scene's sends and receives only find each other
+                let buses = Arc::new(FeedbackBuses::default());
+                for effect in dag.nodes() {
+                    buses.adopt(effect);
+                }
 
                 dag
             }
         }
     }
 
-    pub fn from_effect_dag() -> Self {
-        todo!()
+    /// Save everything feeding into root, with effects used more than once only saved the once
+    pub fn from_effects(root: Arc<dyn Effect>, registry: &Registry) -> Result<Self, RegistryError> {
+        let mut scene = Self {
+            start_index: None,
+            nodes: vec![],
+        };
+        let mut saved = vec![];
+        scene.start_index = Some(scene.add_effect(root, registry, &mut saved)?);
+        Ok(scene)
+    }
+
+    /// Add a node for the effect (and its inputs) if it isn't already in, giving back its index
+    fn add_effect(
+        &mut self,
+        effect: Arc<dyn Effect>,
+        registry: &Registry,
+        saved: &mut Vec<(Arc<dyn Effect>, usize)>,
+    ) -> Result<usize, RegistryError> {
+        // the zeros are all the same, so they share a node
+        let is_zero = |e: &Arc<dyn Effect>| (&**e as &dyn Any).is::<Zero>();
+        if let Some((_, index)) = saved
+            .iter()
+            .find(|(e, _)| Arc::ptr_eq(e, &effect) || (is_zero(e) && is_zero(&effect)))
+        {
+            return Ok(*index);
+        }
+
+        // leave a space for this one so its inputs come after it, like the scenes written by hand
+        let index = self.nodes.len();
+        self.nodes.push(NodeType::Zero);
+        saved.push((effect.clone(), index));
+
+        let any = &*effect as &dyn Any;
+        if let Some(track) = any.downcast_ref::<Track>() {
+            let file_path = track
+                ._file_path()
+                .map(|p| p.to_path_buf())
+                .ok_or_else(|| RegistryError::NotRegistered(effect.name().to_string()))?;
+            self.nodes[index] = NodeType::Track {
+                file_path,
+                trim: track.trim(),
+                switches: effect.switches(),
+            };
+        } else if !any.is::<Zero>() {
+            let data = registry.save(&*effect)?;
+            let mut inputs = vec![];
+            let mut outputs = vec![];
+            for i in 0..effect.input_count() {
+                let mut input = effect.get_input_at_index(i)?;
+                if input.tap_source().is_some() {
+                    let (source, output) = output_source(input);
+                    outputs.push((i, output));
+                    input = source;
+                }
+                inputs.push(self.add_effect(input, registry, saved)?);
+            }
+            self.nodes[index] = NodeType::Effect {
+                data,
+                inputs,
+                outputs,
+                switches: effect.switches(),
+            };
+        }
+
+        Ok(index)
     }
 
     pub fn from_track(path: PathBuf) -> Self {
         Self {
             start_index: Some(0),
-            nodes: vec![NodeType::Track { file_path: path }],
+            nodes: vec![NodeType::Track {
+                file_path: path,
+                trim: TrackTrim::default(),
+                switches: Switches::default(),
+            }],
         }
     }
 }
@@ -80,7 +216,7 @@ mod test {
         let scene = Scene::from_track(PathBuf::from(path));
 
         match scene.nodes[0].clone() {
-            NodeType::Track { file_path: p } => {
+            NodeType::Track { file_path: p, .. } => {
                 //println!("{:?}, {path}", p);
                 assert!(p.to_str().unwrap() == path, "Path not saved appropriately")
             }
@@ -114,7 +250,7 @@ mod test {
     start_index: Some(0),
     nodes: [
         Track(
-            file_path: "\\mp3s\\C_major.mp3",
+            file_path: "mp3s\\C_major.mp3",
         ),
     ],
 )"#;
@@ -147,11 +283,17 @@ mod test {
                 NodeType::Gain { dB: db, input: 1 },
                 NodeType::Track {
                     file_path: file_path.clone(),
+                    trim: TrackTrim {
+                        offset: 480,
+                        looping: true,
+                        ..Default::default()
+                    },
+                    switches: Switches::default(),
                 },
             ],
         };
 
-        let dag = scene.generate_effect_dag();
+        let dag = scene.generate_effect_dag(&Registry::default());
 
         // we being a bit silly
         assert_eq!(dag.root_index(), 0);
@@ -166,5 +308,35 @@ mod test {
                 .unwrap(),
             file_path
         );
+        assert_eq!(node_one.downcast_ref::<Track>().unwrap().trim().offset, 480);
+    }
+
+    #[test]
+    fn test_save_registered_effects() {
+        let registry = Registry::default();
+        let zero: Arc<dyn Effect> = Arc::new(Zero);
+        let lfo = registry.create("lfo", zero.clone()).unwrap();
+        let filter = registry.create("filter", zero.clone()).unwrap();
+        filter.set_setting("mode", "HighPass").unwrap();
+        // the cutoff is modulated by the lfo, the lfo's output goes to both
+        filter.set_input_at_index(1, lfo.clone()).unwrap();
+        let add = registry.create("add", zero.clone()).unwrap();
+        add.set_input_at_index(0, filter.clone()).unwrap();
+        add.set_input_at_index(1, lfo).unwrap();
+        let bypassed = Switches {
+            bypass: true,
+            ..Default::default()
+        };
+        switches::set(&filter, bypassed);
+
+        let scene = Scene::from_effects(add, &registry).unwrap();
+        let text = ron::to_string(&scene).unwrap();
+        let scene: Scene = ron::from_str(&text).unwrap();
+        assert_eq!(scene.nodes.len(), 4, "the lfo should only be saved once");
+
+        let dag = scene.generate_effect_dag(&registry);
+        let root = dag.nodes().last().unwrap().clone();
+        let loaded = Scene::from_effects(root, &registry).unwrap();
+        assert_eq!(loaded, scene, "the filter should still be bypassed");
     }
 }
@@ -23,4 +23,6 @@ pub mod nodegraph;
 pub mod playpausebutton;
 pub mod progresstracker;
 pub mod threadtracker;
+pub mod timeline;
+pub mod trimwidget;
 pub mod waveformwidget;
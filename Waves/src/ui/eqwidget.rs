@@ -41,6 +41,7 @@ impl EQWidget {
             ..(current_sample as i32 + data_width as i32 / 2);
 
         let track_len = track.length() as i32;
+        let track_data = track.data().0;
         let mut useful_sample_buffer;
 
         let useful_samples = if current_range.start < 0 || current_range.end >= track_len {
@@ -59,11 +60,11 @@ impl EQWidget {
             }
 
             useful_sample_buffer[start_in_useful..end_in_useful]
-                .copy_from_slice(&track.sample_data().0[start_in_track..end_in_track]);
+                .copy_from_slice(&track_data.get_full_data()[start_in_track..end_in_track]);
 
             &useful_sample_buffer[..]
         } else {
-            &track.sample_data().0[current_range.start as usize..current_range.end as usize]
+            &track_data.get_full_data()[current_range.start as usize..current_range.end as usize]
         };
 
         Self::new(useful_samples.to_vec(), sample_rate, (150.0, 75.0))
@@ -1,24 +1,57 @@
-use std::{hash::Hash, sync::Arc};
+use std::{any::Any, hash::Hash, sync::Arc};
 
-use eframe::{
-    egui::{
-        self, Color32, DragAndDrop, Grid, Id, InnerResponse, Label, LayerId, Order, Pos2, Rect,
-        Response, RichText, Sense, Shape, Stroke, Ui, UiBuilder, UiStackInfo, Vec2, ahash::HashMap,
-    },
-    epaint::{CircleShape, CubicBezierShape, PathStroke},
+use eframe::egui::{
+    self, Color32, Event, Id, Key, KeyboardShortcut, LayerId, Modifiers, Order, PointerButton,
+    Pos2, Rect, Response, RichText, Sense, Stroke, StrokeKind, Ui, UiBuilder, UiStackInfo, Vec2,
+    ahash::HashMap, emath::TSTransform,
 };
 
 use crate::{
-    audio::effects::{
-        Effect, add::Add, gain::Gain, output::Output, sinewave::SineWave, zero::Zero,
+    audio::{
+        effects::{
+            Effect,
+            feedback::{FeedbackBuses, FeedbackReceive, FeedbackSend},
+            group::{Group, GroupInput},
+            lane::Lane,
+            output::Output,
+            output_of, output_source,
+            zero::Zero,
+        },
+        port::{Conversion, PortType},
+        registry::{EffectData, EffectType, Registry},
+        switches,
+    },
+    common::presets,
+    ui::nodegraph::{
+        clipboard::{Fragment, PlacedEffect},
+        edge::Edge,
+        history::{Command, History},
+        layout::layered_layout,
+        node::{Node, NodeAction},
+        nodecircle::NodeCircleIdentifier,
     },
-    common::{dB, track::Track},
-    ui::nodegraph::{edge::Edge, node::Node, nodecircle::NodeCircleIdentifier},
 };
 
+mod clipboard;
 mod edge;
+pub mod history;
+mod layout;
 mod node;
 mod nodecircle;
+pub mod preview;
+
+/// How far the canvas can be zoomed out and in
+const MIN_ZOOM: f32 = 0.1;
+const MAX_ZOOM: f32 = 4.0;
+/// Space left around the nodes when framing them
+const FRAME_MARGIN: f32 = 40.0;
+/// How far duplicated nodes are put from the originals
+const DUPLICATE_OFFSET: Vec2 = Vec2::new(30.0, 30.0);
+/// The overview of the whole graph in the bottom right corner, and how far in from the corner it is
+const MINIMAP_SIZE: Vec2 = Vec2::new(200.0, 140.0);
+const MINIMAP_MARGIN: f32 = 8.0;
+/// What a node is taken to take up when laying out before it has been drawn
+const DEFAULT_NODE_SIZE: Vec2 = Vec2::new(220.0, 160.0);
 
 #[derive(Default, Debug)]
 pub struct GraphAudioData {
@@ -52,19 +85,25 @@ pub struct GraphStyle {
 
     connected_colour: Color32,
     disconnected_colour: Color32,
-    line_colour: Color32,
+    pub line_colour: Color32,
+
+    audio_port_colour: Color32,
+    control_port_colour: Color32,
+    midi_port_colour: Color32,
 
     corner_radius: f32,
     margin: f32,
     plot_margin: f32,
 
-    plot_height: f32,
-    plot_width: f32,
+    pub plot_height: f32,
+    pub plot_width: f32,
 
     header_height: f32,
     header_text_size: f32,
 
     header_colour: Color32,
+    /// What a header is coloured towards as its effect takes up more of each block
+    hot_header_colour: Color32,
     header_text_colour: Color32,
 
     main_text_size: f32,
@@ -72,6 +111,12 @@ pub struct GraphStyle {
 
     main_colour: Color32,
     main_text_colour: Color32,
+
+    meter_colour: Color32,
+    clip_colour: Color32,
+
+    /// Colour the node headers by how long their effects take while playing
+    pub cpu_colours: bool,
 }
 
 impl Default for GraphStyle {
@@ -91,6 +136,10 @@ impl Default for GraphStyle {
             disconnected_colour: Color32::from_rgb(186, 79, 13),
             line_colour: Color32::from_rgb(200, 200, 200),
 
+            audio_port_colour: Color32::from_rgb(200, 200, 200),
+            control_port_colour: Color32::from_rgb(90, 160, 230),
+            midi_port_colour: Color32::from_rgb(200, 110, 210),
+
             corner_radius: 8.0,
             margin: 10.0,
             plot_margin: 0.0,
@@ -102,6 +151,7 @@ impl Default for GraphStyle {
             header_text_size: 20.0,
 
             header_colour: Color32::from_rgb(50, 50, 50),
+            hot_header_colour: Color32::from_rgb(190, 40, 30),
             header_text_colour: Color32::from_rgb(220, 220, 220),
 
             main_text_size: 16.0,
@@ -109,18 +159,60 @@ impl Default for GraphStyle {
 
             main_colour: Color32::DARK_GRAY,
             main_text_colour: Color32::from_rgb(200, 200, 200),
+
+            meter_colour: Color32::from_rgb(13, 160, 15),
+            clip_colour: Color32::from_rgb(220, 40, 30),
+
+            cpu_colours: false,
         }
     }
 }
 
+impl GraphStyle {
+    pub fn port_colour(&self, port: PortType) -> Color32 {
+        match port {
+            PortType::Audio { .. } => self.audio_port_colour,
+            PortType::Control => self.control_port_colour,
+            PortType::Midi => self.midi_port_colour,
+        }
+    }
+}
+
+/// The transform (canvas to screen) that fits bounds in the middle of target,
+/// never zooming in past 1:1 so a single node doesn't fill the screen
+fn fit_transform(bounds: Rect, target: Rect) -> TSTransform {
+    let target = target.shrink(FRAME_MARGIN);
+    let scale = (target.width() / bounds.width())
+        .min(target.height() / bounds.height())
+        .clamp(MIN_ZOOM, 1.0);
+    TSTransform::from_translation(target.center().to_vec2())
+        * TSTransform::from_scaling(scale)
+        * TSTransform::from_translation(-bounds.center().to_vec2())
+}
+
+/// The transform (canvas to minimap) that fits all of bounds in the middle of target, however big it is
+fn minimap_transform(bounds: Rect, target: Rect) -> TSTransform {
+    let scale = (target.width() / bounds.width()).min(target.height() / bounds.height());
+    TSTransform::from_translation(target.center().to_vec2())
+        * TSTransform::from_scaling(scale)
+        * TSTransform::from_translation(-bounds.center().to_vec2())
+}
+
+/// The (value, depth) of each of the effect's parameters, to spot which have been changed
+fn parameter_values(effect: &Arc<dyn Effect>) -> Vec<(f32, f32)> {
+    effect
+        .parameters()
+        .iter()
+        .map(|p| (p.get(), p.depth()))
+        .collect()
+}
+
 pub struct ArcWrapper(Arc<dyn Effect>);
 
+/// Compared by the address of the effect alone, as the same effect can come with different vtables (eg from a tap)
 impl PartialEq for ArcWrapper {
     fn eq(&self, other: &Self) -> bool {
-        let s = Arc::into_raw(self.0.clone());
-        let sother = Arc::into_raw(other.0.clone());
-
-        s == sother
+        std::ptr::addr_eq(Arc::as_ptr(&self.0), Arc::as_ptr(&other.0))
     }
 }
 
@@ -128,9 +220,7 @@ impl Eq for ArcWrapper {}
 
 impl Hash for ArcWrapper {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        let s = Arc::into_raw(self.0.clone());
-
-        s.hash(state);
+        Arc::as_ptr(&self.0).cast::<()>().hash(state);
     }
 }
 
@@ -142,31 +232,88 @@ pub struct NodeGraph {
     pub output: Arc<Output>,
     hash: HashMap<ArcWrapper, usize>,
     pub audio_data: GraphAudioData,
+    pub history: History,
+    /// Open while picking an effect to add by typing its name
+    palette: Option<Palette>,
+    /// Where the last right click on the background was, for the menu's new node to go
+    menu_pos: Pos2,
+    /// Every kind of effect that can be added, by id
+    pub registry: Registry,
+    /// From the canvas the nodes are placed on to the screen, for panning and zooming
+    transform: TSTransform,
+    /// Where on the screen the box being dragged out to select nodes was started
+    box_start: Option<Pos2>,
+    /// The group whose insides this graph is showing, if it isn't the top
+    group: Option<Arc<Group>>,
+    /// Inside a group the nodes can be rewired and changed, but not added or taken away
+    fixed_nodes: bool,
+    /// The group opened up to be edited, which is shown instead of this graph
+    inner: Option<Box<NodeGraph>>,
+    /// The group being saved as a preset, with the name being typed in for it
+    saving_preset: Option<(Arc<dyn Effect>, String)>,
+    /// Lay the nodes out again once they have all been drawn, and so have their real sizes
+    needs_layout: bool,
+    /// This graph's output and those of the graphs it was opened from, which all play any node soloed in it
+    solo_outputs: Vec<Arc<Output>>,
+    /// The feedback buses by name, which are shared with the graphs inside groups
+    buses: Arc<FeedbackBuses>,
+}
+
+/// The searchable list of effects, with where the chosen one goes
+struct Palette {
+    search: String,
+    pos: Pos2,
 }
 
 impl NodeGraph {
+    /// A graph with one of each kind of effect in to play with
     pub fn new_non_trivial() -> Self {
         let mut s = Self::new();
 
-        let g1 = Arc::new(Gain::new(dB(-12.0), s.zero.clone()));
-        let g2 = Arc::new(Gain::new(dB(12.0), s.zero.clone()));
-        let s1 = Arc::new(SineWave::new(0.5, 440.0, 0.0));
-        let s2 = Arc::new(SineWave::new(0.5, 480.0, 0.0));
-        let a1 = Arc::new(Add::new(s.zero.clone(), s.zero.clone()));
-
-        s.add_node(g1);
-        s.add_node(g2);
-        s.add_node(s1);
-        s.add_node(s2);
-        s.add_node(a1);
+        let effects = s
+            .registry
+            .types()
+            .iter()
+            .map(|effect_type| (effect_type.create)(s.zero.clone()))
+            .collect::<Vec<_>>();
+        for effect in effects {
+            s.add_node(effect);
+        }
 
         s.set_node_connection_status();
+        s.tidy();
         s
     }
 
     pub fn new() -> Self {
         let zero = Arc::new(Zero);
         let output = Arc::new(Output::new(zero.clone()));
+        Self::with_output(zero, output)
+    }
+
+    /// The graph inside a group, playing into the group's output, opened from the parent graph
+    fn for_group(group: Arc<Group>, parent: &NodeGraph) -> Self {
+        let mut graph = Self::with_output(Arc::new(Zero), group.output());
+        graph.solo_outputs.extend_from_slice(&parent.solo_outputs);
+        graph.buses = parent.buses.clone();
+        for input in group.inputs() {
+            graph.add_node(input.clone());
+        }
+        for effect in group.effects() {
+            graph.add_node(effect.clone());
+        }
+        for node in &mut graph.nodes {
+            node.set_deletable(false);
+        }
+
+        graph.fixed_nodes = true;
+        graph.group = Some(group);
+        graph.set_node_connection_status();
+        graph.tidy();
+        graph
+    }
+
+    fn with_output(zero: Arc<Zero>, output: Arc<Output>) -> Self {
         Self {
             nodes: vec![
                 Node::new(0, zero.clone(), 6.0),
@@ -178,36 +325,893 @@ impl NodeGraph {
             output: output.clone(),
             hash: Default::default(),
             audio_data: Default::default(),
+            history: Default::default(),
+            palette: None,
+            menu_pos: Pos2::ZERO,
+            registry: Registry::default(),
+            transform: TSTransform::IDENTITY,
+            box_start: None,
+            group: None,
+            fixed_nodes: false,
+            inner: None,
+            saving_preset: None,
+            needs_layout: false,
+            solo_outputs: vec![output],
+            buses: Default::default(),
         }
     }
 
-    pub fn add_track(&mut self, track: Arc<Track>) {
-        self.add_node(track);
+    /// Add the node for a lane just put on the timeline. This isn't in the history,
+    /// as undoing it would leave the lane on the timeline playing into nothing
+    pub fn add_lane(&mut self, lane: Arc<Lane>) {
+        self.add_node(lane);
     }
 
     fn add_node(&mut self, effect: Arc<dyn Effect>) {
         let index = self.nodes.len();
-        let node = Node::new(index, effect.clone(), 6.0);
+        let mut node = Node::new(index, effect.clone(), 6.0);
+        if let Some(effect_type) = effect.effect_id().and_then(|id| self.registry.find(id)) {
+            node.set_factory_presets(effect_type.presets);
+        }
+        self.buses.adopt(&effect);
         self.hash.insert(ArcWrapper(effect), index);
         self.nodes.push(node);
     }
 
+    /// Make a new effect of the type with a node at pos (on the screen), which can be undone
+    fn create_node(&mut self, effect_type: &EffectType, pos: Pos2) {
+        let effect = (effect_type.create)(self.zero.clone());
+        // a new send starts a bus of its own, and a new receive listens to the newest one
+        let bus = match effect_type.id {
+            "feedback_send" => Some(self.buses.unused_name()),
+            "feedback_receive" => Some(self.buses.newest_name()),
+            _ => None,
+        };
+        if let Some(bus) = bus {
+            let _ = effect.set_setting("bus", &bus);
+        }
+        self.place_node(effect, pos);
+    }
+
+    /// Add a node for a new effect at pos (on the screen), which can be undone
+    fn place_node(&mut self, effect: Arc<dyn Effect>, pos: Pos2) {
+        self.add_node(effect.clone());
+        let pos = self.transform.inverse() * pos;
+        if let Some(node) = self.nodes.last_mut() {
+            node.set_position(pos);
+        }
+        self.set_node_connection_status();
+
+        self.history.push(Command::AddNode(effect));
+    }
+
+    /// Right clicking the background gives a menu of every effect by category
+    fn add_menu_ui(&mut self, background: &Response) {
+        if background.secondary_clicked()
+            && let Some(pos) = background.interact_pointer_pos()
+        {
+            self.menu_pos = pos;
+        }
+
+        let mut chosen = None;
+        let mut chosen_preset = None;
+        let mut group = false;
+        background.context_menu(|ui| {
+            let any_selected = self.nodes.iter().any(|node| node.is_selected());
+            if ui
+                .add_enabled(any_selected, egui::Button::new("Group selected"))
+                .clicked()
+            {
+                group = true;
+            }
+            ui.separator();
+
+            for category in self.registry.categories() {
+                ui.menu_button(category, |ui| {
+                    let types = self.registry.types().iter();
+                    for effect_type in types.filter(|t| t.category == category) {
+                        if ui.button(effect_type.name).clicked() {
+                            chosen = Some(effect_type.clone());
+                        }
+                    }
+                });
+            }
+
+            let group_presets = presets::group_presets();
+            if !group_presets.is_empty() {
+                ui.menu_button("Groups", |ui| {
+                    for name in group_presets {
+                        if ui.button(&name).clicked() {
+                            chosen_preset = Some(name);
+                        }
+                    }
+                });
+            }
+        });
+
+        if let Some(effect_type) = chosen {
+            self.create_node(&effect_type, self.menu_pos);
+        }
+        if let Some(name) = chosen_preset {
+            let loaded = presets::load_group_preset(&name)
+                .map_err(|e| e.to_string())
+                .and_then(|data| {
+                    let zero = self.zero.clone();
+                    self.registry.load(&data, zero).map_err(|e| e.to_string())
+                });
+            match loaded {
+                Ok(effect) => self.place_node(effect, self.menu_pos),
+                Err(e) => println!("could not load {name}: {e}"),
+            }
+        }
+        if group {
+            self.group_selected();
+        }
+    }
+
+    /// Collapse the selected nodes into a single group node, which can be undone.
+    /// Inputs from outside the selection become the group's inputs, and anything outside taking
+    /// one of the selected nodes as an input takes the group instead. The group puts out
+    /// whichever was feeding out of the selection first, or the last selected if none were
+    fn group_selected(&mut self) {
+        let selected = self
+            .selected_effects()
+            .into_iter()
+            .filter(|(effect, _)| !(&**effect as &dyn Any).is::<Output>())
+            .collect::<Vec<_>>();
+        let Some(top_left) = selected.iter().map(|(_, pos)| *pos).reduce(Pos2::min) else {
+            return;
+        };
+        // an input taking one of a node's outputs counts as taking the node
+        let is_selected = |e: &Arc<dyn Effect>| {
+            let (e, _) = output_source(e.clone());
+            selected.iter().any(|(s, _)| Arc::ptr_eq(s, &e))
+        };
+        let mut dependants = vec![];
+        for node in &self.nodes {
+            let effect = node.effect();
+            if is_selected(&effect) {
+                continue;
+            }
+            for i in 0..effect.input_count() {
+                if let Ok(input) = effect.get_input_at_index(i)
+                    && is_selected(&input)
+                {
+                    dependants.push((effect.clone(), i, input));
+                }
+            }
+        }
+
+        // the group only has the one output, so only one of the selected outputs can be taken outside it
+        let taken = dependants.iter().map(|(_, _, output)| output);
+        if let Some((_, _, first)) = dependants.first()
+            && taken
+                .clone()
+                .any(|output| !std::ptr::addr_eq(Arc::as_ptr(output), Arc::as_ptr(first)))
+        {
+            println!(
+                "could not group: more than one of the selected outputs goes outside the group"
+            );
+            return;
+        }
+
+        let mut commands = vec![];
+
+        // each node outside feeding in becomes one of the group's inputs
+        let mut inputs: Vec<(Arc<dyn Effect>, Arc<GroupInput>)> = vec![];
+        for (effect, _) in &selected {
+            for i in 0..effect.input_count() {
+                let Ok(source) = effect.get_input_at_index(i) else {
+                    continue;
+                };
+                let is_zero = std::ptr::addr_eq(Arc::as_ptr(&source), Arc::as_ptr(&self.zero));
+                if is_selected(&source) || is_zero || self.find_output(source.clone()).is_none() {
+                    continue;
+                }
+
+                let group_input = match inputs.iter().find(|(s, _)| Arc::ptr_eq(s, &source)) {
+                    Some((_, group_input)) => group_input.clone(),
+                    None => {
+                        let name = format!("{} in", source.name());
+                        let group_input = Arc::new(GroupInput::new(name, source.clone()));
+                        inputs.push((source.clone(), group_input.clone()));
+                        group_input
+                    }
+                };
+                let _ = effect.set_input_at_index(i, group_input.clone());
+                commands.push(Command::SetInput {
+                    effect: effect.clone(),
+                    index: i,
+                    before: source,
+                    after: group_input,
+                });
+            }
+        }
+
+        let output = match dependants.first() {
+            Some((_, _, output)) => output.clone(),
+            None => selected[selected.len() - 1].0.clone(),
+        };
+        let group: Arc<dyn Effect> = Arc::new(Group::new(
+            "Group".to_string(),
+            inputs
+                .into_iter()
+                .map(|(_, group_input)| group_input)
+                .collect(),
+            selected.iter().map(|(effect, _)| effect.clone()).collect(),
+            output,
+            vec![],
+        ));
+
+        for (effect, index, before) in dependants {
+            let _ = effect.set_input_at_index(index, group.clone());
+            commands.push(Command::SetInput {
+                effect,
+                index,
+                before,
+                after: group.clone(),
+            });
+        }
+        for (effect, _) in &selected {
+            self.remove_node(effect.clone());
+            commands.push(Command::RemoveNode {
+                effect: effect.clone(),
+                dependants: vec![],
+            });
+        }
+
+        self.add_node(group.clone());
+        if let Some(node) = self.nodes.last_mut() {
+            node.set_position(top_left);
+            node.set_is_selected(true);
+        }
+        commands.push(Command::AddNode(group));
+        self.set_node_connection_status();
+
+        self.history.push(Command::Group(commands));
+    }
+
+    /// Set an effect's parameters (and settings) from a preset, which can be undone in one go.
+    /// Only the parameters are put back on undo as settings aren't kept in the history
+    fn recall_preset(&mut self, effect: Arc<dyn Effect>, data: &EffectData) {
+        let before = parameter_values(&effect);
+        if let Err(e) = self.registry.restore(&*effect, data) {
+            println!("could not recall preset: {e}");
+        }
+
+        let changes = before
+            .into_iter()
+            .zip(parameter_values(&effect))
+            .enumerate()
+            .filter(|(_, (before, after))| before != after)
+            .map(|(index, (before, after))| Command::Parameter {
+                effect: effect.clone(),
+                index,
+                before,
+                after,
+            })
+            .collect::<Vec<_>>();
+        if !changes.is_empty() {
+            self.history.push(Command::Group(changes));
+        }
+    }
+
+    /// The box to name a group in before saving it as a preset
+    fn save_preset_ui(&mut self, ui: &Ui) {
+        let Some((effect, name)) = &mut self.saving_preset else {
+            return;
+        };
+
+        let mut open = true;
+        let mut save = false;
+        egui::Window::new("Save group preset")
+            .collapsible(false)
+            .resizable(false)
+            .open(&mut open)
+            .show(ui.ctx(), |ui| {
+                ui.text_edit_singleline(name).request_focus();
+                let enter = ui.input(|i| i.key_pressed(Key::Enter));
+                save = (ui.button("Save").clicked() || enter) && !name.trim().is_empty();
+            });
+
+        if save {
+            let name = name.trim().to_string();
+            let saved = self.registry.save(&**effect).map_err(|e| e.to_string());
+            let saved = saved.and_then(|mut data| {
+                if let Some(group) = &mut data.group {
+                    group.name = name.clone();
+                }
+                presets::save_group_preset(&name, &data).map_err(|e| e.to_string())
+            });
+            if let Err(e) = saved {
+                println!("could not save {name}: {e}");
+            }
+        }
+        if save || !open {
+            self.saving_preset = None;
+        }
+    }
+
+    /// Put the nodes in columns from left to right, each after everything feeding into it,
+    /// ordered to cross as few edges as possible
+    fn tidy(&mut self) {
+        // the zero isn't drawn (or in the hash), so it is left out and connections to it don't count
+        let inputs = self
+            .nodes
+            .iter()
+            .skip(1)
+            .map(|node| {
+                let effect = node.effect();
+                (0..effect.input_count())
+                    .filter_map(|i| effect.get_input_at_index(i).ok())
+                    .filter_map(|input| self.find_output(input))
+                    .filter_map(|output| output.node_index.checked_sub(1))
+                    .collect()
+            })
+            .collect::<Vec<Vec<usize>>>();
+        let sizes = self
+            .nodes
+            .iter()
+            .skip(1)
+            .map(|node| match node.rect().is_finite() {
+                true => node.rect().size(),
+                false => DEFAULT_NODE_SIZE,
+            })
+            .collect::<Vec<_>>();
+
+        let positions = layered_layout(&inputs, &sizes);
+        for (node, position) in self.nodes.iter_mut().skip(1).zip(positions) {
+            node.move_to(position + Vec2::splat(FRAME_MARGIN));
+        }
+        self.needs_layout = self.nodes.iter().skip(1).any(|n| !n.rect().is_finite());
+    }
+
+    /// Buttons in the corner to lay the nodes out again, and to show what the port colours mean
+    fn tools_ui(&mut self, ui: &Ui, target: Rect) {
+        let tidy = egui::Area::new(ui.id().with("graph_tools"))
+            .order(Order::Foreground)
+            .fixed_pos(target.min + Vec2::splat(8.0))
+            .show(ui.ctx(), |ui| {
+                ui.horizontal(|ui| {
+                    let tidy = ui
+                        .button("Tidy")
+                        .on_hover_text("Lay the nodes out from left to right")
+                        .clicked();
+                    ui.menu_button("Ports", |ui| {
+                        for port in PortType::ALL {
+                            let colour = self.style.port_colour(port);
+                            ui.label(RichText::new(format!("⏺ {port}")).color(colour));
+                        }
+                    });
+                    ui.toggle_value(&mut self.style.cpu_colours, "CPU")
+                        .on_hover_text("Colour the nodes by how long they take while playing");
+                    tidy
+                })
+                .inner
+            })
+            .inner;
+        if tidy {
+            self.tidy();
+        }
+    }
+
+    /// Every node, coloured by whether it can be heard, with the part of the canvas on the screen outlined.
+    /// Clicking (or dragging) on it moves the screen there
+    fn minimap_ui(&mut self, ui: &Ui, target: Rect) {
+        let viewport = self.transform.inverse() * target;
+        let bounds = self
+            .nodes
+            .iter()
+            .skip(1) // the zero isn't drawn
+            .map(|node| node.rect())
+            .filter(|rect| rect.is_finite())
+            .fold(viewport, |bounds, rect| bounds.union(rect));
+        if !bounds.is_positive() {
+            return;
+        }
+
+        let minimap = egui::Area::new(ui.id().with("graph_minimap"))
+            .order(Order::Foreground)
+            .fixed_pos(target.max - MINIMAP_SIZE - Vec2::splat(MINIMAP_MARGIN))
+            .show(ui.ctx(), |ui| {
+                let (response, painter) =
+                    ui.allocate_painter(MINIMAP_SIZE, Sense::click_and_drag());
+                let rect = response.rect;
+                let to_minimap = minimap_transform(bounds, rect.shrink(MINIMAP_MARGIN));
+
+                painter.rect_filled(rect, self.style.corner_radius, self.style.header_colour);
+                for node in self.nodes.iter().skip(1) {
+                    if !node.rect().is_finite() {
+                        continue;
+                    }
+                    let colour = match node.is_connected_to_output() {
+                        true => self.style.connected_colour,
+                        false => self.style.disconnected_colour,
+                    };
+                    painter.rect_filled(to_minimap * node.rect(), 1.0, colour);
+                }
+                painter.rect_stroke(
+                    to_minimap * viewport,
+                    1.0,
+                    Stroke::new(1.0, self.style.line_colour),
+                    StrokeKind::Inside,
+                );
+
+                let pointer = response
+                    .interact_pointer_pos()
+                    .filter(|_| response.clicked() || response.dragged());
+                pointer.map(|pointer| to_minimap.inverse() * pointer)
+            })
+            .inner;
+
+        // the point clicked on goes in the middle of the screen
+        if let Some(centre) = minimap {
+            self.transform.translation =
+                target.center().to_vec2() - centre.to_vec2() * self.transform.scaling;
+        }
+    }
+
+    /// Middle drag the background to move around, scroll (or pinch) to zoom in around the pointer
+    fn pan_and_zoom(&mut self, ui: &Ui, background: &Response) {
+        if background.dragged_by(PointerButton::Middle) {
+            self.transform =
+                TSTransform::from_translation(background.drag_delta()) * self.transform;
+        }
+
+        let (scroll, zoom, pointer) = ui.input(|i| {
+            (
+                i.smooth_scroll_delta.y,
+                i.zoom_delta(),
+                i.pointer.hover_pos(),
+            )
+        });
+        let Some(pointer) = pointer.filter(|p| background.rect.contains(*p)) else {
+            return;
+        };
+        let zoom = zoom * (scroll / 200.0).exp();
+        if zoom != 1.0 {
+            let scale = (self.transform.scaling * zoom).clamp(MIN_ZOOM, MAX_ZOOM);
+            let zoom = scale / self.transform.scaling;
+            self.transform = TSTransform::from_translation(pointer.to_vec2())
+                * TSTransform::from_scaling(zoom)
+                * TSTransform::from_translation(-pointer.to_vec2())
+                * self.transform;
+        }
+    }
+
+    /// Fit the selected nodes (or every node if none are) onto the screen
+    fn frame(&mut self, target: Rect, selected_only: bool) {
+        let bounds = self
+            .nodes
+            .iter()
+            .skip(1) // the zero isn't drawn
+            .filter(|node| !selected_only || node.is_selected())
+            .map(|node| node.rect())
+            .fold(Rect::NOTHING, |bounds, rect| bounds.union(rect));
+
+        if bounds.is_positive() {
+            self.transform = fit_transform(bounds, target);
+        }
+    }
+
+    /// Home frames every node, F frames the selected ones
+    fn frame_shortcuts_ui(&mut self, ui: &Ui, target: Rect) {
+        if ui.ctx().wants_keyboard_input() {
+            return;
+        }
+        if ui.input_mut(|i| i.consume_key(Modifiers::NONE, Key::Home)) {
+            self.frame(target, false);
+        }
+        if ui.input_mut(|i| i.consume_key(Modifiers::NONE, Key::F)) {
+            let any_selected = self.nodes.iter().any(|node| node.is_selected());
+            self.frame(target, any_selected);
+        }
+    }
+
+    /// Each selected effect with the top left of its node on the canvas
+    fn selected_effects(&self) -> Vec<PlacedEffect> {
+        self.nodes
+            .iter()
+            .filter(|node| node.is_selected())
+            .map(|node| (node.effect(), node.rect().min))
+            .collect()
+    }
+
+    /// Add new nodes for the fragment at pos on the canvas, selecting just them, all of which is undone together
+    fn paste(&mut self, fragment: &Fragment, pos: Pos2) {
+        let pasted = match fragment.paste(&self.registry, self.zero.clone(), pos) {
+            Ok(pasted) => pasted,
+            Err(e) => {
+                println!("could not paste: {e}");
+                return;
+            }
+        };
+
+        self.nodes
+            .iter_mut()
+            .for_each(|node| node.set_is_selected(false));
+        let mut commands = vec![];
+        for (effect, pos) in pasted {
+            self.add_node(effect.clone());
+            if let Some(node) = self.nodes.last_mut() {
+                node.set_position(pos);
+                node.set_is_selected(true);
+            }
+            commands.push(Command::AddNode(effect));
+        }
+        self.set_node_connection_status();
+
+        self.history.push(Command::Group(commands));
+    }
+
+    /// Ctrl+C copies the selected nodes to the clipboard as RON, Ctrl+V pastes them at the pointer,
+    /// Ctrl+D duplicates them without going through the clipboard and Ctrl+G groups them
+    fn clipboard_ui(&mut self, ui: &Ui) {
+        if ui.ctx().wants_keyboard_input() {
+            return;
+        }
+
+        let (copy, pasted) = ui.input(|i| {
+            let copy = i.events.iter().any(|e| matches!(e, Event::Copy));
+            let pasted = i.events.iter().find_map(|e| match e {
+                Event::Paste(text) => Some(text.clone()),
+                _ => None,
+            });
+            (copy, pasted)
+        });
+
+        if copy {
+            let fragment = Fragment::copy(&self.selected_effects(), &self.registry);
+            if !fragment.is_empty() {
+                ui.ctx().copy_text(fragment.to_ron());
+            }
+        }
+
+        if let Some(fragment) = pasted.as_deref().and_then(Fragment::from_ron) {
+            let pos = ui
+                .input(|i| i.pointer.hover_pos())
+                .unwrap_or(ui.max_rect().center());
+            self.paste(&fragment, self.transform.inverse() * pos);
+        }
+
+        let group = KeyboardShortcut::new(Modifiers::COMMAND, Key::G);
+        if ui.input_mut(|i| i.consume_shortcut(&group)) {
+            self.group_selected();
+        }
+
+        let duplicate = KeyboardShortcut::new(Modifiers::COMMAND, Key::D);
+        if ui.input_mut(|i| i.consume_shortcut(&duplicate)) {
+            let selected = self.selected_effects();
+            let fragment = Fragment::copy(&selected, &self.registry);
+            if let Some(top_left) = selected.iter().map(|(_, pos)| *pos).reduce(Pos2::min) {
+                self.paste(&fragment, top_left + DUPLICATE_OFFSET);
+            }
+        }
+    }
+
+    /// While a group is open, a bar to go back out of it with the group's graph below.
+    /// None if there isn't one open
+    fn inner_graph_ui(&mut self, ui: &mut Ui) -> Option<Response> {
+        let inner = self.inner.as_mut()?;
+        inner.audio_data =
+            GraphAudioData::new(self.audio_data.current_sample, self.audio_data.sample_rate);
+        inner.style.cpu_colours = self.style.cpu_colours;
+
+        let mut back = false;
+        ui.horizontal(|ui| {
+            back = ui.button("⬅ Back").clicked();
+            if let Some(group) = &inner.group {
+                ui.label(group.name());
+            }
+        });
+        let rect = ui.available_rect_before_wrap();
+        let response = inner.node_graph_ui(&mut ui.new_child(UiBuilder::new().max_rect(rect)));
+
+        if back {
+            self.inner = None;
+        }
+        Some(response)
+    }
+
+    /// Dragging on the background draws a box, selecting the nodes it touches when let go (shift adds to them)
+    fn box_select_ui(&mut self, ui: &Ui, background: &Response) {
+        if background.drag_started_by(PointerButton::Primary) {
+            self.box_start = background.interact_pointer_pos();
+        }
+        let Some(start) = self.box_start else {
+            return;
+        };
+        let Some(end) = ui.input(|i| i.pointer.interact_pos()) else {
+            return;
+        };
+        let selection = Rect::from_two_pos(start, end);
+
+        if background.dragged_by(PointerButton::Primary) {
+            let colour = self.style.drag_colour;
+            ui.painter()
+                .rect_filled(selection, 0.0, colour.gamma_multiply(0.1));
+            ui.painter()
+                .rect_stroke(selection, 0.0, Stroke::new(1.0, colour), StrokeKind::Inside);
+        }
+
+        if background.drag_stopped() {
+            let add = ui.input(|i| i.modifiers.shift);
+            for node in self.nodes.iter_mut().skip(1) {
+                let touched = (self.transform * node.rect()).intersects(selection);
+                node.set_is_selected(touched || (add && node.is_selected()));
+            }
+            self.box_start = None;
+        }
+    }
+
+    /// Ctrl+Space brings up a box to type the name of an effect into, enter adds the first match
+    fn palette_ui(&mut self, ui: &mut Ui) {
+        let shortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::Space);
+        if ui.input_mut(|i| i.consume_shortcut(&shortcut)) {
+            let pos = ui
+                .input(|i| i.pointer.hover_pos())
+                .unwrap_or(ui.max_rect().center());
+            self.palette = Some(Palette {
+                search: String::new(),
+                pos,
+            });
+        }
+
+        let Some(palette) = &mut self.palette else {
+            return;
+        };
+        let registry = &self.registry;
+
+        let mut chosen = None;
+        let mut open = true;
+        egui::Window::new("Add effect")
+            .fixed_pos(palette.pos)
+            .collapsible(false)
+            .resizable(false)
+            .open(&mut open)
+            .show(ui.ctx(), |ui| {
+                ui.text_edit_singleline(&mut palette.search).request_focus();
+
+                let enter = ui.input(|i| i.key_pressed(Key::Enter));
+                let matches = registry
+                    .types()
+                    .iter()
+                    .filter(|t| t.matches(&palette.search));
+                for (i, effect_type) in matches.enumerate() {
+                    let label = format!("{} ({})", effect_type.name, effect_type.category);
+                    let response = ui.selectable_label(i == 0, label).on_hover_ui(|ui| {
+                        for schema in effect_type.parameter_schema() {
+                            ui.label(format!(
+                                "{}: {} ({}..={})",
+                                schema.name,
+                                schema.default,
+                                schema.range.start(),
+                                schema.range.end()
+                            ));
+                        }
+                    });
+                    if response.clicked() || (i == 0 && enter) {
+                        chosen = Some(effect_type.clone());
+                    }
+                }
+            });
+
+        let pos = palette.pos;
+        if chosen.is_some() || !open || ui.input(|i| i.key_pressed(Key::Escape)) {
+            self.palette = None;
+        }
+        if let Some(effect_type) = chosen {
+            self.create_node(&effect_type, pos);
+        }
+    }
+
+    /// Take the effect's node out, moving the nodes after it down to fill the gap
+    fn remove_node(&mut self, effect: Arc<dyn Effect>) {
+        let Some(&index) = self.get_node_index_from_effect(effect) else {
+            return;
+        };
+
+        self.nodes.remove(index);
+        for (i, node) in self.nodes.iter_mut().enumerate().skip(index) {
+            node.set_index(i);
+        }
+        // the zero and the output aren't in the hash, as when the nodes were added
+        self.hash = self
+            .nodes
+            .iter()
+            .skip(2)
+            .map(|node| (ArcWrapper(node.effect()), node.index()))
+            .collect();
+
+        self.set_node_connection_status();
+    }
+
+    /// Everything with (one of the outputs of) the effect as an input, as (effect, input index, which output)
+    fn dependants(&self, effect: &Arc<dyn Effect>) -> Vec<(Arc<dyn Effect>, usize, usize)> {
+        let mut dependants = vec![];
+        for node in &self.nodes {
+            let node_effect = node.effect();
+            for i in 0..node_effect.input_count() {
+                if let Ok(input) = node_effect.get_input_at_index(i)
+                    && let (source, output) = output_source(input)
+                    && Arc::ptr_eq(&source, effect)
+                {
+                    dependants.push((node_effect.clone(), i, output));
+                }
+            }
+        }
+        dependants
+    }
+
+    /// Delete the effect's node, with everything that took it as an input taking zero instead
+    fn delete_node(&mut self, effect: Arc<dyn Effect>) {
+        let dependants = self.dependants(&effect);
+        for (dependant, index, _) in &dependants {
+            let _ = dependant.set_input_at_index(*index, self.zero.clone());
+        }
+        self.remove_node(effect.clone());
+
+        self.history
+            .push(Command::RemoveNode { effect, dependants });
+    }
+
+    /// Make a change from the history happen, either the way it was first done or taking it back
+    fn apply_command(&mut self, command: Command, undo: bool) {
+        match command {
+            Command::AddNode(effect) => match undo {
+                true => self.remove_node(effect),
+                false => self.add_node(effect),
+            },
+            Command::RemoveNode { effect, dependants } => {
+                if undo {
+                    self.add_node(effect.clone());
+                }
+                for (dependant, index, output) in dependants {
+                    let input = match undo {
+                        true => output_of(&effect, output),
+                        false => self.zero.clone(),
+                    };
+                    let _ = dependant.set_input_at_index(index, input);
+                }
+                if !undo {
+                    self.remove_node(effect);
+                }
+            }
+            Command::SetInput {
+                effect,
+                index,
+                before,
+                after,
+            } => {
+                let input = if undo { before } else { after };
+                let _ = effect.set_input_at_index(index, input);
+            }
+            Command::Parameter {
+                effect,
+                index,
+                before,
+                after,
+            } => {
+                let (value, depth) = if undo { before } else { after };
+                if let Some(parameter) = effect.parameters().get(index) {
+                    parameter.set(value);
+                    parameter.set_depth(depth);
+                }
+            }
+            Command::TrackEdits {
+                track,
+                before,
+                after,
+            } => {
+                let (edits, trim) = if undo { before } else { after };
+                track.set_edits(edits);
+                track.set_trim(trim);
+            }
+            Command::Group(commands) => match undo {
+                true => commands
+                    .into_iter()
+                    .rev()
+                    .for_each(|command| self.apply_command(command, true)),
+                false => commands
+                    .into_iter()
+                    .for_each(|command| self.apply_command(command, false)),
+            },
+        }
+
+        self.set_node_connection_status();
+    }
+
+    pub fn undo(&mut self) {
+        if let Some(inner) = &mut self.inner {
+            return inner.undo();
+        }
+        if let Some(command) = self.history.undo() {
+            self.apply_command(command, true);
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if let Some(inner) = &mut self.inner {
+            return inner.redo();
+        }
+        if let Some(command) = self.history.redo() {
+            self.apply_command(command, false);
+        }
+    }
+
     /// This function is given data to add a new edge
     /// It checks if it is a valid edge, and if so it adds it (if not do a println for now)
     fn add_edge(&mut self, input: NodeCircleIdentifier, output: NodeCircleIdentifier) {
         // Check for updates (primarily this is detecting the instantiation of a)
         //println!("ADDING EDGE");
         let e = self.nodes[input.node_index].effect().clone();
-        let _ = e.set_input_at_index(
-            input.circle_index,
-            self.nodes[output.node_index].effect().clone(),
-        );
+        let before = e.get_input_at_index(input.circle_index);
+        let mut after = output_of(&self.nodes[output.node_index].effect(), output.circle_index);
+
+        // disconnecting (onto the zero) always goes, otherwise the ports have to fit
+        let mut commands = vec![];
+        if output != NodeCircleIdentifier::ZERO_OUTPUT {
+            let from = self.get_node_circle_port(output);
+            let to = self.get_node_circle_port(input);
+            match from.conversion_to(to) {
+                Conversion::Direct => (),
+                Conversion::Rejected => {
+                    println!("cannot connect {from} to {to}");
+                    return;
+                }
+                // there's nowhere to keep a converter inside a group
+                Conversion::Through(_) if self.fixed_nodes => {
+                    println!("cannot connect {from} to {to} inside a group");
+                    return;
+                }
+                Conversion::Through(converter) => {
+                    let converter = converter.create(after);
+                    let between = self.nodes[input.node_index]
+                        .rect()
+                        .center()
+                        .lerp(self.nodes[output.node_index].rect().center(), 0.5);
+                    self.add_node(converter.clone());
+                    if let Some(node) = self.nodes.last_mut()
+                        && between.is_finite()
+                    {
+                        node.set_position(between);
+                    }
+                    commands.push(Command::AddNode(converter.clone()));
+                    after = converter;
+                }
+            }
+        }
+
+        if e.set_input_at_index(input.circle_index, after.clone())
+            .is_ok()
+            && let Ok(before) = before
+        {
+            commands.push(Command::SetInput {
+                effect: e,
+                index: input.circle_index,
+                before,
+                after,
+            });
+        }
+        match commands.len() {
+            0 => (),
+            1 => self.history.push(commands.remove(0)),
+            _ => self.history.push(Command::Group(commands)),
+        }
         //self.edges.push(Edge::new(input, output));
 
         // Now just recalculate the connected
         self.set_node_connection_status();
     }
 
+    fn get_node_circle_port(&self, identifier: NodeCircleIdentifier) -> PortType {
+        let effect = self.nodes[identifier.node_index].effect();
+        match identifier.circle_is_input {
+            true => effect.input_type(identifier.circle_index),
+            false => effect.output_type(identifier.circle_index),
+        }
+    }
+
     fn get_node_circle_pos(&self, identifier: NodeCircleIdentifier) -> Pos2 {
         self.nodes[identifier.node_index]
             .get_circle_pos(identifier.circle_index, identifier.circle_is_input)
@@ -248,6 +1252,7 @@ impl NodeGraph {
             match next_effect {
                 Err(_) => continue,
                 Ok(next_effect) => {
+                    let (next_effect, _) = output_source(next_effect);
                     if self.dfs_upstream_path(&ArcWrapper(next_effect), destination_effect) {
                         return true;
                     }
@@ -286,7 +1291,7 @@ impl NodeGraph {
             let child = self.nodes[index].effect().get_input_at_index(i).unwrap();
 
             // use the hash map to get the next index
-            let wrapper = ArcWrapper(child);
+            let wrapper = ArcWrapper(output_source(child).0);
             let child_index = self.hash.get(&wrapper).unwrap_or(&0);
             self.iterate_node_connection_status(*child_index);
         }
@@ -301,34 +1306,163 @@ impl NodeGraph {
 
         // Start from output (ie index zero and iterate)
         self.iterate_node_connection_status(1);
+        self.find_soloed();
+    }
+
+    /// Have the outputs look again for what is soloed, as it is too slow to do while playing
+    fn find_soloed(&self) {
+        for output in &self.solo_outputs {
+            output.find_soloed();
+        }
     }
 
     fn get_node_index_from_effect(&self, effect: Arc<dyn Effect>) -> Option<&usize> {
         self.hash.get(&ArcWrapper(effect))
     }
 
+    /// The (send, receive) node indices of every feedback send and receive on the same bus
+    fn feedback_links(&self) -> Vec<(usize, usize)> {
+        let buses = self
+            .nodes
+            .iter()
+            .map(|node| {
+                let effect = node.effect();
+                let effect = &*effect as &dyn Any;
+                if let Some(send) = effect.downcast_ref::<FeedbackSend>() {
+                    Some((true, send.bus_name()))
+                } else {
+                    effect
+                        .downcast_ref::<FeedbackReceive>()
+                        .map(|receive| (false, receive.bus_name()))
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut links = vec![];
+        for (send, send_bus) in buses.iter().enumerate() {
+            for (receive, receive_bus) in buses.iter().enumerate() {
+                if let (Some((true, a)), Some((false, b))) = (send_bus, receive_bus)
+                    && a == b
+                {
+                    links.push((send, receive));
+                }
+            }
+        }
+        links
+    }
+
+    /// The node and output circle an input is connected to, if it is one in the graph
+    fn find_output(&self, input: Arc<dyn Effect>) -> Option<NodeCircleIdentifier> {
+        let (effect, output) = output_source(input);
+        let &node_index = self.get_node_index_from_effect(effect)?;
+        Some(NodeCircleIdentifier::new(node_index, output, false))
+    }
+
     pub fn node_graph_ui(&mut self, ui: &mut eframe::egui::Ui) -> Response {
         let scope = tracing::trace_span!("node_graph_ui");
         let _span = scope.enter();
 
         // let mut area = area.begin(ctx);
 
+        if let Some(response) = self.inner_graph_ui(ui) {
+            return response;
+        }
+
+        let background = ui.interact(
+            ui.max_rect(),
+            ui.id().with("graph_background"),
+            Sense::click_and_drag(),
+        );
+        if background.clicked() {
+            self.nodes
+                .iter_mut()
+                .for_each(|node| node.set_is_selected(false));
+        }
+        self.pan_and_zoom(ui, &background);
+        self.frame_shortcuts_ui(ui, background.rect);
+        self.box_select_ui(ui, &background);
+        if !self.fixed_nodes {
+            self.clipboard_ui(ui);
+            self.add_menu_ui(&background);
+            self.palette_ui(ui);
+        }
+        self.save_preset_ui(ui);
+        self.tools_ui(ui, background.rect);
+        self.minimap_ui(ui, background.rect);
+
         // do node ui and find if we need a new edge
         let mut r = None;
         let mut i = None;
+        let mut clicked = None;
+        let mut moved = None;
         for j in 0..self.nodes.len() {
             if j == 0 {
                 continue;
             }
 
             let node = &mut self.nodes[j];
-            let inner_resp = node.node_ui(ui, &self.style, &self.audio_data);
+            let effect = node.effect();
+            let parameters_before = parameter_values(&effect);
+
+            let inner_resp = node.node_ui(ui, &self.style, &self.audio_data, self.transform);
+            let response = &inner_resp.response;
+            if response.clicked() || (response.drag_started() && !node.is_selected()) {
+                clicked = Some(j);
+            }
+            if response.dragged() && node.is_selected() {
+                moved = Some((j, response.drag_delta()));
+            }
+
+            for (index, (before, after)) in parameters_before
+                .into_iter()
+                .zip(parameter_values(&effect))
+                .enumerate()
+            {
+                if before != after {
+                    self.history.push(Command::Parameter {
+                        effect: effect.clone(),
+                        index,
+                        before,
+                        after,
+                    });
+                }
+            }
 
             r = Some(inner_resp.response);
 
             i = i.or(inner_resp.inner);
         }
 
+        // shift clicking adds or takes away a node, otherwise it is the only one selected
+        if let Some(clicked) = clicked {
+            let toggle = ui.input(|i| i.modifiers.shift);
+            for (j, node) in self.nodes.iter_mut().enumerate() {
+                match (toggle, j == clicked) {
+                    (true, true) => node.set_is_selected(!node.is_selected()),
+                    (true, false) => (),
+                    (false, is_clicked) => node.set_is_selected(is_clicked),
+                }
+            }
+        }
+
+        // the rest of the selection follows the one being dragged
+        if let Some((dragged, delta)) = moved {
+            for (j, node) in self.nodes.iter_mut().enumerate() {
+                if j != dragged && node.is_selected() {
+                    node.move_by(delta);
+                }
+            }
+        }
+
+        if self.needs_layout {
+            self.tidy();
+        }
+
+        // a slider or edge drag is over once the mouse is let go of
+        if !ui.input(|i| i.pointer.any_down()) {
+            self.history.end_coalescing();
+        }
+
         match i.clone() {
             None => (),
             Some(j) => println!("{j:?}"),
@@ -352,13 +1486,63 @@ impl NodeGraph {
             }
         }
 
+        // only once the new edge is in, as its node indices are from before any removal
+        let action = self
+            .nodes
+            .iter_mut()
+            .find_map(|node| Some((node.take_action()?, node.effect())));
+        match action {
+            None => (),
+            Some((NodeAction::Remove, effect)) => self.delete_node(effect),
+            Some((NodeAction::Open, effect)) => {
+                let effect: Arc<dyn Any + Send + Sync> = effect;
+                if let Ok(group) = effect.downcast::<Group>() {
+                    let group = NodeGraph::for_group(group, self);
+                    self.inner = Some(Box::new(group));
+                }
+            }
+            Some((NodeAction::SaveGroup, effect)) => {
+                self.saving_preset = Some((effect, String::new()));
+            }
+            Some((NodeAction::SavePreset(name), effect)) => {
+                let saved = self.registry.save(&*effect).map_err(|e| e.to_string());
+                let saved = saved.and_then(|data| {
+                    presets::save_effect_preset(&name, &data).map_err(|e| e.to_string())
+                });
+                if let Err(e) = saved {
+                    println!("could not save {name}: {e}");
+                }
+            }
+            Some((NodeAction::LoadPreset(name), effect)) => {
+                let id = effect.effect_id().unwrap_or_default();
+                match presets::load_effect_preset(id, &name) {
+                    Ok(data) => self.recall_preset(effect, &data),
+                    Err(e) => println!("could not load {name}: {e}"),
+                }
+            }
+            Some((NodeAction::DeletePreset(name), effect)) => {
+                let id = effect.effect_id().unwrap_or_default();
+                if let Err(e) = presets::delete_effect_preset(id, &name) {
+                    println!("could not delete {name}: {e}");
+                }
+            }
+            Some((NodeAction::Recall(data), effect)) => self.recall_preset(effect, &data),
+            Some((NodeAction::Switch(to), effect)) => {
+                switches::set(&effect, to);
+                self.find_soloed();
+            }
+        }
+
         // do edge ui
 
         // This is kinda a little hack to force the edges to exist in front of the nodes (I mean it makes sense tbf)
+        let edge_layer = LayerId::new(Order::Foreground, Id::new("UHH I WANT EDGES"));
+        ui.ctx().set_transform_layer(edge_layer, self.transform);
+        let clip_rect = self.transform.inverse() * ui.clip_rect();
         let ui = &mut ui.new_child(UiBuilder {
             id_salt: None,
             ui_stack_info: UiStackInfo::default(),
-            layer_id: Some(LayerId::new(Order::Foreground, Id::new("UHH I WANT EDGES"))),
+            layer_id: Some(edge_layer),
             max_rect: None,
             layout: None,
             disabled: false,
@@ -367,28 +1551,25 @@ impl NodeGraph {
             style: None,
             sense: None,
         });
+        ui.set_clip_rect(clip_rect);
 
         // Draw the edges by iterating through the data in each node
         for node in &self.nodes {
             // Get the edge data
             for i in 0..node.effect().input_count() {
                 let start_effect = node.effect().get_input_at_index(i).unwrap();
-                let start_index = self.get_node_index_from_effect(start_effect);
 
-                match start_index {
-                    None | Some(0) => (),
-                    Some(start_index) => {
+                match self.find_output(start_effect) {
+                    None => (),
+                    Some(start) if start.node_index == 0 => (),
+                    Some(start) => {
                         let edge = Edge::new(
                             NodeCircleIdentifier {
                                 node_index: node.index(),
                                 circle_index: i,
                                 circle_is_input: true,
                             },
-                            NodeCircleIdentifier {
-                                node_index: *start_index,
-                                circle_index: 0,
-                                circle_is_input: false,
-                            },
+                            start,
                         );
 
                         let colour = match node.is_connected_to_output() {
@@ -401,6 +1582,15 @@ impl NodeGraph {
                 }
             }
         }
+        // sends and receives aren't joined by an edge, so the loops they make are shown with a dashed line
+        for (send, receive) in self.feedback_links() {
+            let from = self.nodes[send].rect().right_center();
+            let to = self.nodes[receive].rect().left_center();
+            let stroke = Stroke::new(self.style.edge_line_width, self.style.line_colour);
+            ui.painter()
+                .extend(egui::Shape::dashed_line(&[from, to], stroke, 8.0, 6.0));
+        }
+
         // Draw the edges
         // for edge in &self.edges {
         //     edge.draw_edge(m, &self.style, self);
@@ -412,7 +1602,233 @@ impl NodeGraph {
 
 #[cfg(test)]
 mod test {
-    //use super::*;
+    use super::*;
+    use crate::{
+        audio::effects::{
+            events::{Clock, TriggerEnvelope},
+            gain::Gain,
+            pan::{Pan, PanLaw},
+            sinewave::SineWave,
+        },
+        audio::switches::Switches,
+        common::{
+            dB,
+            edit::Edit,
+            mipmapchannel::MipMapChannel,
+            track::{Track, TrackTrim},
+        },
+    };
+    use symphonia::core::codecs::CodecParameters;
+
+    fn input_of(effect: &Arc<dyn Effect>) -> Arc<dyn Effect> {
+        effect.get_input_at_index(0).unwrap()
+    }
+
+    #[test]
+    fn test_undo_edge_and_node() {
+        let mut graph = NodeGraph::new();
+        let gain: Arc<dyn Effect> = Arc::new(Gain::new(dB(0.0), graph.zero.clone()));
+        let sine: Arc<dyn Effect> = Arc::new(SineWave::new(0.5, 440.0, 0.0));
+        graph.add_node(gain.clone());
+        graph.add_node(sine.clone());
+
+        graph.add_edge(
+            NodeCircleIdentifier {
+                node_index: 2,
+                circle_index: 0,
+                circle_is_input: true,
+            },
+            NodeCircleIdentifier {
+                node_index: 3,
+                circle_index: 0,
+                circle_is_input: false,
+            },
+        );
+        assert!(Arc::ptr_eq(&input_of(&gain), &sine));
+
+        graph.undo();
+        assert!(Arc::ptr_eq(
+            &input_of(&gain),
+            &(graph.zero.clone() as Arc<dyn Effect>)
+        ));
+        graph.redo();
+        assert!(Arc::ptr_eq(&input_of(&gain), &sine));
+
+        // loading a track goes on the timeline too, so undo skips over it
+        graph.add_lane(Arc::new(Lane::new("lane".to_string())));
+        assert_eq!(graph.nodes.len(), 5);
+        graph.undo();
+        assert_eq!(graph.nodes.len(), 5);
+        assert!(Arc::ptr_eq(
+            &input_of(&gain),
+            &(graph.zero.clone() as Arc<dyn Effect>)
+        ));
+
+        // the nodes after a removed one move down, and the map from effects follows them
+        graph.remove_node(gain);
+        assert_eq!(graph.nodes[2].index(), 2);
+        assert_eq!(graph.get_node_index_from_effect(sine), Some(&2));
+    }
+
+    #[test]
+    fn test_delete_node() {
+        let mut graph = NodeGraph::new();
+        let zero: Arc<dyn Effect> = graph.zero.clone();
+        let sine: Arc<dyn Effect> = Arc::new(SineWave::new(0.5, 440.0, 0.0));
+        let gain: Arc<dyn Effect> = Arc::new(Gain::new(dB(0.0), sine.clone()));
+        let pan: Arc<dyn Effect> = Arc::new(Pan::new(0.0, PanLaw::Linear, sine.clone()));
+        graph.add_node(sine.clone());
+        graph.add_node(gain.clone());
+        graph.add_node(pan.clone());
+
+        graph.delete_node(sine.clone());
+        assert_eq!(graph.nodes.len(), 4);
+        assert!(Arc::ptr_eq(&input_of(&gain), &zero));
+        assert!(Arc::ptr_eq(&input_of(&pan), &zero));
+        assert_eq!(graph.get_node_index_from_effect(pan.clone()), Some(&3));
+        // the zero and the output are still left out of the map, as before anything was removed
+        assert_eq!(graph.find_output(zero.clone()), None);
+        assert!(
+            graph
+                .get_node_index_from_effect(graph.output.clone())
+                .is_none()
+        );
+
+        graph.undo();
+        assert_eq!(graph.nodes.len(), 5);
+        assert!(Arc::ptr_eq(&input_of(&gain), &sine));
+        assert!(Arc::ptr_eq(&input_of(&pan), &sine));
+        assert_eq!(graph.get_node_index_from_effect(sine.clone()), Some(&4));
+
+        graph.redo();
+        assert!(graph.get_node_index_from_effect(sine).is_none());
+        assert!(Arc::ptr_eq(&input_of(&gain), &zero));
+    }
+
+    #[test]
+    fn test_tidy_after_delete() {
+        let mut graph = NodeGraph::new();
+        let sine: Arc<dyn Effect> = Arc::new(SineWave::new(0.5, 440.0, 0.0));
+        let gain: Arc<dyn Effect> = Arc::new(Gain::new(dB(0.0), sine.clone()));
+        let pan: Arc<dyn Effect> = Arc::new(Pan::new(0.0, PanLaw::Linear, gain.clone()));
+        graph.add_node(sine.clone());
+        graph.add_node(gain.clone());
+        graph.add_node(pan.clone());
+
+        // the gain takes the zero now, which isn't laid out
+        graph.delete_node(sine);
+        assert_eq!(graph.find_output(input_of(&gain)), None);
+        graph.tidy();
+        assert!(
+            graph.needs_layout,
+            "nothing has been drawn to be measured yet"
+        );
+    }
+
+    #[test]
+    fn test_group_selected() {
+        let mut graph = NodeGraph::new();
+        let sine: Arc<dyn Effect> = Arc::new(SineWave::new(0.5, 440.0, 0.0));
+        let gain: Arc<dyn Effect> = Arc::new(Gain::new(dB(-3.0), sine.clone()));
+        let output: Arc<dyn Effect> = graph.output.clone();
+        graph.add_node(sine.clone());
+        graph.add_node(gain.clone());
+        output.set_input_at_index(0, gain.clone()).unwrap();
+        graph.nodes[3].set_is_selected(true);
+
+        graph.group_selected();
+        assert_eq!(graph.nodes.len(), 4);
+        let group = graph.nodes[3].effect();
+        assert_eq!(group.input_count(), 1);
+        assert!(Arc::ptr_eq(&input_of(&group), &sine));
+        assert!(Arc::ptr_eq(&input_of(&output), &group));
+
+        graph.undo();
+        assert_eq!(graph.nodes.len(), 4);
+        assert!(Arc::ptr_eq(&input_of(&gain), &sine));
+        assert!(Arc::ptr_eq(&input_of(&output), &gain));
+        assert!(graph.get_node_index_from_effect(group).is_none());
+    }
+
+    #[test]
+    fn test_group_leaves_out_zero() {
+        let mut graph = NodeGraph::new();
+        let zero: Arc<dyn Effect> = graph.zero.clone();
+        let gain: Arc<dyn Effect> = Arc::new(Gain::new(dB(-3.0), zero.clone()));
+        graph.add_node(gain.clone());
+        graph.nodes[2].set_is_selected(true);
+
+        graph.group_selected();
+        let group = graph.nodes[2].effect();
+        assert_eq!(group.input_count(), 0);
+        assert!(Arc::ptr_eq(&input_of(&gain), &zero));
+    }
+
+    #[test]
+    fn test_group_refuses_two_outputs() {
+        let mut graph = NodeGraph::new();
+        let sine: Arc<dyn Effect> = Arc::new(SineWave::new(0.5, 440.0, 0.0));
+        let gain: Arc<dyn Effect> = Arc::new(Gain::new(dB(-3.0), sine.clone()));
+        let other: Arc<dyn Effect> = Arc::new(Gain::new(dB(-6.0), sine.clone()));
+        let output: Arc<dyn Effect> = graph.output.clone();
+        graph.add_node(sine.clone());
+        graph.add_node(gain.clone());
+        graph.add_node(other.clone());
+        output.set_input_at_index(0, gain.clone()).unwrap();
+        graph.nodes[2].set_is_selected(true);
+        graph.nodes[3].set_is_selected(true);
+
+        // the sine goes out to the other gain and the gain to the output, which one group can't do
+        graph.group_selected();
+        assert_eq!(graph.nodes.len(), 5);
+        assert!(Arc::ptr_eq(&input_of(&other), &sine));
+        assert!(Arc::ptr_eq(&input_of(&output), &gain));
+        assert!(Arc::ptr_eq(&input_of(&gain), &sine));
+    }
+
+    #[test]
+    fn test_duplicate_undoes_together() {
+        let mut graph = NodeGraph::new();
+        let sine: Arc<dyn Effect> = Arc::new(SineWave::new(0.5, 440.0, 0.0));
+        let gain: Arc<dyn Effect> = Arc::new(Gain::new(dB(-3.0), sine.clone()));
+        graph.add_node(sine.clone());
+        graph.add_node(gain.clone());
+
+        let fragment = Fragment::copy(
+            &[(sine, Pos2::ZERO), (gain, Pos2::new(200.0, 0.0))],
+            &graph.registry,
+        );
+        graph.paste(&fragment, DUPLICATE_OFFSET.to_pos2());
+        assert_eq!(graph.nodes.len(), 6);
+        assert_eq!(graph.selected_effects().len(), 2);
+        let copy = graph.nodes[5].effect();
+        assert!(Arc::ptr_eq(&input_of(&copy), &graph.nodes[4].effect()));
+
+        graph.undo();
+        assert_eq!(graph.nodes.len(), 4);
+        graph.redo();
+        assert_eq!(graph.nodes.len(), 6);
+    }
+
+    #[test]
+    fn test_recall_preset_undoes_together() {
+        let mut graph = NodeGraph::new();
+        let sine = graph
+            .registry
+            .create("sine_wave", graph.zero.clone())
+            .unwrap();
+        graph.add_node(sine.clone());
+        let before = parameter_values(&sine);
+
+        let preset = &graph.registry.find("sine_wave").unwrap().presets[1];
+        graph.recall_preset(sine.clone(), &preset.data("sine_wave", &*sine));
+        assert_eq!(sine.parameters()[2].get(), 55.0);
+
+        graph.undo();
+        assert_eq!(parameter_values(&sine), before);
+        graph.redo();
+        assert_eq!(sine.parameters()[2].get(), 55.0);
+    }
 
     // #[test]
     // fn test_graph_works() {
@@ -440,4 +1856,218 @@ mod test {
     //         None,
     //     );
     // }
+
+    #[test]
+    fn test_audio_into_control_is_converted() {
+        let mut graph = NodeGraph::new();
+        let sine: Arc<dyn Effect> = Arc::new(SineWave::new(0.5, 440.0, 0.0));
+        let gain: Arc<dyn Effect> = Arc::new(Gain::new(dB(-3.0), graph.zero.clone()));
+        graph.add_node(sine.clone());
+        graph.add_node(gain.clone());
+        let unmodulated = gain.get_input_at_index(1).unwrap();
+
+        // the gain's modulation input takes a control signal, so sine's loudness is followed into it
+        graph.add_edge(
+            NodeCircleIdentifier::new(3, 1, true),
+            NodeCircleIdentifier::new(2, 0, false),
+        );
+        assert_eq!(graph.nodes.len(), 5);
+        let converter = gain.get_input_at_index(1).unwrap();
+        assert_eq!(converter.name(), "Envelope Follower");
+        assert!(Arc::ptr_eq(&input_of(&converter), &sine));
+
+        // audio into audio is left as it is
+        graph.add_edge(
+            NodeCircleIdentifier::new(3, 0, true),
+            NodeCircleIdentifier::new(2, 0, false),
+        );
+        assert!(Arc::ptr_eq(&input_of(&gain), &sine));
+
+        graph.undo();
+        graph.undo();
+        assert_eq!(graph.nodes.len(), 4);
+        assert!(Arc::ptr_eq(
+            &gain.get_input_at_index(1).unwrap(),
+            &unmodulated
+        ));
+    }
+
+    #[test]
+    fn test_solo_follows_connections() {
+        let mut graph = NodeGraph::new();
+        let sine: Arc<dyn Effect> = Arc::new(SineWave::new(0.5, 440.0, 0.0));
+        let gain: Arc<dyn Effect> = Arc::new(Gain::new(dB(-6.0), graph.zero.clone()));
+        graph.add_node(sine.clone());
+        graph.add_node(gain.clone());
+        graph.add_edge(
+            NodeCircleIdentifier::new(1, 0, true),
+            NodeCircleIdentifier::new(3, 0, false),
+        );
+        switches::set(
+            &sine,
+            Switches {
+                solo: true,
+                ..Default::default()
+            },
+        );
+
+        let mut dry = vec![0.0; 128];
+        sine.apply(&mut dry, 0, 2);
+        let output = graph.output.clone();
+        let played = || {
+            let mut played = vec![0.0; 128];
+            output.apply(&mut played, 0, 2);
+            played
+        };
+
+        // the sine isn't heard until it is connected, and then on its own
+        graph.add_edge(
+            NodeCircleIdentifier::new(3, 0, true),
+            NodeCircleIdentifier::new(2, 0, false),
+        );
+        assert_eq!(played(), dry);
+        graph.undo();
+        assert!(played().iter().all(|s| *s == 0.0));
+    }
+
+    #[test]
+    fn test_feedback_buses_kept_to_the_graph() {
+        let mut graph = NodeGraph::new();
+        let send = graph.registry.find("feedback_send").unwrap().clone();
+        let receive = graph.registry.find("feedback_receive").unwrap().clone();
+        graph.create_node(&send, Pos2::ZERO);
+        graph.create_node(&send, Pos2::ZERO);
+        graph.create_node(&receive, Pos2::ZERO);
+        let bus =
+            |graph: &NodeGraph, index: usize| graph.nodes[index].effect().settings()[0].1.clone();
+        assert_eq!(bus(&graph, 2), "bus 1");
+        assert_eq!(bus(&graph, 3), "bus 2");
+        assert_eq!(
+            bus(&graph, 4),
+            "bus 2",
+            "a receive listens to the newest bus"
+        );
+
+        // another graph has buses of its own
+        let mut other = NodeGraph::new();
+        other.create_node(&send, Pos2::ZERO);
+        assert_eq!(bus(&other, 2), "bus 1");
+    }
+
+    #[test]
+    fn test_undo_edit_puts_trim_back() {
+        let mut parameters = CodecParameters::new();
+        parameters.with_n_frames(100).with_sample_rate(48000);
+        let track = Arc::new(Track::new(
+            None,
+            parameters,
+            MipMapChannel::new(vec![0.5; 100], 5),
+            MipMapChannel::new(vec![0.5; 100], 5),
+        ));
+        let trim = TrackTrim {
+            start: 20,
+            end: Some(60),
+            ..Default::default()
+        };
+        track.set_trim(trim);
+
+        let mut graph = NodeGraph::new();
+        let before = (track.edits(), track.trim());
+        track.edit(Edit::Delete(0..10));
+        graph.history.push(Command::TrackEdits {
+            track: track.clone(),
+            before,
+            after: (track.edits(), track.trim()),
+        });
+        assert_eq!(track.trim().end, Some(50));
+
+        graph.undo();
+        assert_eq!(track.trim(), trim);
+        graph.redo();
+        assert_eq!(track.trim().end, Some(50));
+    }
+
+    #[test]
+    fn test_events_into_audio_are_rejected() {
+        let mut graph = NodeGraph::new();
+        let clock: Arc<dyn Effect> = Arc::new(Clock::new(120.0, 1.0));
+        let gain: Arc<dyn Effect> = Arc::new(Gain::new(dB(-3.0), graph.zero.clone()));
+        graph.add_node(clock.clone());
+        graph.add_node(gain.clone());
+
+        // there is no sensible way to play ticks as sound, so the gain keeps its input
+        graph.add_edge(
+            NodeCircleIdentifier::new(3, 0, true),
+            NodeCircleIdentifier::new(2, 0, false),
+        );
+        assert_eq!(graph.nodes.len(), 4);
+        let zero: Arc<dyn Effect> = graph.zero.clone();
+        assert!(Arc::ptr_eq(&input_of(&gain), &zero));
+
+        // but they can trigger an envelope
+        let envelope: Arc<dyn Effect> =
+            Arc::new(TriggerEnvelope::new(1.0, 50.0, graph.zero.clone()));
+        graph.add_node(envelope.clone());
+        graph.add_edge(
+            NodeCircleIdentifier::new(4, 0, true),
+            NodeCircleIdentifier::new(2, 0, false),
+        );
+        assert!(Arc::ptr_eq(&input_of(&envelope), &clock));
+    }
+
+    #[test]
+    fn test_inputs_keep_their_output() {
+        let mut graph = NodeGraph::new();
+        let splitter = graph
+            .registry
+            .create("band_splitter", graph.zero.clone())
+            .unwrap();
+        let output: Arc<dyn Effect> = graph.output.clone();
+        graph.add_node(splitter.clone());
+
+        graph.add_edge(
+            NodeCircleIdentifier::new(1, 0, true),
+            NodeCircleIdentifier::new(2, 1, false),
+        );
+        let mid = output_of(&splitter, 1);
+        assert!(Arc::ptr_eq(&input_of(&output), &mid));
+        assert_eq!(
+            graph.find_output(mid.clone()),
+            Some(NodeCircleIdentifier::new(2, 1, false))
+        );
+        assert!(graph.nodes[2].is_connected_to_output());
+
+        graph.delete_node(splitter);
+        assert_eq!(graph.find_output(input_of(&output)), None);
+        graph.undo();
+        assert!(Arc::ptr_eq(&input_of(&output), &mid));
+    }
+
+    #[test]
+    fn test_minimap_transform() {
+        let target = Rect::from_min_size(Pos2::new(600.0, 400.0), Vec2::new(200.0, 140.0));
+        let bounds = Rect::from_min_size(Pos2::new(-3000.0, 0.0), Vec2::new(8000.0, 1000.0));
+
+        let shrunk = minimap_transform(bounds, target) * bounds;
+        assert!(target.expand(1e-3).contains_rect(shrunk));
+        assert!((shrunk.width() - target.width()).abs() < 1e-3);
+        assert!((shrunk.center() - target.center()).length() < 1e-3);
+    }
+
+    #[test]
+    fn test_fit_transform() {
+        let target = Rect::from_min_size(Pos2::ZERO, Vec2::new(880.0, 480.0));
+
+        // too big to fit at 1:1 so it is zoomed out to just fit
+        let bounds = Rect::from_min_size(Pos2::new(1000.0, -200.0), Vec2::new(1600.0, 400.0));
+        let fitted = fit_transform(bounds, target) * bounds;
+        assert!((fitted.width() - 800.0).abs() < 1e-3);
+        assert!((fitted.center() - target.center()).length() < 1e-3);
+
+        // small ones aren't blown up
+        let bounds = Rect::from_min_size(Pos2::new(-50.0, -50.0), Vec2::splat(100.0));
+        let fitted = fit_transform(bounds, target) * bounds;
+        assert_eq!(fitted.size(), bounds.size());
+        assert!((fitted.center() - target.center()).length() < 1e-3);
+    }
 }
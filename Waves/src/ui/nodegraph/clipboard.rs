@@ -0,0 +1,170 @@
+use std::sync::Arc;
+
+use eframe::egui::{Pos2, Vec2};
+use serde::{Deserialize, Serialize};
+
+use crate::audio::{
+    effects::{Effect, output_of, output_source},
+    registry::{EffectData, Registry, RegistryError},
+};
+
+/// An effect with the top left of its node on the canvas
+pub type PlacedEffect = (Arc<dyn Effect>, Pos2);
+
+/// One of the copied nodes
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct FragmentNode {
+    pub effect: EffectData,
+    /// Where the node is from the top left of everything copied
+    pub offset: (f32, f32),
+    /// (input index, node) for each input taken from another copied node,
+    /// the rest are left on zero when pasted
+    #[serde(default)]
+    pub inputs: Vec<(usize, usize)>,
+    /// (input index, which output) for the inputs taking one of the outputs of a node with several
+    #[serde(default)]
+    pub outputs: Vec<(usize, usize)>,
+}
+
+/// Some nodes with the connections between them, as put on the clipboard as RON
+/// so they can be pasted into another graph (or another copy of the app)
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default)]
+pub struct Fragment {
+    pub nodes: Vec<FragmentNode>,
+}
+
+impl Fragment {
+    /// Save the effects, each with the top left of its node, skipping any that can't be made
+    /// by the registry (eg the output) along with connections to them
+    pub fn copy(effects: &[PlacedEffect], registry: &Registry) -> Self {
+        let copied = effects
+            .iter()
+            .filter_map(|(effect, pos)| Some((effect, *pos, registry.save(&**effect).ok()?)))
+            .collect::<Vec<_>>();
+        let top_left = copied
+            .iter()
+            .map(|(_, pos, _)| *pos)
+            .reduce(|a, b| a.min(b))
+            .unwrap_or(Pos2::ZERO);
+
+        let nodes = copied
+            .iter()
+            .map(|(effect, pos, data)| {
+                let mut inputs = vec![];
+                let mut outputs = vec![];
+                for i in 0..effect.input_count() {
+                    let Ok(input) = effect.get_input_at_index(i) else {
+                        continue;
+                    };
+                    let is_tap = input.tap_source().is_some();
+                    let (input, output) = output_source(input);
+                    if let Some(from) = copied.iter().position(|(e, ..)| Arc::ptr_eq(e, &input)) {
+                        inputs.push((i, from));
+                        if is_tap {
+                            outputs.push((i, output));
+                        }
+                    }
+                }
+                let offset = *pos - top_left;
+                FragmentNode {
+                    effect: data.clone(),
+                    offset: (offset.x, offset.y),
+                    inputs,
+                    outputs,
+                }
+            })
+            .collect();
+
+        Self { nodes }
+    }
+
+    /// Make new effects from the fragment with their nodes' top left corners, placing the whole thing at pos
+    pub fn paste(
+        &self,
+        registry: &Registry,
+        zero: Arc<dyn Effect>,
+        pos: Pos2,
+    ) -> Result<Vec<PlacedEffect>, RegistryError> {
+        let effects = self
+            .nodes
+            .iter()
+            .map(|node| registry.load(&node.effect, zero.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (node, effect) in self.nodes.iter().zip(&effects) {
+            for &(index, from) in &node.inputs {
+                let output = node.outputs.iter().find(|(i, _)| *i == index);
+                let input = match (effects.get(from), output) {
+                    (Some(input), Some(&(_, output))) => output_of(input, output),
+                    (Some(input), None) => input.clone(),
+                    (None, _) => continue,
+                };
+                effect.set_input_at_index(index, input)?;
+            }
+        }
+
+        Ok(self
+            .nodes
+            .iter()
+            .zip(effects)
+            .map(|(node, effect)| (effect, pos + Vec2::new(node.offset.0, node.offset.1)))
+            .collect())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn to_ron(&self) -> String {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::new()).unwrap_or_default()
+    }
+
+    /// None if the text isn't a fragment, eg something else was copied
+    pub fn from_ron(text: &str) -> Option<Self> {
+        ron::from_str(text).ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audio::effects::zero::Zero;
+
+    #[test]
+    fn test_copy_and_paste() {
+        let registry = Registry::default();
+        let zero: Arc<dyn Effect> = Arc::new(Zero);
+
+        let sine = registry.create("sine_wave", zero.clone()).unwrap();
+        let outside = registry.create("oscillator", zero.clone()).unwrap();
+        let gain = registry.create("gain", zero.clone()).unwrap();
+        gain.parameters()[0].set(-6.0);
+        let add = registry.create("add", zero.clone()).unwrap();
+        add.set_input_at_index(0, gain.clone()).unwrap();
+        add.set_input_at_index(1, outside).unwrap();
+        gain.set_input_at_index(0, sine.clone()).unwrap();
+
+        let fragment = Fragment::copy(
+            &[
+                (sine, Pos2::new(100.0, 50.0)),
+                (gain, Pos2::new(300.0, 80.0)),
+                (add, Pos2::new(500.0, 60.0)),
+            ],
+            &registry,
+        );
+        let fragment = Fragment::from_ron(&fragment.to_ron()).unwrap();
+        let pasted = fragment
+            .paste(&registry, zero.clone(), Pos2::new(0.0, 0.0))
+            .unwrap();
+
+        let (sine, gain, add) = (&pasted[0].0, &pasted[1].0, &pasted[2].0);
+        assert_eq!(pasted[1].1, Pos2::new(200.0, 30.0));
+        assert_eq!(gain.parameters()[0].get(), -6.0);
+        assert!(Arc::ptr_eq(&gain.get_input_at_index(0).unwrap(), sine));
+        assert!(Arc::ptr_eq(&add.get_input_at_index(0).unwrap(), gain));
+        // the oscillator wasn't copied so its connection isn't either
+        assert!(Arc::ptr_eq(&add.get_input_at_index(1).unwrap(), &zero));
+
+        assert!(Fragment::from_ron("not a fragment").is_none());
+    }
+}
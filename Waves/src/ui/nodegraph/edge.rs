@@ -42,6 +42,7 @@ impl Edge {
         graph.get_node_circle_pos(self.output)
     }
 
+    /// The rim of the edge is coloured by what goes through it
     fn draw_outer(&self, ui: &mut Ui, style: &GraphStyle, graph: &NodeGraph) {
         //println!("SHOULD BE WORKING");
         let input = self.get_input_pos(graph);
@@ -52,7 +53,7 @@ impl Edge {
             input,
             output,
             style.edge_line_width * 2.0 + style.edge_inner_width,
-            style.edge_outer_colour,
+            style.port_colour(graph.get_node_circle_port(self.output)),
         );
     }
 
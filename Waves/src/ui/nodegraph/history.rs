@@ -0,0 +1,186 @@
+use std::sync::Arc;
+
+use crate::{
+    audio::effects::Effect,
+    common::{
+        edit::Edit,
+        track::{Track, TrackTrim},
+    },
+};
+
+/// How many changes back can be undone
+const MAX_HISTORY: usize = 500;
+
+/// A change to the graph (or something in it) with enough kept to do it again or take it back.
+/// Effects are held by Arc rather than by node index as the indices move when nodes are removed
+#[derive(Clone)]
+pub enum Command {
+    AddNode(Arc<dyn Effect>),
+    /// A node being deleted, along with the (effect, input index, which output) of everything that took it as an input
+    RemoveNode {
+        effect: Arc<dyn Effect>,
+        dependants: Vec<(Arc<dyn Effect>, usize, usize)>,
+    },
+    /// An input being pointed at a different effect, which is how edges are connected and disconnected
+    SetInput {
+        effect: Arc<dyn Effect>,
+        index: usize,
+        before: Arc<dyn Effect>,
+        after: Arc<dyn Effect>,
+    },
+    /// The (value, depth) of one of an effect's parameters
+    Parameter {
+        effect: Arc<dyn Effect>,
+        index: usize,
+        before: (f32, f32),
+        after: (f32, f32),
+    },
+    /// A track's edits, with its trim as that moves along with the audio edited under it
+    TrackEdits {
+        track: Arc<Track>,
+        before: (Vec<Edit>, TrackTrim),
+        after: (Vec<Edit>, TrackTrim),
+    },
+    /// Several changes made at once (eg pasting some nodes) that are undone together
+    Group(Vec<Command>),
+}
+
+impl Command {
+    /// If both change the same parameter or input, join the other onto the end of this one
+    fn coalesce(&mut self, other: &Command) -> bool {
+        match (self, other) {
+            (
+                Command::Parameter {
+                    effect,
+                    index,
+                    after,
+                    ..
+                },
+                Command::Parameter {
+                    effect: other_effect,
+                    index: other_index,
+                    after: other_after,
+                    ..
+                },
+            ) if Arc::ptr_eq(effect, other_effect) && index == other_index => {
+                *after = *other_after;
+                true
+            }
+            (
+                Command::SetInput {
+                    effect,
+                    index,
+                    after,
+                    ..
+                },
+                Command::SetInput {
+                    effect: other_effect,
+                    index: other_index,
+                    after: other_after,
+                    ..
+                },
+            ) if Arc::ptr_eq(effect, other_effect) && index == other_index => {
+                *after = other_after.clone();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The undo and redo stacks
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Command>,
+    redo: Vec<Command>,
+    /// While a slider (or edge) is being dragged its changes all go into the one command
+    coalescing: bool,
+}
+
+impl History {
+    /// Remember a change that has just been made, which means anything undone can't be redone any more.
+    /// Changes to the same parameter or input are joined together until end_coalescing is called,
+    /// so dragging an edge off one output and onto another is a single change
+    pub fn push(&mut self, command: Command) {
+        self.redo.clear();
+
+        if self.coalescing
+            && let Some(last) = self.undo.last_mut()
+            && last.coalesce(&command)
+        {
+            return;
+        }
+
+        self.coalescing = matches!(
+            command,
+            Command::Parameter { .. } | Command::SetInput { .. }
+        );
+        self.undo.push(command);
+        if self.undo.len() > MAX_HISTORY {
+            self.undo.remove(0);
+        }
+    }
+
+    /// The next change starts a command of its own, eg once the mouse has been let go of
+    pub fn end_coalescing(&mut self) {
+        self.coalescing = false;
+    }
+
+    /// Take the last change off to be undone, putting it ready to be redone
+    pub fn undo(&mut self) -> Option<Command> {
+        self.coalescing = false;
+        let command = self.undo.pop()?;
+        self.redo.push(command.clone());
+        Some(command)
+    }
+
+    pub fn redo(&mut self) -> Option<Command> {
+        self.coalescing = false;
+        let command = self.redo.pop()?;
+        self.undo.push(command.clone());
+        Some(command)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audio::effects::zero::Zero;
+
+    fn parameter(effect: &Arc<dyn Effect>, before: f32, after: f32) -> Command {
+        Command::Parameter {
+            effect: effect.clone(),
+            index: 0,
+            before: (before, 0.0),
+            after: (after, 0.0),
+        }
+    }
+
+    #[test]
+    fn test_slider_drags_coalesce() {
+        let effect: Arc<dyn Effect> = Arc::new(Zero);
+        let mut history = History::default();
+
+        history.push(parameter(&effect, 0.0, 1.0));
+        history.push(parameter(&effect, 1.0, 2.0));
+        history.push(parameter(&effect, 2.0, 3.0));
+        history.end_coalescing();
+        history.push(parameter(&effect, 3.0, 4.0));
+
+        let Some(Command::Parameter { before, after, .. }) = history.undo() else {
+            panic!("expected a parameter change");
+        };
+        assert_eq!((before.0, after.0), (3.0, 4.0));
+
+        let Some(Command::Parameter { before, after, .. }) = history.undo() else {
+            panic!("expected a parameter change");
+        };
+        assert_eq!((before.0, after.0), (0.0, 3.0));
+        assert!(history.undo().is_none());
+
+        // redoing comes back in the same order, until something new is done
+        assert!(history.redo().is_some());
+        history.push(parameter(&effect, 3.0, 5.0));
+        assert!(history.redo().is_none());
+    }
+}
@@ -0,0 +1,210 @@
+use eframe::egui::{Pos2, Vec2};
+
+/// Space between columns, and between the nodes in a column
+const GAP: Vec2 = Vec2::new(80.0, 30.0);
+/// How many times the columns are reordered, going back and forth
+const SWEEPS: usize = 8;
+
+/// Where to put the top left of each node so they are in columns from left to right, each node
+/// to the right of everything feeding into it, ordered in their columns to cross as few edges as possible.
+/// inputs has the nodes feeding into each node, which must not loop back on themselves
+pub fn layered_layout(inputs: &[Vec<usize>], sizes: &[Vec2]) -> Vec<Pos2> {
+    let layers = assign_layers(inputs);
+    let layer_count = layers.iter().max().map_or(0, |l| l + 1);
+
+    // Edges going over more than one column go through invisible slots in the columns
+    // between, so they are kept out of the way of other nodes too. Slots after the nodes are these
+    let mut slot_layers = layers.clone();
+    let mut edges = vec![];
+    for (to, node_inputs) in inputs.iter().enumerate() {
+        let mut node_inputs = node_inputs.clone();
+        node_inputs.sort();
+        node_inputs.dedup();
+        for from in node_inputs {
+            let mut previous = from;
+            for layer in layers[from] + 1..layers[to] {
+                slot_layers.push(layer);
+                edges.push((previous, slot_layers.len() - 1));
+                previous = slot_layers.len() - 1;
+            }
+            edges.push((previous, to));
+        }
+    }
+
+    let mut columns = vec![vec![]; layer_count];
+    for (slot, &layer) in slot_layers.iter().enumerate() {
+        columns[layer].push(slot);
+    }
+
+    let mut best = columns.clone();
+    let mut fewest = count_crossings(&columns, &edges, &slot_layers);
+    for sweep in 0..SWEEPS {
+        if fewest == 0 {
+            break;
+        }
+        if sweep % 2 == 0 {
+            for layer in 1..layer_count {
+                order_by_barycentre(&mut columns, layer, layer - 1, &edges);
+            }
+        } else {
+            for layer in (0..layer_count.saturating_sub(1)).rev() {
+                order_by_barycentre(&mut columns, layer, layer + 1, &edges);
+            }
+        }
+
+        let crossings = count_crossings(&columns, &edges, &slot_layers);
+        if crossings < fewest {
+            fewest = crossings;
+            best = columns.clone();
+        }
+    }
+
+    place(&best, sizes)
+}
+
+/// The column of each node, which is the longest path to it from a node with no inputs
+fn assign_layers(inputs: &[Vec<usize>]) -> Vec<usize> {
+    fn layer_of(node: usize, inputs: &[Vec<usize>], layers: &mut [Option<usize>]) -> usize {
+        if let Some(layer) = layers[node] {
+            return layer;
+        }
+        let layer = inputs[node]
+            .iter()
+            .map(|&input| layer_of(input, inputs, layers) + 1)
+            .max()
+            .unwrap_or(0);
+        layers[node] = Some(layer);
+        layer
+    }
+
+    let mut layers = vec![None; inputs.len()];
+    (0..inputs.len())
+        .map(|node| layer_of(node, inputs, &mut layers))
+        .collect()
+}
+
+/// Sort a column by the average position of what each slot is connected to in the next column over,
+/// leaving the slots connected to nothing there where they were
+fn order_by_barycentre(
+    columns: &mut [Vec<usize>],
+    layer: usize,
+    neighbour_layer: usize,
+    edges: &[(usize, usize)],
+) {
+    let neighbour_position = |slot: usize| columns[neighbour_layer].iter().position(|&s| s == slot);
+
+    let mut keyed = columns[layer]
+        .iter()
+        .enumerate()
+        .map(|(position, &slot)| {
+            let neighbours = edges
+                .iter()
+                .filter_map(|&(from, to)| match (from == slot, to == slot) {
+                    (true, _) => neighbour_position(to),
+                    (_, true) => neighbour_position(from),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            let key = match neighbours.len() {
+                0 => position as f32,
+                n => neighbours.iter().sum::<usize>() as f32 / n as f32,
+            };
+            (key, slot)
+        })
+        .collect::<Vec<_>>();
+    keyed.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    columns[layer] = keyed.into_iter().map(|(_, slot)| slot).collect();
+}
+
+/// How many pairs of edges cross, which can only happen when they leave from the same column
+fn count_crossings(
+    columns: &[Vec<usize>],
+    edges: &[(usize, usize)],
+    slot_layers: &[usize],
+) -> usize {
+    let mut positions = vec![0; slot_layers.len()];
+    for column in columns {
+        for (position, &slot) in column.iter().enumerate() {
+            positions[slot] = position;
+        }
+    }
+
+    let lines = edges
+        .iter()
+        .map(|&(from, to)| (slot_layers[from], positions[from], positions[to]))
+        .collect::<Vec<_>>();
+    let mut crossings = 0;
+    for (i, &(layer, a_from, a_to)) in lines.iter().enumerate() {
+        for &(other_layer, b_from, b_to) in &lines[i + 1..] {
+            let crossed = a_from < b_from && a_to > b_to || a_from > b_from && a_to < b_to;
+            if layer == other_layer && crossed {
+                crossings += 1;
+            }
+        }
+    }
+    crossings
+}
+
+/// Stack each column from the top, centred against the tallest, with the columns as wide as their widest node
+fn place(columns: &[Vec<usize>], sizes: &[Vec2]) -> Vec<Pos2> {
+    let size_of = |slot: usize| sizes.get(slot).copied().unwrap_or(Vec2::ZERO);
+    let column_height = |column: &Vec<usize>| {
+        column
+            .iter()
+            .map(|&slot| size_of(slot).y + GAP.y)
+            .sum::<f32>()
+            - GAP.y
+    };
+    let tallest = columns.iter().map(column_height).fold(0.0, f32::max);
+
+    let mut positions = vec![Pos2::ZERO; sizes.len()];
+    let mut x = 0.0;
+    for column in columns {
+        let mut y = (tallest - column_height(column)) / 2.0;
+        for &slot in column {
+            if let Some(position) = positions.get_mut(slot) {
+                *position = Pos2::new(x, y);
+            }
+            y += size_of(slot).y + GAP.y;
+        }
+        x += column
+            .iter()
+            .map(|&slot| size_of(slot).x)
+            .fold(0.0, f32::max)
+            + GAP.x;
+    }
+    positions
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_layered_layout() {
+        // 0 and 1 are sources, 2 takes 1, 3 takes 0 and 2, so 0 has to reach over a column
+        let inputs = vec![vec![], vec![], vec![1], vec![0, 2]];
+        let sizes = vec![Vec2::new(100.0, 50.0); 4];
+        let positions = layered_layout(&inputs, &sizes);
+
+        assert_eq!(assign_layers(&inputs), vec![0, 0, 1, 2]);
+        assert_eq!(positions[0].x, positions[1].x);
+        assert!(positions[1].x < positions[2].x);
+        assert!(positions[2].x < positions[3].x);
+        assert_ne!(positions[0].y, positions[1].y);
+    }
+
+    #[test]
+    fn test_crossings_are_undone() {
+        // 0 feeds 3 and 1 feeds 2, which cross if left in the order they were given
+        let inputs = vec![vec![], vec![], vec![1], vec![0]];
+        let sizes = vec![Vec2::new(100.0, 50.0); 4];
+        let positions = layered_layout(&inputs, &sizes);
+
+        assert_eq!(
+            positions[0].y < positions[1].y,
+            positions[3].y < positions[2].y
+        );
+    }
+}
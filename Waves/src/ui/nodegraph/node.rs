@@ -1,21 +1,38 @@
+use std::any::Any;
 use std::sync::Arc;
 
+use eframe::egui::mutex::Mutex;
 use eframe::egui::{
-    self, Grid, InnerResponse, Label, Pos2, RadioButton, Rect, Response, RichText, Stroke, Ui, Vec2,
+    self, Button, Grid, InnerResponse, Label, LayerId, Order, Pos2, Rect, Response, RichText,
+    Sense, Stroke, Ui, Vec2, emath::TSTransform,
 };
 
 use crate::{
-    audio::effects::Effect,
+    audio::{
+        effects::{Effect, group::Group, output::Output},
+        meters::{self, Meter},
+        registry::{EffectData, FactoryPreset},
+        streamstate,
+        switches::Switches,
+    },
+    common::{dB, presets},
     ui::{
         eqwidget::EQWidget,
         nodegraph::{
             GraphAudioData, GraphStyle,
             nodecircle::{NodeCircle, NodeCircleIdentifier},
+            preview::Preview,
         },
         waveformwidget::WaveformWidget,
     },
 };
 
+/// The quietest level the meters show, in dB
+const METER_FLOOR: f32 = -60.0;
+const METER_HEIGHT: f32 = 6.0;
+/// How much of each block a node takes to have its header coloured all the way
+const HOT_LOAD: f32 = 0.1;
+
 #[derive(PartialEq, Debug, Clone)]
 pub enum PlotChoice {
     Wave,
@@ -42,7 +59,7 @@ pub fn draw_freq_plot(
         let scope = tracing::trace_span!("getting data");
         let _span = scope.enter();
 
-        effect.apply(&mut sample_data, start_sample, 1);
+        streamstate::previewing(|| effect.process(&mut sample_data, start_sample, 1));
     }
 
     let eq_widget = EQWidget::new(sample_data, sample_rate, plot_size);
@@ -51,6 +68,7 @@ pub fn draw_freq_plot(
 
 pub fn draw_waveform_plot(
     effect: Arc<dyn Effect>,
+    preview: Arc<Mutex<Preview>>,
     ui: &mut Ui,
     current_sample: usize,
     sample_rate: u32,
@@ -60,10 +78,66 @@ pub fn draw_waveform_plot(
     let _span = scope.enter();
 
     // do a waveform diagram
-    let waveform_widget = WaveformWidget::new(current_sample, effect, plot_size, false, None);
+    let waveform_widget =
+        WaveformWidget::new(current_sample, effect, plot_size, false, None).previewing(preview);
     waveform_widget.ui(ui, true);
 }
 
+/// A bar for the level, filled to the RMS with a line at the peak, and how long the effect takes
+fn meter_ui(ui: &mut Ui, style: &GraphStyle, meter: Meter) {
+    let position = |level: f32| {
+        let dB(level) = dB::from_amplitude(level);
+        ((level - METER_FLOOR) / -METER_FLOOR).clamp(0.0, 1.0)
+    };
+    let colour = match meter.peak >= 1.0 {
+        true => style.clip_colour,
+        false => style.meter_colour,
+    };
+
+    let (rect, _) = ui.allocate_exact_size(
+        Vec2::new(ui.available_width(), METER_HEIGHT),
+        Sense::hover(),
+    );
+    let painter = ui.painter();
+    painter.rect_filled(rect, 2.0, style.header_colour);
+    let mut level = rect;
+    level.set_width(rect.width() * position(meter.rms));
+    painter.rect_filled(level, 2.0, colour);
+    let peak = rect.left() + rect.width() * position(meter.peak);
+    painter.vline(peak, rect.y_range(), Stroke::new(2.0, colour));
+
+    let text = format!(
+        "{:.1} dB peak  {:.2} ms ({:.1}% CPU)",
+        dB::from_amplitude(meter.peak).0.max(-99.9),
+        meter.time * 1000.0,
+        meter.load * 100.0
+    );
+    ui.add(Label::new(
+        RichText::new(text)
+            .size(style.main_text_size)
+            .color(style.main_text_colour),
+    ));
+}
+
+/// Something pressed in a node's header, for the graph to do
+#[derive(PartialEq, Debug, Clone)]
+pub enum NodeAction {
+    Remove,
+    /// Go inside a group to edit it
+    Open,
+    /// Save a group to be added to other scenes
+    SaveGroup,
+    /// Save the effect's parameters under a name
+    SavePreset(String),
+    /// Set the effect's parameters to one of the user's presets, by name
+    LoadPreset(String),
+    DeletePreset(String),
+    /// Set the effect's parameters to a factory preset
+    Recall(EffectData),
+    /// Bypass, mute or solo the node
+    Switch(Switches),
+}
+
 #[derive(Clone)]
 pub struct Node {
     index: usize,
@@ -71,7 +145,24 @@ pub struct Node {
     effect: Arc<dyn Effect>,
 
     plot_choice: PlotChoice,
+    /// The effect's output as played, for the waveform plot
+    preview: Arc<Mutex<Preview>>,
     is_connected_to_output: bool,
+    /// Set when a header button has been pressed, until the graph takes it
+    action: Option<NodeAction>,
+    /// Inside a group the nodes can be changed but not taken away
+    deletable: bool,
+    /// The presets that come with this type of effect
+    factory_presets: &'static [FactoryPreset],
+    /// What's typed into the presets menu to save under
+    preset_name: String,
+    /// Where the node first shows up, or wherever egui puts it if None
+    position: Option<Pos2>,
+    is_selected: bool,
+    /// Where the node was drawn last frame, on the canvas rather than the screen
+    rect: Rect,
+    /// How far to move the node next frame, as it is dragged along with others
+    pending_move: Vec2,
 
     pub input_node_circles: Vec<NodeCircle>,
     pub output_node_circles: Vec<NodeCircle>,
@@ -84,17 +175,26 @@ impl Node {
 
             input_node_circles: (0..effect.input_count())
                 .into_iter()
-                .map(|i| NodeCircle::new(i, true, Pos2::ZERO, radius))
+                .map(|i| NodeCircle::new(i, true, Pos2::ZERO, radius, effect.input_type(i)))
                 .collect::<Vec<_>>(),
             output_node_circles: (0..effect.output_count())
                 .into_iter()
-                .map(|i| NodeCircle::new(i, false, Pos2::ZERO, radius))
+                .map(|i| NodeCircle::new(i, false, Pos2::ZERO, radius, effect.output_type(i)))
                 .collect::<Vec<_>>(),
 
             effect,
 
             plot_choice: PlotChoice::None,
+            preview: Default::default(),
             is_connected_to_output: false,
+            action: None,
+            deletable: true,
+            factory_presets: &[],
+            preset_name: String::new(),
+            position: None,
+            is_selected: false,
+            rect: Rect::NOTHING,
+            pending_move: Vec2::ZERO,
         }
     }
 
@@ -135,35 +235,136 @@ impl Node {
                         },
                 );
 
+                let header_colour = match meters::of(&self.effect) {
+                    Some(meter) if style.cpu_colours => style
+                        .header_colour
+                        .lerp_to_gamma(style.hot_header_colour, (meter.load / HOT_LOAD).min(1.0)),
+                    _ => style.header_colour,
+                };
+
+                // a node that is taken out of the sound is dimmed
+                let switches = self.effect.switches();
+                let header_colour = match switches.bypass || switches.mute {
+                    true => header_colour.gamma_multiply(0.5),
+                    false => header_colour,
+                };
+
                 // get the shapes
                 let shape_top = egui::frame::Frame::new()
                     .corner_radius(style.corner_radius - 1.0)
-                    .fill(style.header_colour)
+                    .fill(header_colour)
                     .paint(rect_top);
                 let shape_bottom = egui::frame::Frame::new()
-                    .fill(style.header_colour)
+                    .fill(header_colour)
                     .paint(rect_bottom);
 
                 ui.painter().add(shape_top);
                 ui.painter().add(shape_bottom);
 
-                ui.add(
-                    Label::new(
-                        RichText::new(self.effect.name())
-                            .heading()
-                            .size(
-                                style.header_height
-                                    - 2.0 * style.margin
-                                    - 2.0 * style.node_line_width,
-                            )
-                            .color(style.header_text_colour),
-                    )
-                    .selectable(false),
-                );
+                ui.horizontal(|ui| {
+                    ui.add(
+                        Label::new(
+                            RichText::new(self.effect.name())
+                                .heading()
+                                .size(
+                                    style.header_height
+                                        - 2.0 * style.margin
+                                        - 2.0 * style.node_line_width,
+                                )
+                                .color(style.header_text_colour),
+                        )
+                        .selectable(false),
+                    );
+
+                    let button = |ui: &mut Ui, text: &str, hover: &str| {
+                        ui.small_button(RichText::new(text).color(style.header_text_colour))
+                            .on_hover_text(hover)
+                            .clicked()
+                    };
+
+                    if (&*self.effect as &dyn Any).is::<Group>() {
+                        if button(ui, "⤵", "Open") {
+                            self.action = Some(NodeAction::Open);
+                        }
+                        if button(ui, "💾", "Save as preset") {
+                            self.action = Some(NodeAction::SaveGroup);
+                        }
+                    }
+
+                    if let Some(id) = self.effect.effect_id() {
+                        let text = RichText::new("☰").color(style.header_text_colour);
+                        ui.menu_button(text, |ui| self.presets_menu(ui, id))
+                            .response
+                            .on_hover_text("Presets");
+                    }
+
+                    if self.effect.switch_state().is_some() {
+                        self.switches_ui(ui, style);
+                    }
+                    // The output is always there to listen to
+                    let is_output = (&*self.effect as &dyn Any).is::<Output>();
+                    if !is_output && self.deletable && button(ui, "✖", "Delete") {
+                        self.action = Some(NodeAction::Remove);
+                    }
+                });
             });
         header.response
     }
 
+    /// Toggles for bypassing, muting and soloing the node, which are heard straight away
+    fn switches_ui(&mut self, ui: &mut Ui, style: &GraphStyle) {
+        let mut switches = self.effect.switches();
+        let before = switches;
+        for (on, text, hover) in [
+            (&mut switches.bypass, "B", "Bypass"),
+            (&mut switches.mute, "M", "Mute"),
+            (&mut switches.solo, "S", "Solo"),
+        ] {
+            ui.toggle_value(on, RichText::new(text).color(style.header_text_colour))
+                .on_hover_text(hover);
+        }
+        if switches != before {
+            self.action = Some(NodeAction::Switch(switches));
+        }
+    }
+
+    /// Factory presets, then the user's own (which can be deleted), then a box to save the current parameters
+    fn presets_menu(&mut self, ui: &mut Ui, id: &str) {
+        for preset in self.factory_presets {
+            if ui.button(preset.name).clicked() {
+                self.action = Some(NodeAction::Recall(preset.data(id, &*self.effect)));
+            }
+        }
+
+        let user_presets = presets::effect_presets(id);
+        if !self.factory_presets.is_empty() && !user_presets.is_empty() {
+            ui.separator();
+        }
+        for name in user_presets {
+            ui.horizontal(|ui| {
+                if ui.button(&name).clicked() {
+                    self.action = Some(NodeAction::LoadPreset(name.clone()));
+                }
+                if ui.small_button("🗑").on_hover_text("Delete").clicked() {
+                    self.action = Some(NodeAction::DeletePreset(name.clone()));
+                }
+            });
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.preset_name);
+            let name = self.preset_name.trim().to_string();
+            if ui
+                .add_enabled(!name.is_empty(), Button::new("Save"))
+                .clicked()
+            {
+                self.action = Some(NodeAction::SavePreset(name));
+                self.preset_name.clear();
+            }
+        });
+    }
+
     fn draw_main(&mut self, ui: &mut Ui, style: &GraphStyle, audio_data: &GraphAudioData) {
         //println!("{:?}", ui.style().spacing);
         egui::frame::Frame::new()
@@ -176,7 +377,7 @@ impl Node {
                         for i in 0..self.effect.input_count().max(self.effect.output_count()) {
                             if i < self.effect.input_count() {
                                 ui.add(Label::new(
-                                    RichText::new("input")
+                                    RichText::new(self.effect.input_name(i))
                                         .size(style.main_text_size)
                                         .color(style.main_text_colour),
                                 ));
@@ -186,7 +387,7 @@ impl Node {
 
                             if i < self.effect.output_count() {
                                 ui.add(Label::new(
-                                    RichText::new("output")
+                                    RichText::new(self.effect.output_name(i))
                                         .size(style.main_text_size)
                                         .color(style.main_text_colour),
                                 ));
@@ -198,6 +399,10 @@ impl Node {
                         }
                     });
 
+                if let Some(meter) = meters::of(&self.effect) {
+                    meter_ui(ui, style, meter);
+                }
+
                 // implement node specific data ie gain value
                 self.effect.data_ui(ui, style);
 
@@ -233,6 +438,7 @@ impl Node {
                 if self.plot_choice == PlotChoice::Wave {
                     draw_waveform_plot(
                         self.effect.clone(),
+                        self.preview.clone(),
                         ui,
                         audio_data.current_sample,
                         audio_data.sample_rate,
@@ -267,9 +473,13 @@ impl Node {
         style: &GraphStyle,
         audio_data: &GraphAudioData,
     ) {
+        let line_colour = match self.is_selected {
+            true => style.drag_colour,
+            false => style.line_colour,
+        };
         egui::frame::Frame::new()
             .outer_margin(style.node_circle_radius)
-            .stroke(Stroke::new(style.node_line_width, style.line_colour))
+            .stroke(Stroke::new(style.node_line_width, line_colour))
             .fill(style.main_colour)
             .corner_radius(style.corner_radius)
             .show(ui, |ui| {
@@ -441,19 +651,48 @@ impl Node {
         }
     }
 
-    /// Do the ui of the node
+    /// Do the ui of the node, placed on the canvas by transform (canvas to screen)
     /// Return data should be inner response with data about building a new edge if it is required
     pub fn node_ui(
         &mut self,
         ui: &mut Ui,
         style: &GraphStyle,
         audio_data: &GraphAudioData,
+        transform: TSTransform,
     ) -> InnerResponse<Option<(Arc<NodeCircleIdentifier>, Arc<NodeCircleIdentifier>)>> {
         let scope = tracing::trace_span!("node_ui", index = self.index);
         let _span = scope.enter();
 
+        // a group's inputs come and go as its parameters are brought out
+        if self.input_node_circles.len() != self.effect.input_count() {
+            self.input_node_circles = (0..self.effect.input_count())
+                .map(|i| {
+                    let port = self.effect.input_type(i);
+                    NodeCircle::new(i, true, Pos2::ZERO, style.node_circle_radius, port)
+                })
+                .collect();
+        }
+
         let mut new_edge_data = None;
-        let resp = egui::Area::new(egui::Id::new(format!("graph_node {}", self.index)))
+        // keyed on the effect rather than the index so nodes stay put when others are removed
+        let area_id = egui::Id::new(("graph_node", Arc::as_ptr(&self.effect).cast::<()>()));
+        ui.ctx()
+            .set_transform_layer(LayerId::new(Order::Middle, area_id), transform);
+
+        // clipped to the graph's panel, but free to go anywhere on the canvas outside of it
+        let mut area = egui::Area::new(area_id)
+            .order(Order::Middle)
+            .constrain_to(transform.inverse() * ui.clip_rect())
+            .constrain(false)
+            .sense(Sense::click_and_drag());
+        if let Some(position) = self.position {
+            area = area.default_pos(position);
+        }
+        if self.pending_move != Vec2::ZERO && self.rect.is_finite() {
+            area = area.current_pos(self.rect.min + self.pending_move);
+            self.pending_move = Vec2::ZERO;
+        }
+        let resp = area
             .show(ui.ctx(), |ui| {
                 let top_left = ui.next_widget_position();
 
@@ -465,6 +704,7 @@ impl Node {
                 //println!("NODE: {:?}", new_edge_data);
             })
             .response;
+        self.rect = resp.rect;
 
         //println!("{:?} AAAAAAAAAAAAAA", new_edge_data);
 
@@ -482,6 +722,52 @@ impl Node {
         self.index
     }
 
+    pub fn set_index(&mut self, index: usize) {
+        self.index = index;
+    }
+
+    pub fn set_position(&mut self, position: Pos2) {
+        self.position = Some(position);
+    }
+
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    /// Move the node next time it is drawn
+    pub fn move_by(&mut self, delta: Vec2) {
+        self.pending_move += delta;
+    }
+
+    /// Put the node's top left somewhere on the canvas, whether or not it has been drawn yet
+    pub fn move_to(&mut self, position: Pos2) {
+        self.position = Some(position);
+        if self.rect.is_finite() {
+            self.pending_move = position - self.rect.min;
+        }
+    }
+
+    pub fn set_is_selected(&mut self, is_selected: bool) {
+        self.is_selected = is_selected;
+    }
+
+    pub fn is_selected(&self) -> bool {
+        self.is_selected
+    }
+
+    /// The header button pressed since this was last called
+    pub fn take_action(&mut self) -> Option<NodeAction> {
+        self.action.take()
+    }
+
+    pub fn set_deletable(&mut self, deletable: bool) {
+        self.deletable = deletable;
+    }
+
+    pub fn set_factory_presets(&mut self, factory_presets: &'static [FactoryPreset]) {
+        self.factory_presets = factory_presets;
+    }
+
     pub fn set_is_connected_to_output(&mut self, is_connected_to_output: bool) {
         self.is_connected_to_output = is_connected_to_output;
     }
@@ -5,6 +5,7 @@ use eframe::{
     epaint::CircleShape,
 };
 
+use crate::audio::port::PortType;
 use crate::ui::nodegraph::{GraphStyle, edge::Edge};
 
 /// This is to collect data for the edge drag and drop to connect things up
@@ -16,6 +17,13 @@ pub struct NodeCircleIdentifier {
 }
 
 impl NodeCircleIdentifier {
+    /// The output of the zero node, which is what disconnected inputs take
+    pub const ZERO_OUTPUT: NodeCircleIdentifier = NodeCircleIdentifier {
+        node_index: 0,
+        circle_index: 0,
+        circle_is_input: false,
+    };
+
     pub fn new(node_index: usize, circle_index: usize, circle_is_input: bool) -> Self {
         NodeCircleIdentifier {
             node_index,
@@ -31,15 +39,18 @@ pub struct NodeCircle {
     pub is_input: bool,
     pub pos: Pos2,
     pub radius: f32,
+    /// What goes through it, which colours its rim
+    pub port: PortType,
 }
 
 impl NodeCircle {
-    pub fn new(index: usize, is_input: bool, pos: Pos2, radius: f32) -> Self {
+    pub fn new(index: usize, is_input: bool, pos: Pos2, radius: f32, port: PortType) -> Self {
         Self {
             index,
             is_input,
             pos,
             radius,
+            port,
         }
     }
 }
@@ -84,7 +95,9 @@ impl NodeCircle {
 
         let rect = get_generic_circle_rect(ui, self.pos, self.radius);
 
-        let r = ui.allocate_rect(rect, Sense::all());
+        let r = ui
+            .allocate_rect(rect, Sense::all())
+            .on_hover_text(self.port.to_string());
 
         //println!("{:?}", r);
         if r.dragged() {
@@ -144,15 +157,16 @@ impl NodeCircle {
                 self.radius,
                 style.node_line_width,
                 colour,
-                style.line_colour,
+                style.port_colour(self.port),
             );
         }
 
         let payload;
 
-        if r.drag_started() {
-            println!("OH YEAH BABY");
-            payload = Some(Arc::new(NodeCircleIdentifier::new(0, 0, false)));
+        // Starting a drag from an input or right clicking it disconnects it,
+        // by connecting it to zero's output until it is dropped on another output
+        if r.drag_started() || (self.is_input && r.secondary_clicked()) {
+            payload = Some(Arc::new(NodeCircleIdentifier::ZERO_OUTPUT));
         } else {
             payload = r.dnd_release_payload::<NodeCircleIdentifier>();
         }
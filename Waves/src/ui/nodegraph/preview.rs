@@ -0,0 +1,244 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::audio::effects::{
+    Effect,
+    group::{Group, GroupInput},
+    lane::Lane,
+};
+use crate::audio::streamstate;
+use crate::common::Channel;
+use crate::common::mipmapchannel::SamplePlotData;
+use crate::common::track::Track;
+
+/// How many frames each point of a preview covers, which is the step the node plots are drawn at
+pub const PREVIEW_STEP: usize = 2048;
+/// How many points are worked out each frame, so a preview fills in over a few frames rather than holding one up
+const POINTS_PER_FRAME: usize = 16;
+
+/// The (min, max) of the left and right channels over one step
+type Point = [(f32, f32); 2];
+
+/// A node's output as it is actually played, worked out through the effect a few points at a time.
+/// The points are kept until something upstream changes, and are shown until they have been worked out again
+#[derive(Default)]
+pub struct Preview {
+    /// By how many steps in they start, with the generation they were worked out in
+    points: HashMap<usize, (Point, u64)>,
+    generation: u64,
+    fingerprint: u64,
+}
+
+impl Preview {
+    /// Work out the next few points over range that are missing or out of date, from the start of it so
+    /// effects that keep state can carry on from one to the next. True once all of range is up to date
+    pub fn refresh(&mut self, effect: &Arc<dyn Effect>, range: Range<usize>) -> bool {
+        let fingerprint = fingerprint(effect);
+        if fingerprint != self.fingerprint {
+            self.fingerprint = fingerprint;
+            self.generation += 1;
+        }
+
+        let stale = (range.start / PREVIEW_STEP..range.end.div_ceil(PREVIEW_STEP))
+            .filter(|i| {
+                self.points
+                    .get(i)
+                    .is_none_or(|(_, generation)| *generation != self.generation)
+            })
+            .collect::<Vec<_>>();
+
+        let mut frames = vec![0.0; PREVIEW_STEP * 2];
+        for &index in stale.iter().take(POINTS_PER_FRAME) {
+            streamstate::previewing(|| effect.process(&mut frames, index * PREVIEW_STEP, 2));
+            let mut point = [(f32::MAX, f32::MIN); 2];
+            for frame in frames.chunks(2) {
+                for (channel, sample) in point.iter_mut().zip(frame) {
+                    *channel = (channel.0.min(*sample), channel.1.max(*sample));
+                }
+            }
+            self.points.insert(index, (point, self.generation));
+        }
+
+        stale.len() <= POINTS_PER_FRAME
+    }
+
+    /// Fill in the min and max of the channel, as far as it has been worked out
+    pub fn plot_data(&self, sample_plot_data: &mut SamplePlotData, channel: &Channel) {
+        let channel = match channel {
+            Channel::Left => 0,
+            Channel::Right => 1,
+        };
+        let step = sample_plot_data.step;
+        let start_sample = sample_plot_data.start_sample;
+        sample_plot_data.is_min_max = true;
+
+        let (min, max) = sample_plot_data.data.split_at_mut(1);
+        for (i, (min, max)) in min[0].iter_mut().zip(max[0].iter_mut()).enumerate() {
+            let start = start_sample + i * step;
+            let points = start / PREVIEW_STEP..(start + step).div_ceil(PREVIEW_STEP);
+            (*min, *max) = points
+                .filter_map(|index| self.points.get(&index))
+                .map(|(point, _)| point[channel])
+                .fold((0.0f32, 0.0f32), |(low, high), (l, h)| {
+                    (low.min(l), high.max(h))
+                });
+        }
+    }
+}
+
+/// Everything upstream of the effect (and the effect itself) that changes what it puts out,
+/// so a preview knows when to be worked out again
+pub fn fingerprint(effect: &Arc<dyn Effect>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    let mut seen: Vec<*const ()> = vec![];
+    let mut stack = vec![effect.clone()];
+
+    while let Some(effect) = stack.pop() {
+        let address = Arc::as_ptr(&effect).cast::<()>();
+        if seen.contains(&address) {
+            continue;
+        }
+        seen.push(address);
+        address.hash(&mut hasher);
+
+        for parameter in effect.parameters() {
+            parameter.get().to_bits().hash(&mut hasher);
+            parameter.depth().to_bits().hash(&mut hasher);
+        }
+        effect.settings().hash(&mut hasher);
+        let switches = effect.switches();
+        (switches.bypass, switches.mute, switches.solo).hash(&mut hasher);
+
+        for i in 0..effect.input_count() {
+            if let Ok(input) = effect.get_input_at_index(i) {
+                Arc::as_ptr(&input).cast::<()>().hash(&mut hasher);
+                stack.push(input);
+            }
+        }
+        if let Some((source, _)) = effect.tap_source() {
+            stack.push(source);
+        }
+
+        // the effects that play something that isn't one of their inputs
+        let any = &*effect as &dyn Any;
+        if let Some(track) = any.downcast_ref::<Track>() {
+            track_fingerprint(track, &mut hasher);
+        } else if let Some(lane) = any.downcast_ref::<Lane>() {
+            for clip in lane.clips() {
+                track_fingerprint(&clip, &mut hasher);
+            }
+        } else if let Some(group) = any.downcast_ref::<Group>() {
+            stack.push(group.output());
+        } else if let Some(input) = any.downcast_ref::<GroupInput>() {
+            stack.push(input.source());
+        }
+    }
+
+    hasher.finish()
+}
+
+/// A track changes with its trim, and its audio is swapped for a new copy whenever it is edited
+fn track_fingerprint(track: &Track, hasher: &mut DefaultHasher) {
+    track.trim().hash(hasher);
+    let (left, right) = track.data();
+    Arc::as_ptr(&left).hash(hasher);
+    Arc::as_ptr(&right).hash(hasher);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::audio::effects::{
+        chorus::Chorus,
+        distortion::{Curve, Distortion, Oversampling},
+        feedback::{FeedbackBuses, FeedbackReceive, FeedbackSend},
+        filter::{Filter, FilterMode},
+        sinewave::SineWave,
+        timestretch::TimeStretch,
+    };
+    use crate::common::dB;
+
+    #[test]
+    fn test_preview_is_played_output() {
+        let sine: Arc<dyn Effect> = Arc::new(SineWave::new(0.5, 440.0, 0.0));
+        let distortion = Arc::new(Distortion::new(
+            Curve::HardClip,
+            dB(20.0),
+            Oversampling::Off,
+            sine,
+        ));
+        let effect: Arc<dyn Effect> = distortion.clone();
+
+        let mut preview = Preview::default();
+        let mut peak = || {
+            while !preview.refresh(&effect, 0..PREVIEW_STEP * 4) {}
+            let mut data = SamplePlotData::new(PREVIEW_STEP, 0, 4);
+            preview.plot_data(&mut data, &Channel::Left);
+            data.data[1].iter().fold(0.0f32, |peak, s| peak.max(*s))
+        };
+
+        // the mipmaps of the sine would say half, but the drive takes it all the way
+        let loud = peak();
+        assert!(loud > 0.5);
+
+        // turning it down gets it worked out again
+        distortion.parameters()[1].set(-6.0);
+        let quiet = peak();
+        assert!((quiet - loud * dB(-6.0).to_amplitude()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_play_through_previews() {
+        // effects that keep state from one block to the next, each of which is previewed
+        let chain = || {
+            let sine: Arc<dyn Effect> = Arc::new(SineWave::new(0.5, 440.0, 0.0));
+            let chorus: Arc<dyn Effect> = Arc::new(Chorus::new(3, 0.5, 10.0, sine.clone()));
+            let filter: Arc<dyn Effect> =
+                Arc::new(Filter::new(FilterMode::LowPass, 800.0, 2.0, chorus.clone()));
+            let stretch: Arc<dyn Effect> = Arc::new(TimeStretch::new(1.5, 0.0, filter.clone()));
+            vec![sine, chorus, filter, stretch]
+        };
+
+        let block = 512;
+        let play = |chain: &[Arc<dyn Effect>], refresh: bool| {
+            let mut played = vec![];
+            for start in (0..block * 16).step_by(block) {
+                let mut output = vec![0.0; block * 2];
+                chain[3].process(&mut output, start, 2);
+                played.extend(output);
+
+                // more previews than there is room for streams, all over the place
+                for (i, effect) in chain.iter().enumerate().filter(|_| refresh) {
+                    for far in 0..4 {
+                        let at = ((i * 4 + far) * 100 + start / block) * PREVIEW_STEP;
+                        Preview::default().refresh(effect, at..at + PREVIEW_STEP);
+                    }
+                }
+            }
+            played
+        };
+
+        assert_eq!(play(&chain(), true), play(&chain(), false));
+    }
+
+    #[test]
+    fn test_preview_sends_nothing() {
+        let sine: Arc<dyn Effect> = Arc::new(SineWave::new(0.5, 440.0, 0.0));
+        let send: Arc<dyn Effect> = Arc::new(FeedbackSend::new("bus 1", sine));
+        let receive: Arc<dyn Effect> = Arc::new(FeedbackReceive::new("bus 1", 0.0));
+        let buses = Arc::new(FeedbackBuses::default());
+        buses.adopt(&send);
+        buses.adopt(&receive);
+
+        // the receive's preview carries on from the send's, so would hear anything it sent
+        Preview::default().refresh(&send, 0..PREVIEW_STEP);
+        let mut preview = Preview::default();
+        while !preview.refresh(&receive, PREVIEW_STEP..PREVIEW_STEP * 2) {}
+        let mut data = SamplePlotData::new(PREVIEW_STEP, PREVIEW_STEP, 1);
+        preview.plot_data(&mut data, &Channel::Left);
+        assert_eq!((data.data[0][0], data.data[1][0]), (0.0, 0.0));
+    }
+}
@@ -0,0 +1,329 @@
+use std::sync::Arc;
+
+use eframe::egui::{
+    self, Align2, Button, Color32, FontId, Pos2, Rect, Sense, Stroke, StrokeKind, Ui, Vec2,
+};
+
+use crate::{
+    audio::effects::{Effect, lane::Lane},
+    common::{Channel, SAMPLE_RATE, mipmapchannel::SamplePlotData, track::Track},
+};
+
+const HEADER_WIDTH: f32 = 100.0;
+const RULER_HEIGHT: f32 = 20.0;
+const LANE_HEIGHT: f32 = 80.0;
+
+/// The furthest out we let the view zoom, 10 seconds to a point
+const MAX_SAMPLES_PER_POINT: f64 = SAMPLE_RATE as f64 * 10.0;
+
+/// What the user did to a clip this frame, done once we have finished looking through the lanes
+enum ClipAction {
+    Select(Arc<Track>),
+    Move {
+        clip: Arc<Track>,
+        by: f64,
+        from: usize,
+        to: usize,
+    },
+}
+
+/// Tracks laid out as clips on lanes along a shared time axis.
+/// Every lane is also a node in the graph, playing whatever clips are on it.
+pub struct Timeline {
+    lanes: Vec<Arc<Lane>>,
+    /// The sample at the left hand edge of the lanes
+    view_start: f64,
+    samples_per_point: f64,
+    selected: Option<Arc<Track>>,
+}
+
+impl Default for Timeline {
+    fn default() -> Self {
+        Self {
+            lanes: vec![],
+            view_start: 0.0,
+            samples_per_point: 1024.0,
+            selected: None,
+        }
+    }
+}
+
+impl Timeline {
+    /// Put a newly loaded track on a lane of its own, giving back the lane to hook up in the graph
+    pub fn add_track(&mut self, track: Arc<Track>) -> Arc<Lane> {
+        let name = match track._file_path().and_then(|p| p.file_stem()) {
+            Some(stem) => stem.to_string_lossy().to_string(),
+            None => format!("Lane {}", self.lanes.len() + 1),
+        };
+
+        let lane = Arc::new(Lane::new(name));
+        lane.add_clip(track);
+        self.lanes.push(lane.clone());
+        lane
+    }
+
+    /// The clip last clicked on
+    pub fn selected(&self) -> Option<Arc<Track>> {
+        self.selected.clone()
+    }
+
+    fn lane_of(&self, clip: &Arc<Track>) -> Option<&Arc<Lane>> {
+        self.lanes.iter().find(|lane| lane.contains(clip))
+    }
+
+    fn toolbar_ui(&mut self, ui: &mut Ui, current_sample: usize) {
+        ui.horizontal(|ui| {
+            let selected = self.selected.clone();
+            let lane = selected
+                .as_ref()
+                .and_then(|clip| self.lane_of(clip))
+                .cloned();
+
+            if ui
+                .add_enabled(lane.is_some(), Button::new("Split"))
+                .on_hover_text("Cut the selected clip at the playhead")
+                .clicked()
+                && let (Some(lane), Some(clip)) = (&lane, &selected)
+                && let Some(second) = lane.split(clip, current_sample)
+            {
+                self.selected = Some(second);
+            }
+
+            if ui
+                .add_enabled(lane.is_some(), Button::new("Duplicate"))
+                .clicked()
+                && let (Some(lane), Some(clip)) = (&lane, &selected)
+            {
+                self.selected = Some(lane.duplicate(clip));
+            }
+
+            ui.separator();
+
+            if ui.button("-").clicked() {
+                self.samples_per_point = (self.samples_per_point * 2.0).min(MAX_SAMPLES_PER_POINT);
+            }
+            if ui.button("+").clicked() {
+                self.samples_per_point = (self.samples_per_point / 2.0).max(1.0);
+            }
+        });
+    }
+
+    /// Scroll to move along, pinch or ctrl+scroll to zoom in around the pointer
+    fn pan_and_zoom(&mut self, ui: &Ui, lanes_left: f32) {
+        let (scroll, zoom, pointer) =
+            ui.input(|i| (i.smooth_scroll_delta, i.zoom_delta(), i.pointer.hover_pos()));
+
+        if zoom != 1.0
+            && let Some(pointer) = pointer
+        {
+            let from_left = (pointer.x - lanes_left).max(0.0) as f64;
+            let anchor = self.view_start + from_left * self.samples_per_point;
+            self.samples_per_point =
+                (self.samples_per_point / zoom as f64).clamp(1.0, MAX_SAMPLES_PER_POINT);
+            self.view_start = (anchor - from_left * self.samples_per_point).max(0.0);
+        }
+
+        self.view_start =
+            (self.view_start - (scroll.x + scroll.y) as f64 * self.samples_per_point).max(0.0);
+    }
+
+    /// Draw everything, returning where to move the playhead to if the ruler was clicked
+    pub fn ui(&mut self, ui: &mut Ui, current_sample: usize) -> Option<usize> {
+        self.toolbar_ui(ui, current_sample);
+
+        let size = Vec2::new(
+            ui.available_width(),
+            RULER_HEIGHT + LANE_HEIGHT * self.lanes.len().max(1) as f32,
+        );
+        let (response, painter) = ui.allocate_painter(size, Sense::click());
+        let rect = response.rect;
+        let lanes_left = rect.left() + HEADER_WIDTH;
+
+        if response.hovered() {
+            self.pan_and_zoom(ui, lanes_left);
+        }
+
+        let (view_start, samples_per_point) = (self.view_start, self.samples_per_point);
+        let x_of = |sample: f64| lanes_left + ((sample - view_start) / samples_per_point) as f32;
+        let sample_of =
+            |x: f32| (view_start + (x - lanes_left) as f64 * samples_per_point).max(0.0);
+
+        let text_colour = ui.visuals().text_color();
+        let line_colour = ui.visuals().weak_text_color();
+        let painter = painter.with_clip_rect(rect);
+
+        // The ruler, with a tick at least every 60 points
+        let ruler = Rect::from_min_size(
+            Pos2::new(lanes_left, rect.top()),
+            Vec2::new(rect.right() - lanes_left, RULER_HEIGHT),
+        );
+        let seconds_per_point = samples_per_point / SAMPLE_RATE as f64;
+        let interval = [0.01, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0, 600.0]
+            .into_iter()
+            .find(|&i| i / seconds_per_point >= 60.0)
+            .unwrap_or(3600.0);
+        let first_tick = (view_start / SAMPLE_RATE as f64 / interval).ceil() as usize;
+        for tick in first_tick.. {
+            let seconds = tick as f64 * interval;
+            let x = x_of(seconds * SAMPLE_RATE as f64);
+            if x > rect.right() {
+                break;
+            }
+            painter.line_segment(
+                [Pos2::new(x, ruler.top()), Pos2::new(x, rect.bottom())],
+                Stroke::new(1.0, line_colour.gamma_multiply(0.3)),
+            );
+            painter.text(
+                Pos2::new(x + 2.0, ruler.top()),
+                Align2::LEFT_TOP,
+                format!("{seconds}s"),
+                FontId::monospace(10.0),
+                text_colour,
+            );
+        }
+
+        let mut relocate = None;
+        if response.clicked()
+            && let Some(pointer) = response.interact_pointer_pos()
+            && ruler.contains(pointer)
+        {
+            relocate = Some(sample_of(pointer.x) as usize);
+        }
+
+        // The lanes and their clips
+        let mut action = None;
+        for (l, lane) in self.lanes.iter().enumerate() {
+            let row = Rect::from_min_size(
+                Pos2::new(rect.left(), ruler.bottom() + l as f32 * LANE_HEIGHT),
+                Vec2::new(rect.width(), LANE_HEIGHT),
+            );
+            painter.line_segment(
+                [row.left_bottom(), row.right_bottom()],
+                Stroke::new(1.0, line_colour),
+            );
+            painter.text(
+                row.left_top() + Vec2::splat(4.0),
+                Align2::LEFT_TOP,
+                lane.name(),
+                FontId::proportional(14.0),
+                text_colour,
+            );
+
+            let lane_area = Rect::from_x_y_ranges(lanes_left..=rect.right(), row.y_range());
+            for clip in lane.clips() {
+                let trim = clip.trim();
+                let (start, end) = trim.region(clip.length() as usize);
+                let clip_right = match trim.looping {
+                    true => rect.right(),
+                    false => x_of((trim.offset + end - start) as f64),
+                };
+                let clip_rect = Rect::from_x_y_ranges(
+                    x_of(trim.offset as f64)..=clip_right,
+                    row.top() + 2.0..=row.bottom() - 2.0,
+                )
+                .intersect(lane_area);
+                if !clip_rect.is_positive() {
+                    continue;
+                }
+
+                let is_selected = self
+                    .selected
+                    .as_ref()
+                    .is_some_and(|s| Arc::ptr_eq(s, &clip));
+                draw_clip(
+                    &painter,
+                    &clip,
+                    clip_rect,
+                    sample_of(clip_rect.left()),
+                    samples_per_point,
+                    is_selected,
+                );
+
+                let clip_response = ui.interact(
+                    clip_rect,
+                    response.id.with(("clip", Arc::as_ptr(&clip))),
+                    Sense::click_and_drag(),
+                );
+                if clip_response.clicked() || clip_response.drag_started() {
+                    action = Some(ClipAction::Select(clip.clone()));
+                }
+                if clip_response.dragged() {
+                    // drop it on whichever lane the pointer is over
+                    let to = clip_response
+                        .interact_pointer_pos()
+                        .map(|p| ((p.y - ruler.bottom()) / LANE_HEIGHT).floor().max(0.0) as usize)
+                        .unwrap_or(l)
+                        .min(self.lanes.len() - 1);
+                    action = Some(ClipAction::Move {
+                        clip: clip.clone(),
+                        by: clip_response.drag_delta().x as f64 * samples_per_point,
+                        from: l,
+                        to,
+                    });
+                }
+            }
+        }
+
+        match action {
+            None => (),
+            Some(ClipAction::Select(clip)) => self.selected = Some(clip),
+            Some(ClipAction::Move { clip, by, from, to }) => {
+                let mut trim = clip.trim();
+                trim.offset = (trim.offset as f64 + by).max(0.0) as usize;
+                clip.set_trim(trim);
+
+                if from != to {
+                    self.lanes[from].remove_clip(&clip);
+                    self.lanes[to].add_clip(clip);
+                }
+            }
+        }
+
+        // The playhead
+        let x = x_of(current_sample as f64);
+        if x >= lanes_left {
+            painter.line_segment(
+                [Pos2::new(x, rect.top()), Pos2::new(x, rect.bottom())],
+                Stroke::new(1.0, Color32::WHITE),
+            );
+        }
+
+        relocate
+    }
+}
+
+/// A clip as a box with its waveform in, taken from the mipmaps so it stays quick however far out we are
+fn draw_clip(
+    painter: &egui::Painter,
+    clip: &Track,
+    rect: Rect,
+    first_sample: f64,
+    samples_per_point: f64,
+    is_selected: bool,
+) {
+    painter.rect_filled(rect, 4.0, Color32::from_rgb(60, 40, 90));
+
+    let step = (samples_per_point as usize).max(1).next_power_of_two();
+    let columns = (rect.width() as f64 * samples_per_point / step as f64) as usize + 1;
+    let mut data = SamplePlotData::new(step, first_sample as usize, columns);
+    clip.get_waveform_plot_data(&mut data, &Channel::Left);
+
+    let y_of = |v: f32| rect.center().y - v.clamp(-1.0, 1.0) * rect.height() / 2.0;
+    for i in 0..columns {
+        let x = rect.left() + (i as f64 * step as f64 / samples_per_point) as f32;
+        let (min, max) = match data.is_min_max {
+            true => (data.data[0][i], data.data[1][i]),
+            false => (data.data[0][i].min(0.0), data.data[0][i].max(0.0)),
+        };
+        painter.line_segment(
+            [Pos2::new(x, y_of(max)), Pos2::new(x, y_of(min))],
+            Stroke::new(1.0, Color32::PURPLE),
+        );
+    }
+
+    let outline = match is_selected {
+        true => Stroke::new(2.0, Color32::WHITE),
+        false => Stroke::new(1.0, Color32::from_rgb(130, 100, 180)),
+    };
+    painter.rect_stroke(rect, 4.0, outline, StrokeKind::Inside);
+}
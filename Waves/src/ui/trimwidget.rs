@@ -0,0 +1,151 @@
+use eframe::egui::{self, Color32, Pos2, Rect, Sense, Shape, Stroke, Vec2, Widget};
+
+use crate::common::{mipmapchannel::SamplePlotData, track::Track};
+
+/// How wide (in points) the grabbable part of a handle is
+const HANDLE_WIDTH: f32 = 8.0;
+
+/// The whole of a track's file with handles to drag the in/out points and the lengths of the fades
+pub struct TrimWidget<'a> {
+    track: &'a Track,
+    plot_width: f32,
+    plot_height: f32,
+}
+
+impl<'a> TrimWidget<'a> {
+    pub fn new(track: &'a Track, plot_size: (f32, f32)) -> Self {
+        Self {
+            track,
+            plot_width: plot_size.0,
+            plot_height: plot_size.1,
+        }
+    }
+
+    /// The loudest bit of the (left channel of the) file under each column of pixels, as (min, max)
+    fn overview(&self, columns: usize) -> Vec<(f32, f32)> {
+        let length = self.track.length() as usize;
+        let step = (length / columns.max(1)).max(1).next_power_of_two();
+
+        let mut data = SamplePlotData::new(step, 0, columns);
+        self.track
+            .data()
+            .0
+            .get_presampled_data_from_step_and_start(&mut data);
+
+        (0..columns)
+            .map(|i| match data.is_min_max {
+                true => (data.data[0][i], data.data[1][i]),
+                false => (data.data[0][i].min(0.0), data.data[0][i].max(0.0)),
+            })
+            .collect()
+    }
+}
+
+impl Widget for TrimWidget<'_> {
+    fn ui(self, ui: &mut egui::Ui) -> egui::Response {
+        let (response, painter) =
+            ui.allocate_painter(Vec2::new(self.plot_width, self.plot_height), Sense::hover());
+        let rect = response.rect;
+
+        let length = self.track.length().max(1) as usize;
+        let mut trim = self.track.trim();
+        let (start, end) = trim.region(length);
+
+        let samples_per_point = length as f32 / rect.width();
+        let x_of = |sample: usize| rect.left() + sample as f32 / samples_per_point;
+        let y_of = |v: f32| rect.center().y - v * rect.height() / 2.0;
+
+        // The waveform, dimmed where it has been trimmed off
+        let columns = rect.width() as usize;
+        for (i, (min, max)) in self.overview(columns).into_iter().enumerate() {
+            let x = rect.left() + i as f32;
+            let sample = (i as f32 * samples_per_point) as usize;
+            let colour = match (start..end).contains(&sample) {
+                true => Color32::PURPLE,
+                false => Color32::from_gray(70),
+            };
+            painter.line_segment(
+                [Pos2::new(x, y_of(max)), Pos2::new(x, y_of(min))],
+                Stroke::new(1.0, colour),
+            );
+        }
+
+        // The fades, drawn as their gain going across the top
+        let len = end - start;
+        let fade_points = (start..end)
+            .step_by((samples_per_point as usize).max(1))
+            .map(|sample| {
+                let gain = trim.fade_gain(sample - start, len);
+                Pos2::new(x_of(sample), rect.bottom() - gain * rect.height())
+            })
+            .collect::<Vec<_>>();
+        painter.add(Shape::line(fade_points, Stroke::new(1.0, Color32::YELLOW)));
+
+        // A handle to drag at x, either an in/out line the full height or a little square on top for a fade.
+        // Gives back how many samples it was dragged by
+        let id = response.id;
+        let drag = |name: &str, x: f32, full_height: bool| -> f32 {
+            let handle = match full_height {
+                true => Rect::from_center_size(
+                    Pos2::new(x, rect.center().y),
+                    Vec2::new(HANDLE_WIDTH, rect.height()),
+                ),
+                false => Rect::from_center_size(
+                    Pos2::new(x, rect.top() + HANDLE_WIDTH / 2.0),
+                    Vec2::splat(HANDLE_WIDTH),
+                ),
+            };
+            let handle_response = ui
+                .interact(handle, id.with(name), Sense::drag())
+                .on_hover_cursor(egui::CursorIcon::ResizeHorizontal);
+
+            let colour = match handle_response.hovered() || handle_response.dragged() {
+                true => Color32::WHITE,
+                false => Color32::LIGHT_GRAY,
+            };
+            match full_height {
+                true => painter.line_segment(
+                    [Pos2::new(x, rect.top()), Pos2::new(x, rect.bottom())],
+                    Stroke::new(2.0, colour),
+                ),
+                false => painter.rect_filled(handle, 1.0, colour),
+            };
+
+            handle_response.drag_delta().x * samples_per_point
+        };
+
+        let moved = |sample: usize, by: f32| (sample as f32 + by).max(0.0) as usize;
+
+        // only touch what was actually dragged so the end stays following the file unless it was moved
+        let by = drag("start", x_of(start), true);
+        if by != 0.0 {
+            trim.start = moved(start, by).min(end);
+        }
+
+        let by = drag("end", x_of(end), true);
+        if by != 0.0 {
+            trim.end = Some(moved(end, by).clamp(trim.start, length));
+        }
+
+        let (start, end) = trim.region(length);
+        let len = end - start;
+
+        let fade_in = trim.fade_in.min(len);
+        let by = drag("fade_in", x_of(start + fade_in), false);
+        if by != 0.0 {
+            trim.fade_in = moved(fade_in, by).min(len - trim.fade_out.min(len));
+        }
+
+        let fade_out = trim.fade_out.min(len);
+        let by = drag("fade_out", x_of(end - fade_out), false);
+        if by != 0.0 {
+            trim.fade_out = moved(fade_out, -by).min(len - trim.fade_in.min(len));
+        }
+
+        if trim != self.track.trim() {
+            self.track.set_trim(trim);
+        }
+
+        response
+    }
+}
@@ -1,11 +1,17 @@
 use crate::{
     audio::effects::{Effect, output::Output},
-    common::{Channel, mipmapchannel::SamplePlotData, track::Track},
+    common::{
+        Channel,
+        edit::{AudioClip, Edit},
+        mipmapchannel::SamplePlotData,
+        track::Track,
+    },
     player::AudioCommand,
+    ui::nodegraph::preview::{PREVIEW_STEP, Preview},
 };
-use eframe::egui::{self, Widget};
+use eframe::egui::{self, Button, DragValue, mutex::Mutex};
 use std::{
-    ops::RangeInclusive,
+    ops::{Range, RangeInclusive},
     sync::{Arc, mpsc::Sender},
 };
 
@@ -21,6 +27,27 @@ pub struct WaveformWidget {
     allow_scroll: egui::Vec2b,
     is_small_widget: bool,
     tx_commands: Option<Sender<AudioCommand>>,
+    /// The track that edits made on the selected range go to
+    track: Option<Arc<Track>>,
+    /// Where the effect's output is kept once worked out, to draw that rather than going by the mipmaps
+    preview: Option<Arc<Mutex<Preview>>>,
+}
+
+/// Where the cut or copied audio is kept, shared between all the waveforms
+const CLIPBOARD: &str = "waveform_clipboard";
+
+/// The part of the track's file that is heard over a (start, end) range of the timeline
+fn file_range(track: &Track, selection: (usize, usize)) -> Range<usize> {
+    let trim = track.trim();
+    let length = track.length() as usize;
+    let start = trim.file_position(selection.0.min(selection.1), length);
+    let end = trim.file_position(selection.0.max(selection.1), length);
+
+    // if it loops round part way through only go up to the end of the played part
+    match end < start {
+        true => start..trim.region(length).1,
+        false => start..end,
+    }
 }
 
 impl WaveformWidget {
@@ -42,6 +69,8 @@ impl WaveformWidget {
                 allow_scroll: false.into(),
                 is_small_widget: true,
                 tx_commands,
+                track: None,
+                preview: None,
             },
             true => Self {
                 current_sample,
@@ -53,10 +82,121 @@ impl WaveformWidget {
                 allow_scroll: [true, false].into(),
                 is_small_widget: false,
                 tx_commands,
+                track: None,
+                preview: None,
             },
         }
     }
 
+    /// Let a range be selected by shift dragging and edited, with the edits made to the track
+    pub fn editing(mut self, track: Option<Arc<Track>>) -> Self {
+        self.track = track;
+        self
+    }
+
+    /// Draw the effect's output as it is actually played, worked out a bit more each frame
+    pub fn previewing(mut self, preview: Arc<Mutex<Preview>>) -> Self {
+        self.preview = Some(preview);
+        self
+    }
+
+    /// Work out more of the preview over what is about to be drawn, coming back for the rest next frame
+    fn refresh_preview(&self, ui: &egui::Ui, data_width: usize, step: usize) {
+        let Some(preview) = &self.preview else {
+            return;
+        };
+        let start_sample = self.get_start_sample(data_width, step);
+        let range = start_sample..start_sample + data_width * step;
+        if !preview.lock().refresh(&self.effect, range) {
+            ui.ctx().request_repaint();
+        }
+    }
+
+    /// The edit buttons, working on the selected range of the track.
+    /// Cut and delete change the length so they also get rid of the selection
+    fn edit_ui(&self, ui: &mut egui::Ui, track: &Track, selection: &mut Option<(usize, usize)>) {
+        let clipboard_id = egui::Id::new(CLIPBOARD);
+        let gain_id = ui.id().with("gain");
+        let clipboard: Option<Arc<AudioClip>> = ui.data(|d| d.get_temp(clipboard_id));
+        let mut gain: f32 = ui.data(|d| d.get_temp(gain_id)).unwrap_or(0.0);
+
+        let range = selection.map(|s| file_range(track, s));
+        let selected = range.clone().filter(|r| !r.is_empty());
+        let has_range = selected.is_some();
+
+        ui.horizontal(|ui| {
+            if ui.add_enabled(has_range, Button::new("Cut")).clicked()
+                && let Some(range) = selected.clone()
+            {
+                let cut = Arc::new(track.copy(range.clone()));
+                ui.data_mut(|d| d.insert_temp(clipboard_id, cut));
+                track.edit(Edit::Delete(range));
+                *selection = None;
+            }
+
+            if ui.add_enabled(has_range, Button::new("Copy")).clicked()
+                && let Some(range) = selected.clone()
+            {
+                let copy = Arc::new(track.copy(range));
+                ui.data_mut(|d| d.insert_temp(clipboard_id, copy));
+            }
+
+            // pastes over the selection, or at the playhead if nothing is selected
+            if ui
+                .add_enabled(
+                    clipboard.as_ref().is_some_and(|c| !c.is_empty()),
+                    Button::new("Paste"),
+                )
+                .clicked()
+                && let Some(clipboard) = clipboard
+            {
+                let at = match &range {
+                    Some(range) => range.start,
+                    None => track
+                        .trim()
+                        .file_position(self.current_sample, track.length() as usize),
+                };
+                match selected.clone() {
+                    Some(range) => track.edit(Edit::Replace(range, clipboard)),
+                    None => track.edit(Edit::Insert(at, clipboard)),
+                }
+                *selection = None;
+            }
+
+            if ui.add_enabled(has_range, Button::new("Delete")).clicked()
+                && let Some(range) = selected.clone()
+            {
+                track.edit(Edit::Delete(range));
+                *selection = None;
+            }
+
+            ui.separator();
+
+            for (label, edit) in [
+                ("Silence", Edit::Silence as fn(Range<usize>) -> Edit),
+                ("Reverse", Edit::Reverse),
+                ("Normalize", Edit::Normalize),
+            ] {
+                if ui.add_enabled(has_range, Button::new(label)).clicked()
+                    && let Some(range) = selected.clone()
+                {
+                    track.edit(edit(range));
+                }
+            }
+
+            ui.separator();
+
+            ui.add(DragValue::new(&mut gain).speed(0.1).suffix(" dB"));
+            if ui.add_enabled(has_range, Button::new("Gain")).clicked()
+                && let Some(range) = selected.clone()
+            {
+                track.edit(Edit::Gain(range, gain));
+            }
+        });
+
+        ui.data_mut(|d| d.insert_temp(gain_id, gain));
+    }
+
     fn get_start_sample(&self, data_width: usize, step: usize) -> usize {
         // Max 100 minutes
         let sample_count: usize = 48000 * 72 * 60;
@@ -84,9 +224,11 @@ impl WaveformWidget {
 
         let mut sample_plot_data = SamplePlotData::new(step, start_sample, data_width);
 
-        // do the maths to get the plot data back
-        self.effect
-            .get_waveform_plot_data(&mut sample_plot_data, &channel);
+        // do the maths to get the plot data back, unless it has been worked out properly
+        match &self.preview {
+            Some(preview) => preview.lock().plot_data(&mut sample_plot_data, &channel),
+            None => self.effect.plot_data(&mut sample_plot_data, &channel),
+        }
 
         let offset_func = match channel {
             Channel::Left => |f: f64| f / 2.0 + 0.5,
@@ -256,11 +398,23 @@ impl WaveformWidget {
     pub fn ui(mut self, ui: &mut egui::Ui, show_current_sample: bool) -> egui::Response {
         let plot_id = ui.id();
 
+        let selection_id = plot_id.with("selection");
+        let mut selection: Option<(usize, usize)> = ui.data(|d| d.get_temp(selection_id));
+        let track = self.track.clone().filter(|_| !self.is_small_widget);
+        if let Some(track) = &track {
+            self.edit_ui(ui, track, &mut selection);
+        }
+        // holding shift drags out a selection rather than moving the plot along
+        let selecting = track.is_some() && ui.input(|i| i.modifiers.shift);
+
         let samp_rate = 48000.0;
         let time_span = 20.0 * 60.0;
 
         let (line_left, line_right, line_time) = match self.is_small_widget {
-            true => self.get_small_line_data(samp_rate, 256, 2048),
+            true => {
+                self.refresh_preview(ui, 256, PREVIEW_STEP);
+                self.get_small_line_data(samp_rate, 256, PREVIEW_STEP)
+            }
             false => {
                 // Initialise data eg getting start stop times and step size
                 let range =
@@ -280,7 +434,10 @@ impl WaveformWidget {
             .legend(egui_plot::Legend::default())
             .clamp_grid(false)
             .allow_zoom(self.allow_zoom)
-            .allow_drag(self.allow_drag)
+            .allow_drag(match selecting {
+                true => false.into(),
+                false => self.allow_drag,
+            })
             .allow_scroll(self.allow_scroll)
             .center_y_axis(true)
             .id(plot_id)
@@ -298,6 +455,16 @@ impl WaveformWidget {
                 for l in line_right {
                     plot_ui.line(l);
                 }
+                if let Some((a, b)) = selection {
+                    let (start, end) = (a.min(b) as f64 / samp_rate, a.max(b) as f64 / samp_rate);
+                    plot_ui.polygon(
+                        egui_plot::Polygon::new(
+                            "selection",
+                            vec![[start, -1.0], [end, -1.0], [end, 1.0], [start, 1.0]],
+                        )
+                        .fill_color(egui::Color32::from_white_alpha(30)),
+                    );
+                }
                 if show_current_sample {
                     match line_time {
                         None => (),
@@ -308,6 +475,23 @@ impl WaveformWidget {
                 plot_ui.pointer_coordinate()
             });
 
+        if selecting && let Some(coord) = plt.inner {
+            let sample = (coord.x.max(0.0) * samp_rate) as usize;
+            if plt.response.drag_started() {
+                selection = Some((sample, sample));
+            } else if plt.response.dragged()
+                && let Some((anchor, _)) = selection
+            {
+                selection = Some((anchor, sample));
+            } else if plt.response.clicked() {
+                selection = None;
+            }
+        }
+        match selection {
+            Some(selection) => ui.data_mut(|d| d.insert_temp(selection_id, selection)),
+            None => ui.data_mut(|d| d.remove::<(usize, usize)>(selection_id)),
+        }
+
         if plt.response.clicked() {
             if let Some(coord) = plt.inner {
                 let x_time = coord.x.max(0.0); //.min(track_len * time_per_sample);